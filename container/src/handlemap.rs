@@ -4,8 +4,8 @@ use std::mem;
 
 use std::ops::{ Index, IndexMut, Deref, DerefMut };
 
-use mem::virtual_mem;
-use spark_core::{math_util, freelist::FreeList };
+use mem::virtual_mem::VirtualRegion;
+use spark_core::freelist::FreeList;
 
 ///
 /// A Handle abstracts a pointer to an internal resource of the HandleMap
@@ -41,21 +41,27 @@ pub struct HandleMap<'a, T: 'a> {
     freelist:       FreeList,
     size:           u32,
     max_size:       u32,
+    _dense_region:  VirtualRegion,
+    _sparse_region: VirtualRegion,
+    _meta_region:   VirtualRegion,
 }
 
-fn allocate_mem(size: usize) -> *mut u8 {
-   let v_mem = virtual_mem::reserve_address_space(size).unwrap();
-   let p_mem = virtual_mem::commit_physical_memory(v_mem, size).unwrap();
-    p_mem
+fn allocate_mem(size: usize) -> VirtualRegion {
+    let mut region = VirtualRegion::reserve(size).unwrap();
+    region.commit(0, size).unwrap();
+    region
 } 
 
 impl<'a, T> HandleMap<'a, T> {
     pub fn new(max_size: u32) -> Self {
         // TOOD: To be comparable with the Spark++ equivalent of the HashMap that uses new[]/delete[] we
         // should also use the language's default allocator here and go with liballoc's alloc 
-        let dense_arr_mem = allocate_mem(math_util::round_to_next_multiple(max_size as usize * mem::size_of::<T>(), virtual_mem::get_page_size()));
-        let sparse_arr_mem = allocate_mem(math_util::round_to_next_multiple(max_size as usize * mem::size_of::<HandleData>(), virtual_mem::get_page_size()));
-        let meta_arr_mem = allocate_mem(math_util::round_to_next_multiple(max_size as usize * mem::size_of::<LookupMeta>(), virtual_mem::get_page_size()));
+        let dense_region = allocate_mem(max_size as usize * mem::size_of::<T>());
+        let sparse_region = allocate_mem(max_size as usize * mem::size_of::<HandleData>());
+        let meta_region = allocate_mem(max_size as usize * mem::size_of::<LookupMeta>());
+        let dense_arr_mem = dense_region.base();
+        let sparse_arr_mem = sparse_region.base();
+        let meta_arr_mem = meta_region.base();
 
         unsafe {
             for idx in 0 .. max_size {
@@ -75,6 +81,9 @@ impl<'a, T> HandleMap<'a, T> {
                                 ),
                 size:           0,
                 max_size,
+                _dense_region:  dense_region,
+                _sparse_region: sparse_region,
+                _meta_region:   meta_region,
             }
         }
     }
@@ -255,10 +264,6 @@ impl<'a, T> Drop for HandleMap<'a, T> {
             for idx in 0..self.size {
                 let _ = self.dense_array[idx as usize];
             }
-
-            virtual_mem::free_address_space((&mut self.dense_array[0] as *mut T) as *mut u8);
-            virtual_mem::free_address_space((&mut self.handle_array[0] as *mut HandleData) as *mut u8);
-            virtual_mem::free_address_space((&mut self.meta_array[0] as *mut LookupMeta) as *mut u8);
         }
     }
 }
@@ -4,14 +4,13 @@ use std::option::{ Option };
 use std::ptr::{ Unique, self };
 
 use spark_core::math_util;
-use mem::virtual_mem;
+use mem::virtual_mem::{ VirtualRegion, PageMode };
 
 const INITIAL_GROW_AMOUNT: usize = 8; // Amount of element the vector grows the first time on push when it was empty
 const MAX_VECTOR_CAPACITY: usize = 1024 * 1024 * 1024; // One vector can hold a max of 1GB at a time
 
 pub struct Vector<T> {
-    virtual_mem_begin:  *mut u8,
-    virtual_mem_end:    *mut u8,
+    region:             VirtualRegion,
     internal_array_begin: Unique<T>,
     internal_array_end: *mut u8,
     capacity: usize,
@@ -20,18 +19,22 @@ pub struct Vector<T> {
 
 impl<T> Vector<T> {
     pub fn new() -> Self {
+        Self::with_page_mode(PageMode::Default)
+    }
+
+    ///
+    /// Creates a vector whose elements are backed by pages of the given mode, falling
+    /// back to smaller pages if the system cannot provide them. The vector grows in
+    /// steps of whole pages, so huge pages only pay off for big vectors
+    ///
+    pub fn with_page_mode(page_mode: PageMode) -> Self {
         debug_assert!(mem::size_of::<T>() != 0, "Vector cannot handel zero-sized types");
         
-        let vector_virtual_mem = match { virtual_mem::reserve_address_space(MAX_VECTOR_CAPACITY) } {
-            None => ptr::null_mut(),
-            Some(ptr) => ptr,
-        };
-
-        debug_assert!(vector_virtual_mem != ptr::null_mut(), "Could not allocate any virtual memory for the vector");
+        let region = VirtualRegion::reserve_with_page_mode(MAX_VECTOR_CAPACITY, page_mode).expect("Could not allocate any virtual memory for the vector");
+        let vector_virtual_mem = region.base();
         
         Vector {
-            virtual_mem_begin:      vector_virtual_mem,
-            virtual_mem_end:        unsafe { vector_virtual_mem.offset(MAX_VECTOR_CAPACITY as isize) },
+            region,
             internal_array_begin:   unsafe { Unique::new_unchecked(vector_virtual_mem as *mut T) },
             internal_array_end:     vector_virtual_mem,
             capacity:               0,
@@ -193,7 +196,7 @@ impl<T> Vector<T> {
 
     pub fn shrink_to_fit(&mut self) {
         	// We fullfill the request to handle unused capacity memory back to the OS
-			let commited_bytes = math_util::round_to_next_multiple(self.size * mem::size_of::<T>(), virtual_mem::get_page_size());
+			let commited_bytes = math_util::round_to_next_multiple(self.size * mem::size_of::<T>(), self.region.page_size());
 			let pinned_capacity = commited_bytes / mem::size_of::<T>();
 			if self.capacity > pinned_capacity
 			{
@@ -201,7 +204,8 @@ impl<T> Vector<T> {
 				// we can decommit them
 				let unused_mem = unsafe { (self.internal_array_begin.as_ptr() as *mut u8).offset(commited_bytes as isize) };
 				let bytes_to_decommit = self.internal_array_end as usize - unused_mem as usize;
-				virtual_mem::decommit_physical_memory(unused_mem, bytes_to_decommit);
+				self.region.decommit(commited_bytes, bytes_to_decommit);
+				self.internal_array_end = unused_mem;
 				self.capacity = pinned_capacity;
 			}
     }
@@ -237,22 +241,23 @@ impl<T> Vector<T> {
 
     fn grow(&mut self, bytes: usize) {
         {
-            let virtual_address_space_exhausted = self.internal_array_begin.as_ptr() as *mut u8 == self.virtual_mem_end;
+            let virtual_address_space_exhausted = self.internal_array_end == self.region.end();
             debug_assert!(!virtual_address_space_exhausted, "Not enough address space to grow further");
         }
 
-        let page_bytes_to_grow = math_util::round_to_next_multiple(bytes, virtual_mem::get_page_size());
+        let page_bytes_to_grow = math_util::round_to_next_multiple(bytes, self.region.page_size());
 
-        let is_enough_space_for_requested_pages = unsafe { self.internal_array_end.offset(page_bytes_to_grow as isize) <= self.virtual_mem_end };
+        let is_enough_space_for_requested_pages = unsafe { self.internal_array_end.offset(page_bytes_to_grow as isize) <= self.region.end() };
         let grow_by_bytes = if is_enough_space_for_requested_pages {
             page_bytes_to_grow
         }
         else {
-            let remaining_virtual_address_space = self.virtual_mem_end as usize - self.internal_array_end as usize;
-            math_util::round_to_previous_multiple(remaining_virtual_address_space, virtual_mem::get_page_size())
+            let remaining_virtual_address_space = self.region.end() as usize - self.internal_array_end as usize;
+            math_util::round_to_previous_multiple(remaining_virtual_address_space, self.region.page_size())
         };
 
-        let ptr = match { virtual_mem::commit_physical_memory(self.internal_array_end, grow_by_bytes) } {
+        let committed_bytes = self.internal_array_end as usize - self.region.base() as usize;
+        let ptr = match { self.region.commit(committed_bytes, grow_by_bytes) } {
             None => ptr::null_mut(),
             Some(mem) => mem,
         };
@@ -285,10 +290,7 @@ impl<T> DerefMut for Vector<T> {
 
 impl<T> Drop for Vector<T> {
     fn drop(&mut self) {
-        if self.capacity != 0 {
-            while let Some(_) = self.pop() {}
-            virtual_mem::free_address_space(self.virtual_mem_begin as *mut u8);
-        }
+        while let Some(_) = self.pop() {}
     }
 }
 
@@ -446,5 +448,39 @@ mod tests {
         assert_eq!(vec.size(), 4);
         assert_eq!(vec.capacity(), 512);
     }
+
+    #[test]
+    fn push_after_shrink_to_fit() {
+        let mut vec: Vector<Item> = Vector::new();
+
+        vec.reserve(600);
+        vec.push(Item { data: 0xCC });
+        vec.shrink_to_fit();
+
+        assert_eq!(vec.capacity(), 512);
+
+        for idx in 1 .. 600 {
+            vec.push(Item { data: idx });
+        }
+
+        assert_eq!(vec.size(), 600);
+        assert_eq!(vec[0].data, 0xCC);
+        assert_eq!(vec[599].data, 599);
+    }
+
+    #[test]
+    fn push_with_huge_pages() {
+        let mut vec: Vector<Item> = Vector::with_page_mode(PageMode::TransparentHuge);
+
+        vec.push(Item { data: 0 });
+        assert_eq!(vec.capacity() * mem::size_of::<Item>(), vec.region.page_size(), "Vector did not grow by the page size of its region");
+
+        for idx in 1 .. 1000 {
+            vec.push(Item { data: idx });
+        }
+
+        assert_eq!(vec.size(), 1000);
+        assert_eq!(vec[999].data, 999);
+    }
     
 }
\ No newline at end of file
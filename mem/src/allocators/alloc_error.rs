@@ -0,0 +1,50 @@
+use std::error::Error;
+use std::fmt;
+
+///
+/// The AllocError describes why an allocator could not be created
+/// or could not fulfill an allocation request
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocError {
+    ///
+    /// The allocator has not enough memory left, `available` is the amount of bytes it could
+    /// still hand out in one piece, or 0 if the allocator cannot tell
+    ///
+    OutOfMemory { requested: usize, available: usize },
+    ///
+    /// The alignment is not a power of two or stronger than the allocator supports
+    ///
+    InvalidAlignment { alignment: usize },
+    ///
+    /// The request does not fit into the fixed-size blocks of the allocator
+    ///
+    ExceedsMaxElementSize { requested: usize, max_element_size: usize },
+    ///
+    /// The OS could not reserve the address space for the allocator
+    ///
+    ReserveFailed { size: usize },
+    ///
+    /// The OS could not back the address space of the allocator with physical memory
+    ///
+    CommitFailed { size: usize },
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AllocError::OutOfMemory { requested, available } =>
+                write!(f, "Out of memory, requested {} bytes but only {} bytes are available", requested, available),
+            AllocError::InvalidAlignment { alignment } =>
+                write!(f, "Alignment of {} bytes is not supported", alignment),
+            AllocError::ExceedsMaxElementSize { requested, max_element_size } =>
+                write!(f, "Requested {} bytes but elements can be at most {} bytes", requested, max_element_size),
+            AllocError::ReserveFailed { size } =>
+                write!(f, "Could not reserve {} bytes of address space", size),
+            AllocError::CommitFailed { size } =>
+                write!(f, "Could not commit {} bytes of physical memory", size),
+        }
+    }
+}
+
+impl Error for AllocError {}
\ No newline at end of file
@@ -0,0 +1,129 @@
+use std::cmp;
+
+///
+/// The AllocatorStats are a snapshot of the memory usage of an allocator. `used_bytes` counts
+/// the bytes requested by live allocations, `overhead_bytes` the headers and the alignment
+/// padding they need on top, so both together are the memory that is currently occupied.
+/// The high-water mark is the peak of the occupied memory since the allocator was created
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocatorStats {
+    pub capacity:           usize,
+    pub used_bytes:         usize,
+    pub overhead_bytes:     usize,
+    pub high_water_mark:    usize,
+    pub live_allocations:   usize,
+    pub failed_requests:    usize,
+    ///
+    /// The amount of free fixed-size blocks, only reported by pool-based allocators
+    ///
+    pub free_blocks:        Option<usize>,
+}
+
+impl AllocatorStats {
+    ///
+    /// Returns the memory occupied by live allocations including their overhead
+    ///
+    pub fn occupied_bytes(&self) -> usize {
+        self.used_bytes + self.overhead_bytes
+    }
+
+    ///
+    /// Returns the memory that is neither used nor lost to overhead
+    ///
+    pub fn available_bytes(&self) -> usize {
+        self.capacity.saturating_sub(self.occupied_bytes())
+    }
+
+    ///
+    /// Sums up the stats of two allocators, e.g. of the parts of a composite allocator.
+    /// The combined high-water mark is an upper bound, as both peaks need not coincide
+    ///
+    pub fn combine(&self, other: &AllocatorStats) -> AllocatorStats {
+        AllocatorStats {
+            capacity:           self.capacity.saturating_add(other.capacity),
+            used_bytes:         self.used_bytes + other.used_bytes,
+            overhead_bytes:     self.overhead_bytes + other.overhead_bytes,
+            high_water_mark:    self.high_water_mark + other.high_water_mark,
+            live_allocations:   self.live_allocations + other.live_allocations,
+            failed_requests:    self.failed_requests + other.failed_requests,
+            free_blocks:        match (self.free_blocks, other.free_blocks) {
+                (Some(free_blocks), Some(other_free_blocks)) => Some(free_blocks + other_free_blocks),
+                (free_blocks, None) => free_blocks,
+                (None, other_free_blocks) => other_free_blocks,
+            },
+        }
+    }
+}
+
+///
+/// The StatsCounters are kept by allocators alongside of their storage and
+/// are turned into an AllocatorStats snapshot on request
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct StatsCounters {
+    pub used_bytes:         usize,
+    pub occupied_bytes:     usize,
+    pub high_water_mark:    usize,
+    pub live_allocations:   usize,
+    pub failed_requests:    usize,
+}
+
+impl StatsCounters {
+    pub fn on_alloc(&mut self, size: usize, occupied_size: usize) {
+        self.used_bytes += size;
+        self.occupied_bytes += occupied_size;
+        self.live_allocations += 1;
+        self.high_water_mark = cmp::max(self.high_water_mark, self.occupied_bytes);
+    }
+
+    pub fn on_dealloc(&mut self, size: usize, occupied_size: usize) {
+        self.used_bytes -= size;
+        self.occupied_bytes -= occupied_size;
+        self.live_allocations -= 1;
+    }
+
+    ///
+    /// Accounts for a block that is resized in place, its occupied size can stay the same
+    /// while the requested size changes, which turns the difference into overhead
+    ///
+    pub fn on_resize(&mut self, old_size: usize, old_occupied_size: usize, new_size: usize, new_occupied_size: usize) {
+        self.used_bytes = self.used_bytes - old_size + new_size;
+        self.occupied_bytes = self.occupied_bytes - old_occupied_size + new_occupied_size;
+        self.high_water_mark = cmp::max(self.high_water_mark, self.occupied_bytes);
+    }
+
+    pub fn on_failed_request(&mut self) {
+        self.failed_requests += 1;
+    }
+
+    ///
+    /// Releases all live allocations, the high-water mark and the failed requests are kept
+    ///
+    pub fn on_reset(&mut self) {
+        self.used_bytes = 0;
+        self.occupied_bytes = 0;
+        self.live_allocations = 0;
+    }
+
+    ///
+    /// Restores the counters to the state captured by a marker
+    ///
+    pub fn rewind(&mut self, used_bytes: usize, occupied_bytes: usize, live_allocations: usize) {
+        self.used_bytes = used_bytes;
+        self.occupied_bytes = occupied_bytes;
+        self.live_allocations = live_allocations;
+    }
+
+    pub fn snapshot(&self, capacity: usize, free_blocks: Option<usize>) -> AllocatorStats {
+        AllocatorStats {
+            capacity,
+            used_bytes:         self.used_bytes,
+            overhead_bytes:     self.occupied_bytes - self.used_bytes,
+            high_water_mark:    self.high_water_mark,
+            live_allocations:   self.live_allocations,
+            failed_requests:    self.failed_requests,
+            free_blocks,
+        }
+    }
+}
\ No newline at end of file
@@ -1,5 +1,9 @@
 use std::marker::PhantomData;
-use std::{ mem, ptr, intrinsics, ptr::Unique, ops::Deref, ops::DerefMut };
+use std::{ cmp, mem, ptr, intrinsics, ptr::Unique, ops::Deref, ops::DerefMut };
+use spark_core::pointer_util;
+
+use super::alloc_error::AllocError;
+use super::allocator_stats::AllocatorStats;
 
 ///
 /// Zero-cost abstraction over an allocation done by an allocator
@@ -54,12 +58,18 @@ impl<'a, T: ?Sized, A: Allocator + ?Sized> Deref for AllocatorBox<'a, T, A> {
     type Target = T;
     
     fn deref(&self) -> &T {
+        #[cfg(feature = "epoch_check")]
+        self.allocator.validate_block(&MemoryBlock::new(self.instance.as_ptr() as *mut u8));
+
         unsafe { self.instance.as_ref() }
     }
 }
 
 impl<'a, T: ?Sized, A: Allocator + ?Sized> DerefMut for AllocatorBox<'a, T, A> {
     fn deref_mut(&mut self) -> &mut T {
+        #[cfg(feature = "epoch_check")]
+        self.allocator.validate_block(&MemoryBlock::new(self.instance.as_ptr() as *mut u8));
+
         unsafe { self.instance.as_mut() }
     }
 }
@@ -73,6 +83,25 @@ impl<'a, T: ?Sized, A: Allocator + ?Sized> Drop for AllocatorBox<'a, T, A> {
     }
 }
 
+///
+/// Drops the elements of a partially initialized slice and frees its memory,
+/// in case initializing one of its elements panics
+///
+struct SliceInitGuard<'a, T, A: 'a + Allocator + ?Sized> {
+    elements:       *mut T,
+    initialized:    usize,
+    allocator:      &'a A,
+}
+
+impl<'a, T, A: Allocator + ?Sized> Drop for SliceInitGuard<'a, T, A> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.elements, self.initialized));
+            self.allocator.dealloc_raw(MemoryBlock::new(self.elements as *mut u8));
+        }
+    }
+}
+
 ///
 /// Base trait that indicates that a type is able to fullfil allocation requests
 /// issued by the user
@@ -81,31 +110,211 @@ pub trait Allocator {
     fn alloc<T>(&self, value: T, alignment: usize, offset: usize) -> Option<AllocatorBox<T, Self>> 
     where Self: Sized,
     {
-        match { self.alloc_raw(mem::size_of::<T>(), alignment, offset) } {
-            Some(block) => {
-                unsafe { ptr::write(block.ptr as *mut T, value); }
-
-                Some(AllocatorBox {
-                    instance: Unique::new(block.ptr as *mut T).expect("Could not create AllocatorBox from valid MemoryBlock"),
-                    allocator: self,
-                })
-            },
-            None => None,
+        self.try_alloc(value, alignment, offset).ok()
+    }
+
+    ///
+    /// Like `alloc`, but tells why the allocation failed
+    ///
+    fn try_alloc<T>(&self, value: T, alignment: usize, offset: usize) -> Result<AllocatorBox<T, Self>, AllocError>
+    where Self: Sized,
+    {
+        let block = self.try_alloc_raw(mem::size_of::<T>(), alignment, offset)?;
+        unsafe { ptr::write(block.ptr as *mut T, value); }
+
+        Ok(AllocatorBox {
+            instance: Unique::new(block.ptr as *mut T).expect("Could not create AllocatorBox from valid MemoryBlock"),
+            allocator: self,
+        })
+    }
+
+    ///
+    /// Allocates a slice of `len` clones of `value`, aligned to the alignment of T
+    ///
+    fn alloc_slice<T: Clone>(&self, len: usize, value: T) -> Option<AllocatorBox<[T], Self>>
+    where Self: Sized,
+    {
+        self.alloc_slice_with(len, |_| value.clone())
+    }
+
+    ///
+    /// Allocates a slice of `len` elements, initializing every element with the result of
+    /// calling `f` with its index. If `f` panics, the elements initialized so far are
+    /// dropped and the memory is returned to the allocator
+    ///
+    fn alloc_slice_with<T, F: FnMut(usize) -> T>(&self, len: usize, mut f: F) -> Option<AllocatorBox<[T], Self>>
+    where Self: Sized,
+    {
+        let size = mem::size_of::<T>().checked_mul(len)?;
+        let block = self.alloc_raw(size, mem::align_of::<T>(), 0)?;
+
+        let mut guard = SliceInitGuard {
+            elements: block.ptr as *mut T,
+            initialized: 0,
+            allocator: self,
+        };
+
+        while guard.initialized < len {
+            unsafe { ptr::write(guard.elements.offset(guard.initialized as isize), f(guard.initialized)); }
+            guard.initialized += 1;
         }
+
+        mem::forget(guard);
+
+        Some(AllocatorBox {
+            instance: Unique::new(ptr::slice_from_raw_parts_mut(block.ptr as *mut T, len)).expect("Could not create AllocatorBox from valid MemoryBlock"),
+            allocator: self,
+        })
+    }
+
+    ///
+    /// Allocates a slice holding a copy of `values`, aligned to the alignment of T
+    ///
+    fn alloc_slice_copy<T: Copy>(&self, values: &[T]) -> Option<AllocatorBox<[T], Self>>
+    where Self: Sized,
+    {
+        let block = self.alloc_raw(mem::size_of_val(values), mem::align_of::<T>(), 0)?;
+        unsafe { ptr::copy_nonoverlapping(values.as_ptr(), block.ptr as *mut T, values.len()); }
+
+        Some(AllocatorBox {
+            instance: Unique::new(ptr::slice_from_raw_parts_mut(block.ptr as *mut T, values.len())).expect("Could not create AllocatorBox from valid MemoryBlock"),
+            allocator: self,
+        })
     }
 
     fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock>;
+
+    ///
+    /// Like `alloc_raw`, but tells why the allocation failed. Allocators that do not override
+    /// this only check the alignment and report every other failure as OutOfMemory
+    ///
+    fn try_alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Result<MemoryBlock, AllocError> {
+        if !pointer_util::is_pot(alignment) {
+            return Err(AllocError::InvalidAlignment { alignment });
+        }
+
+        self.alloc_raw(size, alignment, offset).ok_or(AllocError::OutOfMemory { requested: size, available: 0 })
+    }
+
     fn dealloc_raw(&self, memory: MemoryBlock);
     fn reset(&self);
     fn get_allocation_size(&self, memory: &MemoryBlock) -> usize;
+
+    ///
+    /// Resizes `memory` to `new_size` bytes aligned to `alignment`, keeping its contents up to
+    /// the smaller of both sizes. By default the contents are moved to a new allocation and the
+    /// old block is freed, allocators that can grow blocks in place override this. On success
+    /// `memory` must not be used anymore, if None is returned it stays valid and untouched
+    ///
+    fn realloc_raw(&self, memory: &MemoryBlock, new_size: usize, alignment: usize) -> Option<MemoryBlock> {
+        let old_size = self.get_allocation_size(memory);
+        let new_memory = self.alloc_raw(new_size, alignment, 0)?;
+
+        unsafe { ptr::copy_nonoverlapping(memory.ptr, new_memory.ptr, cmp::min(old_size, new_size)) };
+        self.dealloc_raw(MemoryBlock::new(memory.ptr));
+
+        Some(new_memory)
+    }
+
+    ///
+    /// Checks that `memory` was not invalidated since it was allocated. With the `epoch_check`
+    /// feature allocators tag their allocations with an epoch that is bumped by `reset`, which
+    /// catches blocks that are used after a reset on a best-effort basis. It is a no-op otherwise
+    ///
+    fn validate_block(&self, _memory: &MemoryBlock) {}
+
+    ///
+    /// Returns a snapshot of the memory usage of the allocator
+    ///
+    fn stats(&self) -> AllocatorStats;
+}
+
+///
+/// Trait for allocators that can tell from the address of a block alone whether
+/// they allocated it, which lets combinators route a block back to its allocator
+///
+pub trait OwningAllocator: Allocator {
+    fn owns(&self, memory: &MemoryBlock) -> bool;
+}
+
+///
+/// A Marker captures the top of a MarkerAllocator at the time it was taken. Freeing
+/// to a marker releases all allocations issued after it was taken at once
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Marker {
+    pub(crate) offset:              usize,
+    pub(crate) allocation_id:       u32,
+    pub(crate) scope_depth:         usize,
+    pub(crate) used_bytes:          usize,
+    pub(crate) live_allocations:    usize,
+}
+
+///
+/// Trait for allocators that can rewind to a previously taken Marker, which
+/// allows to release all temporary allocations of e.g. a function at once
+///
+pub trait MarkerAllocator: Allocator {
+    fn get_marker(&self) -> Marker;
+    fn free_to_marker(&self, marker: Marker);
+
+    ///
+    /// `begin_scope` and `end_scope` are used by the AllocatorScope, in debug builds
+    /// they verify that scopes are released in the reverse order they were opened
+    ///
+    fn begin_scope(&self) -> Marker;
+    fn end_scope(&self, marker: Marker);
+
+    ///
+    /// Opens a scope that frees all allocations done through it once it is dropped
+    ///
+    fn scope(&self) -> AllocatorScope<Self>
+    where Self: Sized,
+    {
+        AllocatorScope {
+            marker: self.begin_scope(),
+            allocator: self,
+        }
+    }
+}
+
+///
+/// RAII guard rewinding its allocator to the marker taken on creation when it is
+/// dropped. Allocations are done through the scope, which keeps them from outliving it
+///
+pub struct AllocatorScope<'a, A: 'a + MarkerAllocator> {
+    marker: Marker,
+    allocator: &'a A,
+}
+
+impl<'a, A: MarkerAllocator> Deref for AllocatorScope<'a, A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        self.allocator
+    }
+}
+
+impl<'a, A: MarkerAllocator> Drop for AllocatorScope<'a, A> {
+    fn drop(&mut self) {
+        self.allocator.end_scope(self.marker);
+    }
 }
 
 pub trait BasicAllocator {
     type AllocatorImplementation;
-    fn new(size: usize) -> Self::AllocatorImplementation;
+    fn try_new(size: usize) -> Result<Self::AllocatorImplementation, AllocError>;
+
+    fn new(size: usize) -> Self::AllocatorImplementation {
+        Self::try_new(size).expect("Could not create the allocator")
+    }
 }
 
 pub trait TypedAllocator {
     type AllocatorImplementation;
-    fn new(element_size: usize, element_count: usize, element_alignment: usize, offset: usize) -> Self::AllocatorImplementation;
+    fn try_new(element_size: usize, element_count: usize, element_alignment: usize, offset: usize) -> Result<Self::AllocatorImplementation, AllocError>;
+
+    fn new(element_size: usize, element_count: usize, element_alignment: usize, offset: usize) -> Self::AllocatorImplementation {
+        Self::try_new(element_size, element_count, element_alignment, offset).expect("Could not create the allocator")
+    }
 }
\ No newline at end of file
@@ -0,0 +1,497 @@
+use std;
+use std::cell::RefCell;
+use spark_core::pointer_util;
+
+use super::super::virtual_mem::VirtualRegion;
+use super::base::{ Allocator, MemoryBlock, BasicAllocator, OwningAllocator };
+use super::alloc_error::AllocError;
+use super::allocator_stats::{ AllocatorStats, StatsCounters };
+
+///
+/// Every block, free or used, starts with a BlockHeader that
+/// tells its buddy whether both can be merged again
+///
+#[repr(C)]
+struct BlockHeader {
+    pub order:      u32,
+    pub is_free:    u32,
+}
+
+///
+/// Free blocks additionally link to the other free blocks of their order
+///
+#[repr(C)]
+struct FreeBlock {
+    pub header:     BlockHeader,
+    pub next:       *mut FreeBlock,
+    pub prev:       *mut FreeBlock,
+}
+
+///
+/// The AllocationHeader struct describes meta-data
+/// the allocator needs to store alongside of the
+/// allocations.
+///
+struct AllocationHeader {
+    pub block_offset:       u32,
+    pub allocation_size:    u32,
+}
+
+const BLOCK_HEADER_SIZE: usize = std::mem::size_of::<BlockHeader>();
+const ALLOCATION_META_SIZE: usize = std::mem::size_of::<AllocationHeader>();
+
+// Blocks of order 0 have to be able to hold a FreeBlock
+const MIN_BLOCK_SIZE_LOG2: usize = 5;
+const MIN_BLOCK_SIZE: usize = 1 << MIN_BLOCK_SIZE_LOG2;
+const MAX_ORDER_COUNT: usize = 32;
+
+///
+/// Returns the order of the smallest block that can hold `size` bytes
+///
+fn order_for_size(size: usize) -> usize {
+    if size <= MIN_BLOCK_SIZE {
+        0
+    }
+    else {
+        size.next_power_of_two().trailing_zeros() as usize - MIN_BLOCK_SIZE_LOG2
+    }
+}
+
+///
+/// The BuddyAllocatorStorage is a type that is used to
+/// expose a safe API for user allocations where the Allocator
+/// itself is just mutating the Storage backing it
+///
+struct BuddyAllocatorStorage {
+    pub use_internal_mem:   bool,
+    pub region:             VirtualRegion,
+    pub mem_end:            *mut u8,
+    pub max_order:          usize,
+    pub free_lists:         [*mut FreeBlock; MAX_ORDER_COUNT],
+    pub free_block_counts:  [usize; MAX_ORDER_COUNT],
+    pub stats:              StatsCounters,
+}
+
+impl BuddyAllocatorStorage {
+    ///
+    /// Creates a new buddy allocator storage and allocates the memory
+    /// block requested by the allocator from the virtual memory API.
+    /// The size is rounded up to the next power of two
+    ///
+    fn new(size: usize) -> Result<BuddyAllocatorStorage, AllocError> {
+        let max_order = order_for_size(size);
+        debug_assert!(max_order < MAX_ORDER_COUNT, "Size exceeds the maximum size of a buddy allocator");
+
+        let capacity = MIN_BLOCK_SIZE << max_order;
+        let mut region = VirtualRegion::reserve(capacity).ok_or(AllocError::ReserveFailed { size: capacity })?;
+        let physical_address_space = region.commit(0, capacity).ok_or(AllocError::CommitFailed { size: capacity })?;
+
+        let mut storage = BuddyAllocatorStorage {
+            use_internal_mem:   true,
+            region,
+            mem_end:            unsafe { physical_address_space.offset(capacity as isize) },
+            max_order,
+            free_lists:         [std::ptr::null_mut(); MAX_ORDER_COUNT],
+            free_block_counts:  [0; MAX_ORDER_COUNT],
+            stats:              StatsCounters::default(),
+        };
+
+        storage.reset_blocks();
+        Ok(storage)
+    }
+
+    ///
+    /// Turns the whole memory into a single free block of the highest order
+    ///
+    fn reset_blocks(&mut self) {
+        self.free_lists = [std::ptr::null_mut(); MAX_ORDER_COUNT];
+        self.free_block_counts = [0; MAX_ORDER_COUNT];
+
+        let max_order = self.max_order;
+        let mem_begin = self.region.base();
+
+        unsafe { self.push_free_block(mem_begin as *mut FreeBlock, max_order) };
+    }
+
+    unsafe fn push_free_block(&mut self, block: *mut FreeBlock, order: usize) {
+        let head = self.free_lists[order];
+
+        (*block).header.order = order as u32;
+        (*block).header.is_free = 1;
+        (*block).next = head;
+        (*block).prev = std::ptr::null_mut();
+
+        if !head.is_null() {
+            (*head).prev = block;
+        }
+
+        self.free_lists[order] = block;
+        self.free_block_counts[order] += 1;
+    }
+
+    unsafe fn remove_free_block(&mut self, block: *mut FreeBlock) {
+        let order = (*block).header.order as usize;
+
+        if !(*block).next.is_null() {
+            (*(*block).next).prev = (*block).prev;
+        }
+
+        if !(*block).prev.is_null() {
+            (*(*block).prev).next = (*block).next;
+        }
+        else {
+            self.free_lists[order] = (*block).next;
+        }
+
+        (*block).header.is_free = 0;
+        self.free_block_counts[order] -= 1;
+    }
+
+    ///
+    /// Returns the size in bytes of the largest free block, or 0 if there is none
+    ///
+    fn largest_free_block(&self) -> usize {
+        (0 ..= self.max_order).rev()
+            .find(|order| !self.free_lists[*order].is_null())
+            .map_or(0, |order| MIN_BLOCK_SIZE << order)
+    }
+
+    ///
+    /// Returns the buddy of `block`, the address of both blocks only differs
+    /// in the bit matching the size of a block of the given order
+    ///
+    fn buddy_of(&self, block: *mut u8, order: usize) -> *mut u8 {
+        let mem_begin = self.region.base() as usize;
+        let buddy_offset = (block as usize - mem_begin) ^ (MIN_BLOCK_SIZE << order);
+
+        (mem_begin + buddy_offset) as *mut u8
+    }
+}
+
+///
+/// The BuddyAllocator manages its memory in blocks whose sizes are powers of two. A request
+/// is served from the smallest free block that fits, which is split in half repeatedly until
+/// it matches the requested size. Freed blocks are merged with their buddy, the other half
+/// they were split from, as long as the buddy is free as well. The amount of free blocks per
+/// order can be queried to visualize the fragmentation of the allocator.
+///
+pub struct BuddyAllocator {
+    storage: RefCell<BuddyAllocatorStorage>,
+}
+
+// The free lists only link buddies inside of the region owned by the allocator,
+// so it can be moved to another thread as a whole
+unsafe impl Send for BuddyAllocator {}
+
+impl BuddyAllocator {
+    ///
+    /// Returns the highest order a block of this allocator can have,
+    /// a block of this order spans the whole memory of the allocator
+    ///
+    pub fn max_order(&self) -> usize {
+        self.storage.borrow().max_order
+    }
+
+    ///
+    /// Returns the size in bytes of a block of the given order
+    ///
+    pub fn block_size(&self, order: usize) -> usize {
+        MIN_BLOCK_SIZE << order
+    }
+
+    ///
+    /// Returns the amount of free blocks of the given order
+    ///
+    pub fn free_block_count(&self, order: usize) -> usize {
+        {
+            let order_in_range = order <= self.max_order();
+            debug_assert!(order_in_range, "Order exceeds the max order of the allocator");
+        }
+
+        self.storage.borrow().free_block_counts[order]
+    }
+}
+
+impl BasicAllocator for BuddyAllocator {
+    type AllocatorImplementation = BuddyAllocator;
+
+    fn try_new(size: usize) -> Result<Self::AllocatorImplementation, AllocError> {
+        debug_assert!(size > 0usize, "Size is not allowed to be 0");
+
+        Ok(BuddyAllocator {
+            storage: RefCell::new(BuddyAllocatorStorage::new(size)?),
+        })
+    }
+}
+
+impl Allocator for BuddyAllocator {
+    fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
+        self.try_alloc_raw(size, alignment, offset).ok()
+    }
+
+    ///
+    /// A failed request reports the largest free block minus the block and allocation
+    /// headers as available, which is the biggest request without alignment that still fits
+    ///
+    fn try_alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Result<MemoryBlock, AllocError> {
+        let mut storage = self.storage.borrow_mut();
+
+        if !pointer_util::is_pot(alignment) {
+            storage.stats.on_failed_request();
+            return Err(AllocError::InvalidAlignment { alignment });
+        }
+
+        // Reserve space for the worst case padding the alignment can require
+        let required_size = size + BLOCK_HEADER_SIZE + ALLOCATION_META_SIZE + alignment - 1;
+        let order = order_for_size(required_size);
+
+        let mut found_order = order;
+        while found_order <= storage.max_order && storage.free_lists[found_order].is_null() {
+            found_order += 1;
+        }
+
+        if found_order > storage.max_order {
+            storage.stats.on_failed_request();
+            return Err(AllocError::OutOfMemory {
+                requested: size,
+                available: storage.largest_free_block().saturating_sub(BLOCK_HEADER_SIZE + ALLOCATION_META_SIZE),
+            });
+        }
+
+        unsafe {
+            let block = storage.free_lists[found_order];
+            storage.remove_free_block(block);
+
+            // Split the block until it matches the requested order, the upper halves become free blocks
+            while found_order > order {
+                found_order -= 1;
+                let buddy = (block as *mut u8).offset((MIN_BLOCK_SIZE << found_order) as isize) as *mut FreeBlock;
+                storage.push_free_block(buddy, found_order);
+            }
+
+            (*block).header.order = order as u32;
+            (*block).header.is_free = 0;
+
+            let block_ptr = block as *mut u8;
+            let offset_before_alignment = (offset + BLOCK_HEADER_SIZE + ALLOCATION_META_SIZE) as isize;
+            let aligned_ptr = pointer_util::align_top(block_ptr.offset(offset_before_alignment), alignment) as *mut u8;
+            let user_ptr = aligned_ptr.offset(-(offset as isize));
+
+            // The header sits right in front of the user pointer, an odd offset leaves it unaligned
+            std::ptr::write_unaligned(user_ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *mut AllocationHeader, AllocationHeader {
+                block_offset:       (user_ptr as usize - block_ptr as usize) as u32,
+                allocation_size:    size as u32,
+            });
+            storage.stats.on_alloc(size, MIN_BLOCK_SIZE << order);
+
+            Ok(MemoryBlock::new(user_ptr))
+        }
+    }
+
+    fn dealloc_raw(&self, memory: MemoryBlock) {
+        let raw_mem = memory.ptr;
+
+        unsafe {
+            let mut storage = self.storage.borrow_mut();
+
+            {
+                let ptr_in_range = raw_mem >= storage.region.base() && raw_mem < storage.mem_end;
+                debug_assert!(ptr_in_range, "AllocatorMem was not allocated by this allocator");
+            }
+
+            let alloc_header = std::ptr::read_unaligned(raw_mem.offset(-(ALLOCATION_META_SIZE as isize)) as *const AllocationHeader);
+            let mut block = raw_mem.offset(-(alloc_header.block_offset as isize));
+            let mut order = (*(block as *mut BlockHeader)).order as usize;
+
+            debug_assert!((*(block as *mut BlockHeader)).is_free == 0, "MemoryBlock was already freed");
+            storage.stats.on_dealloc(alloc_header.allocation_size as usize, MIN_BLOCK_SIZE << order);
+
+            // Merge with the buddy as long as it is free and was not split any further
+            while order < storage.max_order {
+                let buddy = storage.buddy_of(block, order) as *mut FreeBlock;
+                let buddy_is_mergeable = (*buddy).header.is_free != 0 && (*buddy).header.order as usize == order;
+
+                if !buddy_is_mergeable {
+                    break;
+                }
+
+                storage.remove_free_block(buddy);
+
+                if (buddy as *mut u8) < block {
+                    block = buddy as *mut u8;
+                }
+
+                order += 1;
+            }
+
+            storage.push_free_block(block as *mut FreeBlock, order);
+        }
+    }
+
+    fn reset(&self) {
+        let mut storage = self.storage.borrow_mut();
+        storage.reset_blocks();
+        storage.stats.on_reset();
+    }
+
+    fn get_allocation_size(&self, memory: &MemoryBlock) -> usize {
+        let alloc_header = unsafe { std::ptr::read_unaligned(memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *const AllocationHeader) };
+
+        alloc_header.allocation_size as usize
+    }
+
+    ///
+    /// Every allocation occupies a whole block, rounding requests up to the next
+    /// power of two shows up as overhead
+    ///
+    fn stats(&self) -> AllocatorStats {
+        let storage = self.storage.borrow();
+        storage.stats.snapshot(storage.mem_end as usize - storage.region.base() as usize, None)
+    }
+}
+
+impl OwningAllocator for BuddyAllocator {
+    fn owns(&self, memory: &MemoryBlock) -> bool {
+        let storage = self.storage.borrow();
+        let ptr = memory.ptr as *const u8;
+        ptr >= storage.region.base() as *const u8 && ptr < storage.mem_end as *const u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KB: usize = 1024;
+    const MB: usize = KB * 1024;
+
+    #[test]
+    fn size_is_rounded_to_power_of_two() {
+        let buddy_alloc = BuddyAllocator::new(3 * MB);
+        assert_eq!(buddy_alloc.block_size(buddy_alloc.max_order()), 4 * MB);
+        assert_eq!(buddy_alloc.free_block_count(buddy_alloc.max_order()), 1);
+    }
+
+    #[test]
+    fn single_allocation() {
+        let buddy_alloc = BuddyAllocator::new(MB);
+        let mem = buddy_alloc.alloc_raw(100, 1, 0);
+        assert!(mem.is_some());
+    }
+
+    #[test]
+    fn single_allocation_aligned_with_offset() {
+        let buddy_alloc = BuddyAllocator::new(MB);
+        let mem = buddy_alloc.alloc_raw(100, 64, 4).unwrap();
+
+        let offsetted_ptr = unsafe { mem.ptr.offset(4) };
+        assert!(pointer_util::is_aligned_to(offsetted_ptr, 64), "User pointer was not properly aligned");
+    }
+
+    #[test]
+    fn allocation_with_odd_offset() {
+        let buddy_alloc = BuddyAllocator::new(KB);
+        let max_order = buddy_alloc.max_order();
+        let mem = buddy_alloc.alloc_raw(100, 16, 3).unwrap();
+
+        let offsetted_ptr = unsafe { mem.ptr.offset(3) };
+        assert!(pointer_util::is_aligned_to(offsetted_ptr, 16), "User pointer was not properly aligned");
+        assert_eq!(buddy_alloc.get_allocation_size(&mem), 100);
+
+        buddy_alloc.dealloc_raw(mem);
+        assert_eq!(buddy_alloc.free_block_count(max_order), 1, "Block was not returned to the allocator");
+    }
+
+    #[test]
+    fn return_right_allocation_size() {
+        let buddy_alloc = BuddyAllocator::new(MB);
+        let mem_0 = buddy_alloc.alloc_raw(100, 1, 0).unwrap();
+        let mem_1 = buddy_alloc.alloc_raw(5000, 16, 0).unwrap();
+
+        assert_eq!(buddy_alloc.get_allocation_size(&mem_0), 100);
+        assert_eq!(buddy_alloc.get_allocation_size(&mem_1), 5000);
+    }
+
+    #[test]
+    fn alloc_splits_blocks() {
+        let buddy_alloc = BuddyAllocator::new(KB);
+        let max_order = buddy_alloc.max_order();
+
+        // 100 bytes plus headers need a block of 128 bytes, which requires three splits
+        let _mem = buddy_alloc.alloc_raw(100, 1, 0).unwrap();
+        let order = order_for_size(128);
+
+        assert_eq!(buddy_alloc.free_block_count(max_order), 0);
+        for split_order in order .. max_order {
+            assert_eq!(buddy_alloc.free_block_count(split_order), 1, "Split did not leave a free buddy behind");
+        }
+    }
+
+    #[test]
+    fn dealloc_merges_buddies() {
+        let buddy_alloc = BuddyAllocator::new(KB);
+        let max_order = buddy_alloc.max_order();
+
+        let mem_0 = buddy_alloc.alloc_raw(100, 1, 0).unwrap();
+        let mem_1 = buddy_alloc.alloc_raw(100, 1, 0).unwrap();
+        let mem_2 = buddy_alloc.alloc_raw(300, 1, 0).unwrap();
+
+        buddy_alloc.dealloc_raw(mem_1);
+        buddy_alloc.dealloc_raw(mem_2);
+        assert_eq!(buddy_alloc.free_block_count(max_order), 0);
+
+        buddy_alloc.dealloc_raw(mem_0);
+        assert_eq!(buddy_alloc.free_block_count(max_order), 1, "Buddies were not merged back into one block");
+        for order in 0 .. max_order {
+            assert_eq!(buddy_alloc.free_block_count(order), 0);
+        }
+    }
+
+    #[test]
+    fn returns_none_when_out_of_memory() {
+        let buddy_alloc = BuddyAllocator::new(KB);
+        assert!(buddy_alloc.alloc_raw(KB, 1, 0).is_none());
+
+        let mem = buddy_alloc.alloc_raw(400, 1, 0).unwrap();
+        buddy_alloc.alloc_raw(400, 1, 0).unwrap();
+        assert!(buddy_alloc.alloc_raw(400, 1, 0).is_none());
+
+        buddy_alloc.dealloc_raw(mem);
+        assert!(buddy_alloc.alloc_raw(400, 1, 0).is_some(), "Freed block was not reused");
+    }
+
+    #[test]
+    fn failed_request_reports_largest_free_block() {
+        let buddy_alloc = BuddyAllocator::new(KB);
+        let largest_block_size = buddy_alloc.block_size(buddy_alloc.max_order() - 1);
+        buddy_alloc.alloc_raw(400, 1, 0).unwrap();
+
+        match buddy_alloc.try_alloc_raw(KB, 1, 0) {
+            Err(AllocError::OutOfMemory { requested, available }) => {
+                assert_eq!(requested, KB);
+                assert_eq!(available, largest_block_size - BLOCK_HEADER_SIZE - ALLOCATION_META_SIZE);
+                assert!(buddy_alloc.alloc_raw(available + 1, 1, 0).is_none());
+                assert!(buddy_alloc.alloc_raw(available, 1, 0).is_some(), "Reported memory is not available");
+            },
+            _ => panic!("Expected OutOfMemory"),
+        }
+
+        match buddy_alloc.try_alloc_raw(1, 1, 0) {
+            Err(AllocError::OutOfMemory { available, .. }) => assert_eq!(available, 0),
+            _ => panic!("Expected OutOfMemory"),
+        }
+        assert_eq!(buddy_alloc.try_alloc_raw(8, 3, 0).unwrap_err(), AllocError::InvalidAlignment { alignment: 3 });
+    }
+
+    #[test]
+    fn reset_whole_allocator() {
+        let buddy_alloc = BuddyAllocator::new(MB);
+        let mem_0 = buddy_alloc.alloc_raw(KB, 8, 0).unwrap();
+        buddy_alloc.alloc_raw(4 * KB, 8, 0).unwrap();
+        let mem_0_ptr = mem_0.ptr;
+        buddy_alloc.reset();
+
+        assert_eq!(buddy_alloc.free_block_count(buddy_alloc.max_order()), 1);
+        assert_eq!(buddy_alloc.alloc_raw(KB, 8, 0).unwrap().ptr, mem_0_ptr);
+    }
+}
\ No newline at end of file
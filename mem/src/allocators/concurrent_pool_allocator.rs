@@ -0,0 +1,336 @@
+use std;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+#[cfg(feature = "epoch_check")]
+use std::sync::atomic::AtomicU32;
+use spark_core::{ pointer_util, atomic_freelist, math_util };
+
+use super::super::virtual_mem::VirtualRegion;
+use super::base::{ Allocator, MemoryBlock, TypedAllocator, OwningAllocator };
+use super::alloc_error::AllocError;
+use super::allocator_stats::AllocatorStats;
+
+///
+/// The AllocationHeader struct describes meta-data
+/// the allocator needs to store alongside of the
+/// allocations.
+///
+struct AllocationHeader {
+    pub allocation_size: u32,
+    #[cfg(feature = "epoch_check")]
+    pub epoch:           u32,
+}
+
+const ALLOCATION_META_SIZE: usize = std::mem::size_of::<AllocationHeader>();
+
+// The allocation header in front of every element has to stay aligned
+const HEADER_ALIGNMENT: usize = std::mem::align_of::<AllocationHeader>();
+
+fn calculate_minimal_block_size(max_size: usize, max_alignment: usize) -> usize {
+    if max_size < max_alignment {
+        max_alignment
+    }
+    else {
+        math_util::round_to_next_multiple(max_size, max_alignment)
+    }
+}
+
+///
+/// The ConcurrentPoolAllocator hands out blocks of a fixed size to any number of threads
+/// without taking a lock. Its blocks are managed by an AtomicFreeList, which makes allocating
+/// and freeing a single compare-exchange in the uncontended case. Unlike the PoolAllocator it
+/// is Send and Sync. Releasing blocks that are still in use needs exclusive access, which is
+/// why the allocator is reset through `release_all` instead of `reset`.
+///
+pub struct ConcurrentPoolAllocator {
+    _region:                VirtualRegion,
+    first_block_ptr:        *mut u8,
+    mem_end:                *mut u8,
+    max_element_size:       usize,
+    max_element_alignment:  usize,
+    block_size:             usize,
+    free_list:              atomic_freelist::AtomicFreeList,
+    used_bytes:             AtomicUsize,
+    live_allocations:       AtomicUsize,
+    peak_allocations:       AtomicUsize,
+    failed_requests:        AtomicUsize,
+    #[cfg(feature = "epoch_check")]
+    epoch:                  AtomicU32,
+}
+
+// All state that is mutated after construction is atomic
+unsafe impl Send for ConcurrentPoolAllocator {}
+unsafe impl Sync for ConcurrentPoolAllocator {}
+
+impl ConcurrentPoolAllocator {
+    ///
+    /// Frees all blocks at once, including the ones that are still allocated. This
+    /// invalidates every MemoryBlock handed out so far
+    ///
+    pub fn release_all(&mut self) {
+        self.free_list.reset();
+        *self.used_bytes.get_mut() = 0;
+        *self.live_allocations.get_mut() = 0;
+        #[cfg(feature = "epoch_check")]
+        {
+            *self.epoch.get_mut() += 1;
+        }
+    }
+}
+
+impl TypedAllocator for ConcurrentPoolAllocator {
+    type AllocatorImplementation = ConcurrentPoolAllocator;
+
+    fn try_new(max_element_size: usize, element_count: usize, max_element_alignment: usize, offset: usize) -> Result<Self::AllocatorImplementation, AllocError> {
+        {
+            let offset_keeps_headers_aligned = (offset + ALLOCATION_META_SIZE) % HEADER_ALIGNMENT == 0;
+            debug_assert!(offset_keeps_headers_aligned, "Offset has to be a multiple of 4 bytes");
+        }
+
+        let block_alignment = std::cmp::max(max_element_alignment, HEADER_ALIGNMENT);
+        let block_min_size = calculate_minimal_block_size(max_element_size + ALLOCATION_META_SIZE, block_alignment);
+        let required_memory_size = (element_count * block_min_size) + block_alignment;
+
+        let mut region = VirtualRegion::reserve(required_memory_size).ok_or(AllocError::ReserveFailed { size: required_memory_size })?;
+        let physical_address_space = region.commit(0, required_memory_size).ok_or(AllocError::CommitFailed { size: required_memory_size })?;
+
+        let first_block_ptr = unsafe {
+            let allocation_meta_offset = (offset + ALLOCATION_META_SIZE) as isize;
+            let aligned_ptr  = pointer_util::align_top(physical_address_space.offset(allocation_meta_offset), block_alignment) as *mut u8;
+            let before_aligned_ptr = aligned_ptr.offset(-allocation_meta_offset);
+
+            before_aligned_ptr
+        };
+
+        let mem_end = unsafe { first_block_ptr.offset((element_count * block_min_size) as isize) };
+
+        Ok(ConcurrentPoolAllocator {
+            _region:                region,
+            first_block_ptr,
+            mem_end,
+            max_element_size,
+            max_element_alignment:  block_alignment,
+            block_size:             block_min_size,
+            free_list:              atomic_freelist::AtomicFreeList::new_from(first_block_ptr, mem_end, block_min_size),
+            used_bytes:             AtomicUsize::new(0),
+            live_allocations:       AtomicUsize::new(0),
+            peak_allocations:       AtomicUsize::new(0),
+            failed_requests:        AtomicUsize::new(0),
+            #[cfg(feature = "epoch_check")]
+            epoch:                  AtomicU32::new(0),
+        })
+    }
+}
+
+impl Allocator for ConcurrentPoolAllocator {
+    fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
+        self.try_alloc_raw(size, alignment, offset).ok()
+    }
+
+    fn try_alloc_raw(&self, size: usize, alignment: usize, _offset: usize) -> Result<MemoryBlock, AllocError> {
+        if size > self.max_element_size {
+            self.failed_requests.fetch_add(1, Ordering::Relaxed);
+            return Err(AllocError::ExceedsMaxElementSize { requested: size, max_element_size: self.max_element_size });
+        }
+
+        if !pointer_util::is_pot(alignment) || alignment > self.max_element_alignment {
+            self.failed_requests.fetch_add(1, Ordering::Relaxed);
+            return Err(AllocError::InvalidAlignment { alignment });
+        }
+
+        let mut ptr = self.free_list.get_block();
+
+        if ptr.is_null() {
+            self.failed_requests.fetch_add(1, Ordering::Relaxed);
+            return Err(AllocError::OutOfMemory { requested: size, available: 0 });
+        }
+
+        unsafe {
+            let allocation_header = &mut *(ptr as *mut AllocationHeader);
+            allocation_header.allocation_size = size as u32;
+            #[cfg(feature = "epoch_check")]
+            {
+                allocation_header.epoch = self.epoch.load(Ordering::Relaxed);
+            }
+            ptr = ptr.offset(ALLOCATION_META_SIZE as isize);
+        }
+
+        self.used_bytes.fetch_add(size, Ordering::Relaxed);
+        let live_allocations = self.live_allocations.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak_allocations.fetch_max(live_allocations, Ordering::Relaxed);
+
+        Ok(MemoryBlock::new(ptr))
+    }
+
+    fn dealloc_raw(&self, memory: MemoryBlock) {
+        {
+            let ptr_in_range = self.owns(&memory);
+            debug_assert!(ptr_in_range, "AllocatorMem was not allocated by this allocator");
+        }
+
+        self.validate_block(&memory);
+
+        let allocation_size = self.get_allocation_size(&memory);
+        let original_ptr = unsafe { memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) };
+        self.free_list.return_block(original_ptr);
+
+        self.used_bytes.fetch_sub(allocation_size, Ordering::Relaxed);
+        self.live_allocations.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    ///
+    /// Other threads may still use their blocks while the allocator is shared, so a reset
+    /// through a shared reference cannot take blocks back. It asserts that all blocks were
+    /// returned already, use `release_all` to free blocks that are still allocated
+    ///
+    fn reset(&self) {
+        let all_blocks_returned = self.live_allocations.load(Ordering::Acquire) == 0;
+        assert!(all_blocks_returned, "ConcurrentPoolAllocator can only release live blocks through release_all");
+
+        #[cfg(feature = "epoch_check")]
+        {
+            self.epoch.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn get_allocation_size(&self, memory: &MemoryBlock) -> usize {
+        self.validate_block(memory);
+
+        let alloc_header = unsafe { &*(memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *const AllocationHeader) };
+        alloc_header.allocation_size as usize
+    }
+
+    #[cfg(feature = "epoch_check")]
+    fn validate_block(&self, memory: &MemoryBlock) {
+        let alloc_header = unsafe { &*(memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *const AllocationHeader) };
+        let block_is_current = alloc_header.epoch == self.epoch.load(Ordering::Relaxed);
+        assert!(block_is_current, "MemoryBlock was invalidated by a reset of its allocator");
+    }
+
+    ///
+    /// The counters are updated independently of each other, a snapshot taken while
+    /// other threads allocate is not guaranteed to be consistent
+    ///
+    fn stats(&self) -> AllocatorStats {
+        let block_count = (self.mem_end as usize - self.first_block_ptr as usize) / self.block_size;
+        let used_bytes = self.used_bytes.load(Ordering::Relaxed);
+        let live_allocations = self.live_allocations.load(Ordering::Relaxed);
+
+        AllocatorStats {
+            capacity:           block_count * self.block_size,
+            used_bytes,
+            overhead_bytes:     (live_allocations * self.block_size).saturating_sub(used_bytes),
+            high_water_mark:    self.peak_allocations.load(Ordering::Relaxed) * self.block_size,
+            live_allocations,
+            failed_requests:    self.failed_requests.load(Ordering::Relaxed),
+            free_blocks:        Some(block_count.saturating_sub(live_allocations)),
+        }
+    }
+}
+
+impl OwningAllocator for ConcurrentPoolAllocator {
+    fn owns(&self, memory: &MemoryBlock) -> bool {
+        let ptr = memory.ptr as *const u8;
+        ptr >= self.first_block_ptr as *const u8 && ptr < self.mem_end as *const u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{ AtomicBool, Ordering };
+    use std::thread;
+
+    struct Node {
+        pub thread_idx: usize,
+        pub sequence:   usize,
+    }
+
+    const NODE_COUNT: usize = 128;
+
+    #[test]
+    fn single_allocation_aligned() {
+        let pool_alloc = ConcurrentPoolAllocator::new(std::mem::size_of::<Node>(), 10, 16, 0);
+
+        let mem = pool_alloc.alloc_raw(std::mem::size_of::<Node>(), 16, 0).unwrap();
+        assert!(pointer_util::is_aligned_to(mem.ptr, 16));
+        assert_eq!(pool_alloc.get_allocation_size(&mem), std::mem::size_of::<Node>());
+    }
+
+    #[test]
+    fn return_none_on_oom() {
+        let mut pool_alloc = ConcurrentPoolAllocator::new(std::mem::size_of::<Node>(), 10, 8, 0);
+
+        for _ in 0 .. 10 {
+            assert!(pool_alloc.alloc_raw(std::mem::size_of::<Node>(), 8, 0).is_some());
+        }
+
+        assert!(pool_alloc.alloc_raw(std::mem::size_of::<Node>(), 8, 0).is_none());
+
+        pool_alloc.release_all();
+        assert!(pool_alloc.alloc_raw(std::mem::size_of::<Node>(), 8, 0).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "ConcurrentPoolAllocator can only release live blocks through release_all")]
+    fn shared_reset_keeps_live_blocks() {
+        let pool_alloc = ConcurrentPoolAllocator::new(std::mem::size_of::<Node>(), 10, 8, 0);
+
+        let mem = pool_alloc.alloc_raw(std::mem::size_of::<Node>(), 8, 0).unwrap();
+        pool_alloc.dealloc_raw(mem);
+        pool_alloc.reset();
+
+        pool_alloc.alloc_raw(std::mem::size_of::<Node>(), 8, 0).unwrap();
+        pool_alloc.reset();
+    }
+
+    #[test]
+    fn concurrent_allocations_are_exclusive() {
+        let pool_alloc = Arc::new(ConcurrentPoolAllocator::new(std::mem::size_of::<Node>(), NODE_COUNT, 8, 0));
+        let in_use: Arc<Vec<AtomicBool>> = Arc::new((0 .. NODE_COUNT).map(|_| AtomicBool::new(false)).collect());
+        let first_block = pool_alloc.first_block_ptr as usize;
+        let block_size = (pool_alloc.mem_end as usize - first_block) / NODE_COUNT;
+
+        let threads: Vec<_> = (0 .. 8).map(|thread_idx| {
+            let pool_alloc = pool_alloc.clone();
+            let in_use = in_use.clone();
+            thread::spawn(move || {
+                let mut nodes = Vec::new();
+                for sequence in 0 .. 20000 {
+                    if let Some(mem) = pool_alloc.alloc_raw(std::mem::size_of::<Node>(), 8, 0) {
+                        let index = (mem.ptr as usize - first_block) / block_size;
+                        assert!(!in_use[index].swap(true, Ordering::AcqRel), "Block was handed out twice");
+
+                        let node = unsafe { &mut *(mem.ptr as *mut Node) };
+                        node.thread_idx = thread_idx;
+                        node.sequence = sequence;
+                        nodes.push((mem.ptr, index, sequence));
+                    }
+
+                    if sequence % 4 == 0 {
+                        for (ptr, index, sequence) in nodes.drain(..) {
+                            let node = unsafe { &*(ptr as *const Node) };
+                            assert!(node.thread_idx == thread_idx && node.sequence == sequence, "Node was overwritten by another thread");
+
+                            in_use[index].store(false, Ordering::Release);
+                            pool_alloc.dealloc_raw(MemoryBlock::new(ptr));
+                        }
+                    }
+                }
+
+                for (ptr, index, _) in nodes {
+                    in_use[index].store(false, Ordering::Release);
+                    pool_alloc.dealloc_raw(MemoryBlock::new(ptr));
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        for _ in 0 .. NODE_COUNT {
+            assert!(pool_alloc.alloc_raw(std::mem::size_of::<Node>(), 8, 0).is_some(), "Blocks were lost");
+        }
+    }
+}
\ No newline at end of file
@@ -2,8 +2,10 @@ use std;
 use std::cell::RefCell;
 use spark_core::pointer_util;
 
-use super::super::virtual_mem;
-use super::base::{ Allocator, MemoryBlock, BasicAllocator };
+use super::super::virtual_mem::{ VirtualRegion, PageMode };
+use super::base::{ Allocator, MemoryBlock, BasicAllocator, OwningAllocator };
+use super::alloc_error::AllocError;
+use super::allocator_stats::{ AllocatorStats, StatsCounters };
 
 ///
 /// The AllocationHeader struct describes meta-data
@@ -13,8 +15,10 @@ use super::base::{ Allocator, MemoryBlock, BasicAllocator };
 struct AllocationHeader {
     pub allocation_offset:  u32,
     pub allocation_size:    u32,
-    #[cfg(stack_alloc_lifo_check)]
+    #[cfg(feature = "stack_alloc_lifo_check")]
     pub allocation_id:      u32,
+    #[cfg(feature = "epoch_check")]
+    pub epoch:              u32,
 }
 
 const ALLOCATION_META_SIZE: usize = std::mem::size_of::<AllocationHeader>();
@@ -26,14 +30,17 @@ const ALLOCATION_META_SIZE: usize = std::mem::size_of::<AllocationHeader>();
 ///
 struct DoubleEndedStackAllocatorStorage {
     pub use_internal_mem:       bool,
-    pub mem_begin:              *mut u8,
+    pub region:                 VirtualRegion,
     pub mem_end:                *mut u8,
     pub current_front_ptr:      *mut u8,
     pub current_end_ptr:        *mut u8,
-    #[cfg(stack_alloc_lifo_check)]
+    pub stats:                  StatsCounters,
+    #[cfg(feature = "stack_alloc_lifo_check")]
     pub front_allocation_id:    u32,
-    #[cfg(stack_alloc_lifo_check)]
+    #[cfg(feature = "stack_alloc_lifo_check")]
     pub back_allocation_id:     u32,
+    #[cfg(feature = "epoch_check")]
+    pub epoch:                  u32,
 }
 
 impl DoubleEndedStackAllocatorStorage {
@@ -41,31 +48,26 @@ impl DoubleEndedStackAllocatorStorage {
     /// Creates a new stack allocator storage and allocates the memory
     /// block requested by the allocator from the virtual memory API
     ///
-    fn new(size: usize) -> DoubleEndedStackAllocatorStorage {
-
-        let virtual_mem = match virtual_mem::reserve_address_space(size) {
-            Some(address) => address,
-            None => std::ptr::null_mut(),
-        };
-
-        let physical_address_space = match virtual_mem::commit_physical_memory(virtual_mem, size) {
-            Some(address) => address,
-            None => std::ptr::null_mut(),
-        };
+    fn new(size: usize, page_mode: PageMode) -> Result<DoubleEndedStackAllocatorStorage, AllocError> {
 
+        let mut region = VirtualRegion::reserve_with_page_mode(size, page_mode).ok_or(AllocError::ReserveFailed { size })?;
+        let physical_address_space = region.commit(0, size).ok_or(AllocError::CommitFailed { size })?;
         let physical_address_space_end =  unsafe { physical_address_space.offset(size as isize) };
 
-        DoubleEndedStackAllocatorStorage {
+        Ok(DoubleEndedStackAllocatorStorage {
             use_internal_mem:       true,
-            mem_begin:              physical_address_space,
+            region,
             mem_end:                physical_address_space_end,
             current_front_ptr:      physical_address_space,
             current_end_ptr:        physical_address_space_end,
-            #[cfg(stack_alloc_lifo_check)]
+            stats:                  StatsCounters::default(),
+            #[cfg(feature = "stack_alloc_lifo_check")]
             front_allocation_id:    0,
-            #[cfg(stack_alloc_lifo_check)]
+            #[cfg(feature = "stack_alloc_lifo_check")]
             back_allocation_id:     0,
-        }
+            #[cfg(feature = "epoch_check")]
+            epoch:                  0,
+        })
     }
 }
 
@@ -73,22 +75,59 @@ pub struct DoubleEndedStackAllocator {
     storage: RefCell<DoubleEndedStackAllocatorStorage>,
 }
 
+// Both stack pointers only point into the region owned by the allocator,
+// so it can be moved to another thread as a whole
+unsafe impl Send for DoubleEndedStackAllocator {}
+
 impl DoubleEndedStackAllocator {
+    ///
+    /// Creates a double-ended stack allocator whose memory is backed by pages of the
+    /// given mode, falling back to smaller pages if the system cannot provide them
+    ///
+    pub fn with_page_mode(size: usize, page_mode: PageMode) -> DoubleEndedStackAllocator {
+        DoubleEndedStackAllocator::try_with_page_mode(size, page_mode).expect("Could not create the allocator")
+    }
+
+    ///
+    /// Like `with_page_mode`, but returns an error if the memory could not be reserved or committed
+    ///
+    pub fn try_with_page_mode(size: usize, page_mode: PageMode) -> Result<DoubleEndedStackAllocator, AllocError> {
+        debug_assert!(size > 0usize, "Size is not allowed to be 0");
+
+        Ok(DoubleEndedStackAllocator {
+            storage: RefCell::new(DoubleEndedStackAllocatorStorage::new(size, page_mode)?),
+        })
+    }
+
     pub fn alloc_raw_back(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
-       debug_assert!(pointer_util::is_pot(alignment), "Alignment needs to be a power of two");
+        self.try_alloc_raw_back(size, alignment, offset).ok()
+    }
 
+    pub fn try_alloc_raw_back(&self, size: usize, alignment: usize, offset: usize) -> Result<MemoryBlock, AllocError> {
         let mut allocator_storage = self.storage.borrow_mut();
-        let current_ptr_offset = allocator_storage.current_end_ptr as isize - allocator_storage.mem_end as isize;
+
+        if !pointer_util::is_pot(alignment) {
+            allocator_storage.stats.on_failed_request();
+            return Err(AllocError::InvalidAlignment { alignment });
+        }
+
+        let previous_ptr = allocator_storage.current_end_ptr;
+        let current_ptr_offset = allocator_storage.mem_end as usize - allocator_storage.current_end_ptr as usize;
         let offset_before_alignment = offset + ALLOCATION_META_SIZE;
 
         unsafe {
             allocator_storage.current_end_ptr = allocator_storage.current_end_ptr.offset(-(size as isize));
             allocator_storage.current_end_ptr = pointer_util::align_bottom(allocator_storage.current_end_ptr, alignment) as *mut u8;
 
-            // If we overflow we cannot fulfill this allocation and return None
+            // If we overflow we cannot fulfill this allocation and return an error
             let allocation_overflows_front_block = allocator_storage.current_end_ptr.offset(-(offset_before_alignment as isize)) < allocator_storage.current_front_ptr;
             if  allocation_overflows_front_block {
-                return None;
+                allocator_storage.current_end_ptr = previous_ptr;
+                allocator_storage.stats.on_failed_request();
+                return Err(AllocError::OutOfMemory {
+                    requested: size,
+                    available: (previous_ptr as usize).saturating_sub(allocator_storage.current_front_ptr as usize),
+                });
             }
 
             allocator_storage.current_end_ptr = allocator_storage.current_end_ptr.offset(-(offset_before_alignment as isize));
@@ -99,20 +138,29 @@ impl DoubleEndedStackAllocator {
             // Write allocation meta data
             as_alloc_header.allocation_offset = current_ptr_offset as u32;
             as_alloc_header.allocation_size = size as u32;
-            #[cfg(stack_alloc_lifo_check)]
+            #[cfg(feature = "stack_alloc_lifo_check")]
             {
                 allocator_storage.back_allocation_id += 1;
                 as_alloc_header.allocation_id = allocator_storage.back_allocation_id;
             }
+            #[cfg(feature = "epoch_check")]
+            {
+                as_alloc_header.epoch = allocator_storage.epoch;
+            }
 
             user_ptr = user_ptr.offset(ALLOCATION_META_SIZE as isize);
             allocator_storage.current_end_ptr = allocator_storage.current_end_ptr.offset(-(ALLOCATION_META_SIZE as isize));
 
-            Some(MemoryBlock::new(user_ptr))
+            let occupied_size = previous_ptr as usize - allocator_storage.current_end_ptr as usize;
+            allocator_storage.stats.on_alloc(size, occupied_size);
+
+            Ok(MemoryBlock::new(user_ptr))
         }
     }
 
     pub fn dealloc_raw_back(&self, memory: MemoryBlock) {
+       self.validate_block(&memory);
+
        let raw_mem = memory.ptr;
 
         unsafe {
@@ -120,43 +168,107 @@ impl DoubleEndedStackAllocator {
             let alloc_header = &mut *(raw_mem.offset(-(ALLOCATION_META_SIZE as isize)) as *mut AllocationHeader);
             
             {
-                let ptr_in_range = raw_mem >= storage.mem_begin && raw_mem < storage.mem_end;
+                let ptr_in_range = raw_mem >= storage.region.base() && raw_mem < storage.mem_end;
                 debug_assert!(ptr_in_range, "AllocatorMem was not allocated by this allocator");
                 let ptr_in_back_block = raw_mem >= storage.current_end_ptr;
                 debug_assert!(ptr_in_back_block, "AllocatorMem was not allocated via `alloc_back` (back block)");
             }
 
-            #[cfg(stack_alloc_lifo_check)]
+            #[cfg(feature = "stack_alloc_lifo_check")]
             {
                 let was_freed_in_lifo_fashion = alloc_header.allocation_id == storage.back_allocation_id;
-                debug_assert!(was_freed_in_lifo_fashion, "Double ended stack allocator does only support LIFO fashioned freeing");
+                assert!(was_freed_in_lifo_fashion, "Double ended stack allocator does only support LIFO fashioned freeing");
                 storage.back_allocation_id -= 1;
             }
 
+            let previous_ptr = storage.current_end_ptr;
             storage.current_end_ptr = storage.mem_end.offset(-(alloc_header.allocation_offset as isize));
+
+            let occupied_size = storage.current_end_ptr as usize - previous_ptr as usize;
+            storage.stats.on_dealloc(alloc_header.allocation_size as usize, occupied_size);
+        }
+    }
+
+    ///
+    /// Resizes a block of the back block. As the back block grows downwards, growing its
+    /// topmost allocation moves the contents further down in place, so the returned block
+    /// starts at a different address. Other blocks are shrunk in place, but cannot grow without
+    /// breaking the LIFO order of the back block. None is returned for them and the block stays valid
+    ///
+    pub fn realloc_raw_back(&self, memory: &MemoryBlock, new_size: usize, alignment: usize) -> Option<MemoryBlock> {
+        debug_assert!(pointer_util::is_pot(alignment), "Alignment needs to be a power of two");
+
+        let old_size = self.get_allocation_size(memory);
+
+        if new_size <= old_size && pointer_util::is_aligned_to(memory.ptr, alignment) {
+            let alloc_header = unsafe { &mut *(memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *mut AllocationHeader) };
+            alloc_header.allocation_size = new_size as u32;
+            self.storage.borrow_mut().stats.on_resize(old_size, old_size, new_size, old_size);
+            return Some(MemoryBlock::new(memory.ptr));
         }
+
+        {
+            let mut storage = self.storage.borrow_mut();
+            let header_ptr = unsafe { memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) };
+            // Allocations in the back block leave the size of a header below their own header
+            let is_topmost_allocation = header_ptr == unsafe { storage.current_end_ptr.offset(ALLOCATION_META_SIZE as isize) };
+
+            if is_topmost_allocation {
+                unsafe {
+                    // The header is read up front, as moving the contents can overwrite it
+                    let old_header = std::ptr::read(header_ptr as *const AllocationHeader);
+                    let block_end = storage.mem_end.offset(-(old_header.allocation_offset as isize));
+                    let new_user_ptr = pointer_util::align_bottom(block_end.offset(-(new_size as isize)), alignment) as *mut u8;
+                    let new_header_ptr = new_user_ptr.offset(-(ALLOCATION_META_SIZE as isize));
+                    let new_end_ptr = new_header_ptr.offset(-(ALLOCATION_META_SIZE as isize));
+
+                    let allocation_overflows_front_block = new_end_ptr < storage.current_front_ptr;
+                    if !allocation_overflows_front_block {
+                        std::ptr::copy(memory.ptr, new_user_ptr, std::cmp::min(old_size, new_size));
+                        std::ptr::write(new_header_ptr as *mut AllocationHeader, AllocationHeader {
+                            allocation_size: new_size as u32,
+                            ..old_header
+                        });
+
+                        let old_occupied_size = block_end as usize - storage.current_end_ptr as usize;
+                        storage.current_end_ptr = new_end_ptr;
+                        let new_occupied_size = block_end as usize - storage.current_end_ptr as usize;
+
+                        storage.stats.on_resize(old_size, old_occupied_size, new_size, new_occupied_size);
+                        return Some(MemoryBlock::new(new_user_ptr));
+                    }
+                }
+            }
+        }
+
+        None
     }
 }
 
 impl BasicAllocator for DoubleEndedStackAllocator {
     type AllocatorImplementation = DoubleEndedStackAllocator;
 
-    fn new(size: usize) -> Self::AllocatorImplementation {
-        debug_assert!(size > 0usize, "Size is not allowed to be 0");
-
-        DoubleEndedStackAllocator {
-            storage: RefCell::new(DoubleEndedStackAllocatorStorage::new(size)),
-        }
+    fn try_new(size: usize) -> Result<Self::AllocatorImplementation, AllocError> {
+        DoubleEndedStackAllocator::try_with_page_mode(size, PageMode::Default)
     }
 }
 
 impl Allocator for DoubleEndedStackAllocator {
     
     fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
-        debug_assert!(pointer_util::is_pot(alignment), "Alignment needs to be a power of two");
+        self.try_alloc_raw(size, alignment, offset).ok()
+    }
 
+    fn try_alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Result<MemoryBlock, AllocError> {
         let mut allocator_storage = self.storage.borrow_mut();
-        let current_ptr_offset = allocator_storage.current_front_ptr as usize - allocator_storage.mem_begin as usize;
+
+        if !pointer_util::is_pot(alignment) {
+            allocator_storage.stats.on_failed_request();
+            return Err(AllocError::InvalidAlignment { alignment });
+        }
+
+        let previous_ptr = allocator_storage.current_front_ptr;
+        let current_ptr_offset = allocator_storage.current_front_ptr as usize - allocator_storage.region.base() as usize;
         let offset_before_alignment = offset + ALLOCATION_META_SIZE;
 
         unsafe {
@@ -165,10 +277,15 @@ impl Allocator for DoubleEndedStackAllocator {
             allocator_storage.current_front_ptr = allocator_storage.current_front_ptr.offset(offset_before_alignment as isize);
             allocator_storage.current_front_ptr = pointer_util::align_top(allocator_storage.current_front_ptr, alignment) as *mut u8;
 
-            // If we overflow we cannot fulfill this allocation and return None
+            // If we overflow we cannot fulfill this allocation and return an error
             let allocation_overflows_end_block = allocator_storage.current_front_ptr.offset((size - offset) as isize) > allocator_storage.current_end_ptr;
             if  allocation_overflows_end_block {
-                return None;
+                allocator_storage.current_front_ptr = previous_ptr;
+                allocator_storage.stats.on_failed_request();
+                return Err(AllocError::OutOfMemory {
+                    requested: size,
+                    available: (allocator_storage.current_end_ptr as usize).saturating_sub(previous_ptr as usize),
+                });
             }
 
             allocator_storage.current_front_ptr = allocator_storage.current_front_ptr.offset(-(offset_before_alignment as isize));
@@ -179,20 +296,29 @@ impl Allocator for DoubleEndedStackAllocator {
             // Write allocation meta data
             as_alloc_header.allocation_offset = current_ptr_offset as u32;
             as_alloc_header.allocation_size = size as u32;
-            #[cfg(stack_alloc_lifo_check)]
+            #[cfg(feature = "stack_alloc_lifo_check")]
+            {
+                allocator_storage.front_allocation_id += 1;
+                as_alloc_header.allocation_id = allocator_storage.front_allocation_id;
+            }
+            #[cfg(feature = "epoch_check")]
             {
-                allocator_storage.allocation_id += 1;
-                as_alloc_header.allocation_id = allocator_storage.allocation_id;
+                as_alloc_header.epoch = allocator_storage.epoch;
             }
 
             user_ptr = user_ptr.offset(ALLOCATION_META_SIZE as isize);
             allocator_storage.current_front_ptr = allocator_storage.current_front_ptr.offset((size + ALLOCATION_META_SIZE) as isize);
 
-            Some(MemoryBlock::new(user_ptr))
+            let occupied_size = allocator_storage.current_front_ptr as usize - previous_ptr as usize;
+            allocator_storage.stats.on_alloc(size, occupied_size);
+
+            Ok(MemoryBlock::new(user_ptr))
         }
     }
 
     fn dealloc_raw(&self, memory: MemoryBlock) {
+        self.validate_block(&memory);
+
         let raw_mem = memory.ptr;
 
         unsafe {
@@ -200,37 +326,48 @@ impl Allocator for DoubleEndedStackAllocator {
             let alloc_header = &mut *(raw_mem.offset(-(ALLOCATION_META_SIZE as isize)) as *mut AllocationHeader);
             
             {
-                let ptr_in_range = raw_mem >= storage.mem_begin && raw_mem < storage.mem_end;
+                let ptr_in_range = raw_mem >= storage.region.base() && raw_mem < storage.mem_end;
                 debug_assert!(ptr_in_range, "AllocatorMem was not allocated by this allocator");
                 let ptr_in_front_block = raw_mem < storage.current_end_ptr;
                 debug_assert!(ptr_in_front_block, "AllocatorMem was not allocated via `alloc` (front block)");
             }
 
-            #[cfg(stack_alloc_lifo_check)]
+            #[cfg(feature = "stack_alloc_lifo_check")]
             {
                 let was_freed_in_lifo_fashion = alloc_header.allocation_id == storage.front_allocation_id;
-                debug_assert!(was_freed_in_lifo_fashion, "Double ended stack allocator does only support LIFO fashioned freeing");
+                assert!(was_freed_in_lifo_fashion, "Double ended stack allocator does only support LIFO fashioned freeing");
                 storage.front_allocation_id -= 1;
             }
 
-            storage.current_front_ptr = storage.mem_begin.offset(alloc_header.allocation_offset as isize);
+            let previous_ptr = storage.current_front_ptr;
+            storage.current_front_ptr = storage.region.base().offset(alloc_header.allocation_offset as isize);
+
+            let occupied_size = previous_ptr as usize - storage.current_front_ptr as usize;
+            storage.stats.on_dealloc(alloc_header.allocation_size as usize, occupied_size);
         }
     }
 
     fn reset(&self) {
         let mut storage = self.storage.borrow_mut();
 
-        storage.current_front_ptr = storage.mem_begin;
+        storage.current_front_ptr = storage.region.base();
         storage.current_end_ptr = storage.mem_end;
+        storage.stats.on_reset();
         
-        #[cfg(stack_alloc_lifo_check)]
+        #[cfg(feature = "stack_alloc_lifo_check")]
         {
             storage.front_allocation_id = 0;
             storage.back_allocation_id = 0;
         }
+        #[cfg(feature = "epoch_check")]
+        {
+            storage.epoch = storage.epoch.wrapping_add(1);
+        }
     }
 
     fn get_allocation_size(&self, memory: &MemoryBlock) -> usize {
+        self.validate_block(memory);
+
         let alloc_header: &mut AllocationHeader;
 
         unsafe {
@@ -240,6 +377,63 @@ impl Allocator for DoubleEndedStackAllocator {
 
         alloc_header.allocation_size as usize
     }
+
+    ///
+    /// Grows or shrinks the topmost allocation of the front block in place. Other blocks are shrunk in place
+    /// as well, but cannot grow without breaking the LIFO order of the front block. None is returned
+    /// for them and the block stays valid
+    ///
+    fn realloc_raw(&self, memory: &MemoryBlock, new_size: usize, alignment: usize) -> Option<MemoryBlock> {
+        debug_assert!(pointer_util::is_pot(alignment), "Alignment needs to be a power of two");
+
+        let old_size = self.get_allocation_size(memory);
+
+        if pointer_util::is_aligned_to(memory.ptr, alignment) {
+            let mut storage = self.storage.borrow_mut();
+            let alloc_header = unsafe { &mut *(memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *mut AllocationHeader) };
+            let block_end = unsafe { memory.ptr.offset(old_size as isize) };
+            let new_block_end = unsafe { memory.ptr.offset(new_size as isize) };
+
+            let is_topmost_allocation = block_end == storage.current_front_ptr;
+            if is_topmost_allocation && new_block_end <= storage.current_end_ptr {
+                alloc_header.allocation_size = new_size as u32;
+                storage.current_front_ptr = new_block_end;
+                storage.stats.on_resize(old_size, old_size, new_size, new_size);
+                return Some(MemoryBlock::new(memory.ptr));
+            }
+
+            if new_size <= old_size {
+                alloc_header.allocation_size = new_size as u32;
+                storage.stats.on_resize(old_size, old_size, new_size, old_size);
+                return Some(MemoryBlock::new(memory.ptr));
+            }
+        }
+
+        None
+    }
+
+    #[cfg(feature = "epoch_check")]
+    fn validate_block(&self, memory: &MemoryBlock) {
+        let alloc_header = unsafe { &*(memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *const AllocationHeader) };
+        let block_is_current = alloc_header.epoch == self.storage.borrow().epoch;
+        assert!(block_is_current, "MemoryBlock was invalidated by a reset of its allocator");
+    }
+
+    ///
+    /// The stats cover the front and the back block together
+    ///
+    fn stats(&self) -> AllocatorStats {
+        let storage = self.storage.borrow();
+        storage.stats.snapshot(storage.mem_end as usize - storage.region.base() as usize, None)
+    }
+}
+
+impl OwningAllocator for DoubleEndedStackAllocator {
+    fn owns(&self, memory: &MemoryBlock) -> bool {
+        let storage = self.storage.borrow();
+        let ptr = memory.ptr as *const u8;
+        ptr >= storage.region.base() as *const u8 && ptr < storage.mem_end as *const u8
+    }
 }
 
 #[cfg(test)]
@@ -355,6 +549,18 @@ mod tests {
         assert!(marker == 0xDEADBEEF, "Previously placed marker was not there after deallocation");
     }
     
+    #[test]
+    fn dealloc_back_restores_previous_top() {
+        let de_stack_alloc = DoubleEndedStackAllocator::new(10 * MB);
+        de_stack_alloc.alloc_raw_back(MB, 16, 0).unwrap();
+        let mem_0 = de_stack_alloc.alloc_raw_back(MB, 16, 0).unwrap();
+        let mem_0_ptr = mem_0.ptr;
+        de_stack_alloc.dealloc_raw_back(mem_0);
+
+        let mem_1 = de_stack_alloc.alloc_raw_back(MB, 16, 0).unwrap();
+        assert_eq!(mem_0_ptr, mem_1.ptr, "Deallocation did not restore the previous top of the back block");
+    }
+
     #[test]
     #[should_panic(expected = "AllocatorMem was not allocated via `alloc` (front block)")]
     fn assert_wrong_front_deallocation() {
@@ -461,4 +667,178 @@ mod tests {
         assert_eq!(data_ref_1.pos, 202);
         assert_eq!(data_ref_1.vel, 222);
     }
+
+    #[cfg(feature = "stack_alloc_lifo_check")]
+    #[test]
+    #[should_panic(expected = "Double ended stack allocator does only support LIFO fashioned freeing")]
+    fn assert_out_of_order_front_deallocation() {
+        let de_stack_alloc = DoubleEndedStackAllocator::new(10 * MB);
+        let mem_0 = de_stack_alloc.alloc_raw(MB, 1, 0).unwrap();
+        let _mem_1 = de_stack_alloc.alloc_raw(MB, 1, 0).unwrap();
+        de_stack_alloc.dealloc_raw(mem_0);
+    }
+
+    #[cfg(feature = "stack_alloc_lifo_check")]
+    #[test]
+    #[should_panic(expected = "Double ended stack allocator does only support LIFO fashioned freeing")]
+    fn assert_out_of_order_back_deallocation() {
+        let de_stack_alloc = DoubleEndedStackAllocator::new(10 * MB);
+        let mem_0 = de_stack_alloc.alloc_raw_back(MB, 1, 0).unwrap();
+        let _mem_1 = de_stack_alloc.alloc_raw_back(MB, 1, 0).unwrap();
+        de_stack_alloc.dealloc_raw_back(mem_0);
+    }
+
+    #[cfg(feature = "stack_alloc_lifo_check")]
+    #[test]
+    fn lifo_deallocation_on_both_ends() {
+        let de_stack_alloc = DoubleEndedStackAllocator::new(10 * MB);
+        let front_0 = de_stack_alloc.alloc_raw(MB, 1, 0).unwrap();
+        let back_0 = de_stack_alloc.alloc_raw_back(MB, 1, 0).unwrap();
+        let front_1 = de_stack_alloc.alloc_raw(MB, 1, 0).unwrap();
+        let back_1 = de_stack_alloc.alloc_raw_back(MB, 1, 0).unwrap();
+
+        // Both ends keep their own LIFO order, interleaving them is fine
+        de_stack_alloc.dealloc_raw(front_1);
+        de_stack_alloc.dealloc_raw_back(back_1);
+        de_stack_alloc.dealloc_raw_back(back_0);
+        de_stack_alloc.dealloc_raw(front_0);
+
+        // A reset restarts the IDs of both ends
+        de_stack_alloc.alloc_raw(MB, 1, 0).unwrap();
+        de_stack_alloc.alloc_raw_back(MB, 1, 0).unwrap();
+        de_stack_alloc.reset();
+
+        let front_2 = de_stack_alloc.alloc_raw(MB, 1, 0).unwrap();
+        let back_2 = de_stack_alloc.alloc_raw_back(MB, 1, 0).unwrap();
+        de_stack_alloc.dealloc_raw_back(back_2);
+        de_stack_alloc.dealloc_raw(front_2);
+    }
+
+    #[test]
+    fn realloc_grows_topmost_front_in_place() {
+        let de_stack_alloc = DoubleEndedStackAllocator::new(KB);
+
+        let mem = de_stack_alloc.alloc_raw(64, 8, 0).unwrap();
+        let grown_mem = de_stack_alloc.realloc_raw(&mem, 256, 8).unwrap();
+        assert_eq!(grown_mem.ptr, mem.ptr, "Topmost allocation was moved");
+
+        de_stack_alloc.alloc_raw_back(512, 8, 0).unwrap();
+        assert!(de_stack_alloc.realloc_raw(&grown_mem, 512, 8).is_none(), "Front block grew into the back block");
+    }
+
+    #[test]
+    fn realloc_grows_topmost_back_in_place() {
+        let de_stack_alloc = DoubleEndedStackAllocator::new(KB);
+
+        let mem = de_stack_alloc.alloc_raw_back(64, 8, 0).unwrap();
+        for i in 0 .. 64 {
+            unsafe { *mem.ptr.offset(i) = i as u8 };
+        }
+
+        let grown_mem = de_stack_alloc.realloc_raw_back(&mem, 256, 8).unwrap();
+        assert!(grown_mem.ptr < mem.ptr, "Back block did not grow downwards");
+        assert_eq!(de_stack_alloc.get_allocation_size(&grown_mem), 256);
+        for i in 0 .. 64 {
+            assert_eq!(unsafe { *grown_mem.ptr.offset(i) }, i as u8, "Contents were not moved");
+        }
+
+        // The grown block still frees everything that was allocated in the back block
+        de_stack_alloc.dealloc_raw_back(grown_mem);
+        let front_mem = de_stack_alloc.alloc_raw(KB - 64, 1, 0);
+        assert!(front_mem.is_some());
+    }
+
+    #[test]
+    fn realloc_back_does_not_cross_front_block() {
+        let de_stack_alloc = DoubleEndedStackAllocator::new(KB);
+
+        let back_mem = de_stack_alloc.alloc_raw_back(64, 8, 0).unwrap();
+        let gap = {
+            let storage = de_stack_alloc.storage.borrow();
+            storage.current_end_ptr as usize - storage.current_front_ptr as usize
+        };
+
+        // Leave 16 bytes between the top of the front block and the back block
+        de_stack_alloc.alloc_raw(gap - ALLOCATION_META_SIZE - 16, 1, 0).unwrap();
+
+        assert!(de_stack_alloc.realloc_raw_back(&back_mem, 64 + 24, 8).is_none(), "Back block grew into the front block");
+        assert_eq!(de_stack_alloc.get_allocation_size(&back_mem), 64);
+
+        let grown_mem = de_stack_alloc.realloc_raw_back(&back_mem, 64 + 16, 8).unwrap();
+        assert_eq!(de_stack_alloc.get_allocation_size(&grown_mem), 64 + 16);
+
+        let storage = de_stack_alloc.storage.borrow();
+        assert!(storage.current_end_ptr >= storage.current_front_ptr, "Front and back block overlap");
+    }
+
+    #[test]
+    fn realloc_does_not_grow_buried_blocks() {
+        let de_stack_alloc = DoubleEndedStackAllocator::new(KB);
+
+        let buried_front = de_stack_alloc.alloc_raw(64, 8, 0).unwrap();
+        let top_front = de_stack_alloc.alloc_raw(64, 8, 0).unwrap();
+        let buried_back = de_stack_alloc.alloc_raw_back(64, 8, 0).unwrap();
+        let top_back = de_stack_alloc.alloc_raw_back(64, 8, 0).unwrap();
+        let used_bytes = de_stack_alloc.stats().used_bytes;
+
+        assert!(de_stack_alloc.realloc_raw(&buried_front, 128, 8).is_none(), "Buried front block was grown");
+        assert!(de_stack_alloc.realloc_raw_back(&buried_back, 128, 8).is_none(), "Buried back block was grown");
+        assert_eq!(de_stack_alloc.stats().used_bytes, used_bytes);
+
+        assert_eq!(de_stack_alloc.realloc_raw(&buried_front, 16, 8).unwrap().ptr, buried_front.ptr);
+        assert_eq!(de_stack_alloc.realloc_raw_back(&buried_back, 16, 8).unwrap().ptr, buried_back.ptr);
+
+        de_stack_alloc.dealloc_raw(top_front);
+        de_stack_alloc.dealloc_raw(buried_front);
+        de_stack_alloc.dealloc_raw_back(top_back);
+        de_stack_alloc.dealloc_raw_back(buried_back);
+        assert_eq!(de_stack_alloc.stats().live_allocations, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "stack_alloc_lifo_check")]
+    fn realloc_keeps_lifo_order() {
+        let de_stack_alloc = DoubleEndedStackAllocator::new(KB);
+
+        let buried_front = de_stack_alloc.alloc_raw(64, 8, 0).unwrap();
+        let top_front = de_stack_alloc.alloc_raw(64, 8, 0).unwrap();
+        let buried_back = de_stack_alloc.alloc_raw_back(64, 8, 0).unwrap();
+        let top_back = de_stack_alloc.alloc_raw_back(64, 8, 0).unwrap();
+
+        assert!(de_stack_alloc.realloc_raw(&buried_front, 128, 8).is_none());
+        assert!(de_stack_alloc.realloc_raw_back(&buried_back, 128, 8).is_none());
+        let top_front = de_stack_alloc.realloc_raw(&top_front, 128, 8).unwrap();
+        let top_back = de_stack_alloc.realloc_raw_back(&top_back, 128, 8).unwrap();
+
+        // Freeing in LIFO order asserts if the resized blocks got ids of their own
+        de_stack_alloc.dealloc_raw(top_front);
+        de_stack_alloc.dealloc_raw(buried_front);
+        de_stack_alloc.dealloc_raw_back(top_back);
+        de_stack_alloc.dealloc_raw_back(buried_back);
+    }
+
+    #[test]
+    fn stats_cover_both_blocks() {
+        let de_stack_alloc = DoubleEndedStackAllocator::new(KB);
+
+        let front_mem = de_stack_alloc.alloc_raw(64, 1, 0).unwrap();
+        let back_mem = de_stack_alloc.alloc_raw_back(128, 1, 0).unwrap();
+        assert!(de_stack_alloc.alloc_raw(KB, 1, 0).is_none());
+        assert!(de_stack_alloc.alloc_raw_back(KB, 1, 0).is_none());
+
+        let stats = de_stack_alloc.stats();
+        assert_eq!(stats.capacity, KB);
+        assert_eq!(stats.used_bytes, 192);
+        assert_eq!(stats.live_allocations, 2);
+        assert_eq!(stats.failed_requests, 2);
+
+        de_stack_alloc.dealloc_raw_back(back_mem);
+        assert_eq!(de_stack_alloc.stats().used_bytes, 64);
+        de_stack_alloc.dealloc_raw(front_mem);
+
+        let stats = de_stack_alloc.stats();
+        assert_eq!(stats.occupied_bytes(), 0);
+        assert_eq!(stats.live_allocations, 0);
+        assert!(stats.high_water_mark >= 192 + 2 * ALLOCATION_META_SIZE);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,190 @@
+use super::base::{ Allocator, MemoryBlock, OwningAllocator };
+use super::alloc_error::AllocError;
+use super::allocator_stats::AllocatorStats;
+
+///
+/// The Fallback combinator serves all requests from the primary allocator and only falls back
+/// to the secondary one when the primary runs out of memory, e.g. a fast StackAllocator backed
+/// by a FreeListAllocator or the system heap. Blocks are routed back to the allocator they came
+/// from by asking the primary whether it owns the block, so no meta-data is added to allocations.
+///
+pub struct Fallback<P: OwningAllocator, S: Allocator> {
+    primary:    P,
+    secondary:  S,
+}
+
+impl<P: OwningAllocator, S: Allocator> Fallback<P, S> {
+    pub fn new(primary: P, secondary: S) -> Fallback<P, S> {
+        Fallback {
+            primary,
+            secondary,
+        }
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &S {
+        &self.secondary
+    }
+}
+
+impl<P: OwningAllocator, S: Allocator> Allocator for Fallback<P, S> {
+    fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
+        match self.primary.alloc_raw(size, alignment, offset) {
+            Some(memory) => Some(memory),
+            None => self.secondary.alloc_raw(size, alignment, offset),
+        }
+    }
+
+    ///
+    /// Reports the error of the secondary allocator if neither allocator can serve the request
+    ///
+    fn try_alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Result<MemoryBlock, AllocError> {
+        match self.primary.try_alloc_raw(size, alignment, offset) {
+            Ok(memory) => Ok(memory),
+            Err(_) => self.secondary.try_alloc_raw(size, alignment, offset),
+        }
+    }
+
+    fn dealloc_raw(&self, memory: MemoryBlock) {
+        if self.primary.owns(&memory) {
+            self.primary.dealloc_raw(memory);
+        }
+        else {
+            self.secondary.dealloc_raw(memory);
+        }
+    }
+
+    fn reset(&self) {
+        self.primary.reset();
+        self.secondary.reset();
+    }
+
+    fn get_allocation_size(&self, memory: &MemoryBlock) -> usize {
+        if self.primary.owns(memory) {
+            self.primary.get_allocation_size(memory)
+        }
+        else {
+            self.secondary.get_allocation_size(memory)
+        }
+    }
+
+    fn validate_block(&self, memory: &MemoryBlock) {
+        if self.primary.owns(memory) {
+            self.primary.validate_block(memory);
+        }
+        else {
+            self.secondary.validate_block(memory);
+        }
+    }
+
+    ///
+    /// Sums up the stats of both allocators. Requests the primary cannot serve are not
+    /// counted as failed, only those the secondary cannot serve either
+    ///
+    fn stats(&self) -> AllocatorStats {
+        let primary_stats = AllocatorStats { failed_requests: 0, ..self.primary.stats() };
+        primary_stats.combine(&self.secondary.stats())
+    }
+}
+
+impl<P: OwningAllocator, S: OwningAllocator> OwningAllocator for Fallback<P, S> {
+    fn owns(&self, memory: &MemoryBlock) -> bool {
+        self.primary.owns(memory) || self.secondary.owns(memory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::base::{ BasicAllocator, TypedAllocator };
+    use super::super::pool_allocator::PoolAllocator;
+    use super::super::stack_allocator::StackAllocator;
+    use super::super::free_list_allocator::FreeListAllocator;
+    use super::super::system_allocator::SystemAllocator;
+
+    const KB: usize = 1024;
+
+    #[test]
+    fn falls_back_when_primary_is_exhausted() {
+        let fallback_alloc: Fallback<StackAllocator, FreeListAllocator> = Fallback::new(StackAllocator::new(KB), FreeListAllocator::new(16 * KB));
+
+        let stack_mem = fallback_alloc.alloc_raw(512, 8, 0).unwrap();
+        let heap_mem = fallback_alloc.alloc_raw(1024, 8, 0).unwrap();
+
+        assert!(fallback_alloc.primary().owns(&stack_mem));
+        assert!(fallback_alloc.secondary().owns(&heap_mem));
+        assert_eq!(fallback_alloc.get_allocation_size(&stack_mem), 512);
+        assert_eq!(fallback_alloc.get_allocation_size(&heap_mem), 1024);
+    }
+
+    #[test]
+    fn dealloc_routes_to_owner() {
+        let fallback_alloc = Fallback::new(StackAllocator::new(KB), SystemAllocator::new());
+
+        let stack_mem = fallback_alloc.alloc_raw(256, 8, 0).unwrap();
+        let system_mem = fallback_alloc.alloc_raw(2 * KB, 16, 0).unwrap();
+        assert_eq!(fallback_alloc.secondary().allocation_count(), 1);
+
+        fallback_alloc.dealloc_raw(system_mem);
+        assert_eq!(fallback_alloc.secondary().allocation_count(), 0);
+
+        let stack_ptr = stack_mem.ptr;
+        fallback_alloc.dealloc_raw(stack_mem);
+        let reused_mem = fallback_alloc.alloc_raw(256, 8, 0).unwrap();
+        assert_eq!(reused_mem.ptr, stack_ptr, "Block was not returned to the primary allocator");
+    }
+
+    #[test]
+    fn reset_resets_both_allocators() {
+        let fallback_alloc = Fallback::new(StackAllocator::new(KB), SystemAllocator::new());
+
+        let first_mem = fallback_alloc.alloc_raw(800, 8, 0).unwrap();
+        fallback_alloc.alloc_raw(800, 8, 0).unwrap();
+        fallback_alloc.reset();
+
+        assert_eq!(fallback_alloc.secondary().allocation_count(), 0);
+        assert_eq!(fallback_alloc.alloc_raw(800, 8, 0).unwrap().ptr, first_mem.ptr);
+    }
+
+    #[test]
+    fn reports_error_of_secondary() {
+        let fallback_alloc = Fallback::new(StackAllocator::new(KB), PoolAllocator::new(64, 8, 8, 0));
+
+        assert_eq!(
+            fallback_alloc.try_alloc_raw(2 * KB, 8, 0).unwrap_err(),
+            AllocError::ExceedsMaxElementSize { requested: 2 * KB, max_element_size: 64 }
+        );
+    }
+
+    #[test]
+    fn reports_available_memory_of_secondary() {
+        let fallback_alloc: Fallback<StackAllocator, FreeListAllocator> = Fallback::new(StackAllocator::new(KB), FreeListAllocator::new(16 * KB));
+        let expected_error = fallback_alloc.secondary.try_alloc_raw(32 * KB, 8, 0).unwrap_err();
+
+        match fallback_alloc.try_alloc_raw(32 * KB, 8, 0) {
+            Err(AllocError::OutOfMemory { requested, available }) => {
+                assert_eq!(requested, 32 * KB);
+                assert!(available > 0, "Secondary did not report its free memory");
+                assert_eq!(AllocError::OutOfMemory { requested, available }, expected_error);
+            },
+            _ => panic!("Expected OutOfMemory"),
+        }
+    }
+
+    #[test]
+    fn stats_sum_up_both_allocators() {
+        let fallback_alloc = Fallback::new(StackAllocator::new(KB), SystemAllocator::new());
+
+        fallback_alloc.alloc_raw(800, 8, 0).unwrap();
+        fallback_alloc.alloc_raw(800, 8, 0).unwrap();
+
+        let stats = fallback_alloc.stats();
+        assert_eq!(stats.used_bytes, 1600);
+        assert_eq!(stats.live_allocations, 2);
+        assert_eq!(stats.failed_requests, 0, "Falling back was counted as a failed request");
+        assert_eq!(fallback_alloc.primary().stats().failed_requests, 1);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,203 @@
+use std::cell::Cell;
+use std::mem::ManuallyDrop;
+use std::ops::{ Deref, DerefMut };
+
+use super::base::{ Allocator, AllocatorBox, MemoryBlock };
+use super::linear_allocator::LinearAllocator;
+
+///
+/// The FrameAllocator cycles through N LinearAllocators, one per frame. `flip` starts a new
+/// frame by resetting the oldest arena and making it the current one, which keeps the data
+/// allocated in frame K alive until frame K+N-1. A FrameAllocator<2> is double-buffered,
+/// e.g. to keep the data of the previous frame alive until the GPU consumed it.
+///
+/// Handles returned by `alloc` borrow the allocator, so they cannot outlive it and `reset`,
+/// which releases all frames at once, is rejected as long as a handle is alive. Using a
+/// handle after its window of N frames passed is caught at runtime in all builds.
+///
+pub struct FrameAllocator<const N: usize> {
+    arenas: Vec<LinearAllocator>,
+    frame:  Cell<usize>,
+}
+
+impl<const N: usize> FrameAllocator<N> {
+    ///
+    /// Creates a frame allocator whose arenas can hold `arena_size` bytes each
+    ///
+    pub fn new(arena_size: usize) -> FrameAllocator<N> {
+        debug_assert!(N > 0usize, "A frame allocator needs at least one arena");
+        debug_assert!(arena_size > 0usize, "Size is not allowed to be 0");
+
+        FrameAllocator {
+            arenas: (0 .. N).map(|_| LinearAllocator::new(arena_size)).collect(),
+            frame:  Cell::new(0),
+        }
+    }
+
+    ///
+    /// Returns the index of the current frame, starting at 0
+    ///
+    pub fn frame(&self) -> usize {
+        self.frame.get()
+    }
+
+    ///
+    /// Returns true if data allocated in `frame` is still alive
+    ///
+    pub fn is_frame_alive(&self, frame: usize) -> bool {
+        frame <= self.frame.get() && self.frame.get() - frame < N
+    }
+
+    ///
+    /// Returns the arena of the current frame
+    ///
+    pub fn current(&self) -> &LinearAllocator {
+        &self.arenas[self.frame.get() % N]
+    }
+
+    ///
+    /// Starts a new frame, releasing the data that was allocated N frames ago
+    ///
+    pub fn flip(&self) {
+        let frame = self.frame.get() + 1;
+        self.frame.set(frame);
+        self.arenas[frame % N].reset();
+    }
+
+    ///
+    /// Releases the data of all frames, the frame index keeps counting
+    ///
+    pub fn reset(&mut self) {
+        for arena in &self.arenas {
+            arena.reset();
+        }
+    }
+
+    pub fn alloc<T>(&self, value: T, alignment: usize) -> Option<FrameBox<T, N>> {
+        match self.current().alloc(value, alignment, 0) {
+            Some(instance) => Some(FrameBox {
+                instance:   ManuallyDrop::new(instance),
+                frame:      self.frame.get(),
+                allocator:  self,
+            }),
+            None => None,
+        }
+    }
+
+    pub fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
+        self.current().alloc_raw(size, alignment, offset)
+    }
+}
+
+///
+/// A FrameBox owns a value allocated from a FrameAllocator and remembers the frame it was
+/// allocated in. Accessing the value after its window of N frames passed asserts in all builds.
+/// Values whose frame was already released are not dropped, as their memory might already be
+/// used by a newer frame
+///
+pub struct FrameBox<'a, T: 'a, const N: usize> {
+    instance:   ManuallyDrop<AllocatorBox<'a, T, LinearAllocator>>,
+    frame:      usize,
+    allocator:  &'a FrameAllocator<N>,
+}
+
+impl<'a, T, const N: usize> FrameBox<'a, T, N> {
+    ///
+    /// Returns the frame the value was allocated in
+    ///
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    fn validate_frame(&self) {
+        let frame_is_alive = self.allocator.is_frame_alive(self.frame);
+        assert!(frame_is_alive, "FrameBox was used after its frame was released");
+    }
+}
+
+impl<'a, T, const N: usize> Deref for FrameBox<'a, T, N> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.validate_frame();
+        &self.instance
+    }
+}
+
+impl<'a, T, const N: usize> DerefMut for FrameBox<'a, T, N> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.validate_frame();
+        &mut self.instance
+    }
+}
+
+impl<'a, T, const N: usize> Drop for FrameBox<'a, T, N> {
+    fn drop(&mut self) {
+        if self.allocator.is_frame_alive(self.frame) {
+            unsafe { ManuallyDrop::drop(&mut self.instance) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KB: usize = 1024;
+
+    #[test]
+    fn data_survives_n_minus_one_flips() {
+        let frame_alloc: FrameAllocator<3> = FrameAllocator::new(4 * KB);
+
+        let value = frame_alloc.alloc(42u32, 4).unwrap();
+        frame_alloc.alloc(7u32, 4).unwrap();
+        frame_alloc.flip();
+        frame_alloc.alloc(0xDEADBEEFu32, 4).unwrap();
+        frame_alloc.flip();
+        frame_alloc.alloc(0xDEADBEEFu32, 4).unwrap();
+
+        assert_eq!(frame_alloc.frame(), 2);
+        assert_eq!(value.frame(), 0);
+        assert_eq!(*value, 42, "Data was overwritten within its window");
+    }
+
+    #[test]
+    fn flip_reuses_oldest_arena() {
+        let frame_alloc: FrameAllocator<2> = FrameAllocator::new(4 * KB);
+
+        let frame_0_ptr = frame_alloc.alloc_raw(64, 8, 0).unwrap().ptr;
+        frame_alloc.flip();
+        let frame_1_ptr = frame_alloc.alloc_raw(64, 8, 0).unwrap().ptr;
+        assert!(frame_0_ptr != frame_1_ptr, "Double-buffered frames share an arena");
+
+        frame_alloc.flip();
+        assert!(!frame_alloc.is_frame_alive(0));
+        assert!(frame_alloc.is_frame_alive(1));
+        assert_eq!(frame_alloc.alloc_raw(64, 8, 0).unwrap().ptr, frame_0_ptr, "Oldest arena was not reset");
+    }
+
+    #[test]
+    fn reset_releases_all_frames() {
+        let mut frame_alloc: FrameAllocator<2> = FrameAllocator::new(4 * KB);
+
+        let frame_0_ptr = frame_alloc.alloc_raw(4 * KB - 64, 8, 0).unwrap().ptr;
+        frame_alloc.flip();
+        frame_alloc.flip();
+        assert!(frame_alloc.alloc_raw(4 * KB - 64, 8, 0).is_some());
+
+        frame_alloc.reset();
+        assert_eq!(frame_alloc.alloc_raw(64, 8, 0).unwrap().ptr, frame_0_ptr);
+    }
+
+    #[test]
+    #[should_panic(expected = "FrameBox was used after its frame was released")]
+    fn assert_on_data_used_after_window() {
+        let frame_alloc: FrameAllocator<2> = FrameAllocator::new(4 * KB);
+
+        let value = frame_alloc.alloc(42u32, 4).unwrap();
+        frame_alloc.flip();
+        frame_alloc.flip();
+
+        let _stale_value = *value;
+    }
+}
\ No newline at end of file
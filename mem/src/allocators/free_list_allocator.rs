@@ -0,0 +1,563 @@
+use std;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use spark_core::{ pointer_util, math_util };
+
+use super::super::virtual_mem::VirtualRegion;
+use super::base::{ Allocator, MemoryBlock, BasicAllocator, OwningAllocator };
+use super::alloc_error::AllocError;
+use super::allocator_stats::{ AllocatorStats, StatsCounters };
+
+///
+/// A FreeBlock is placed at the beginning of every unused range of
+/// memory, the blocks form a singly linked list sorted by address
+///
+struct FreeBlock {
+    pub size: usize,
+    pub next: *mut FreeBlock,
+}
+
+///
+/// The AllocationHeader struct describes meta-data
+/// the allocator needs to store alongside of the
+/// allocations.
+///
+struct AllocationHeader {
+    pub allocation_size:    u32,
+    pub block_offset:       u32,
+    pub block_size:         u32,
+}
+
+const ALLOCATION_META_SIZE: usize = std::mem::size_of::<AllocationHeader>();
+const FREE_BLOCK_ALIGNMENT: usize = std::mem::align_of::<FreeBlock>();
+const MIN_BLOCK_SIZE: usize = std::mem::size_of::<FreeBlock>();
+
+///
+/// A FitPolicy decides which of the free blocks large enough for an
+/// allocation request is used to fulfill it
+///
+pub trait FitPolicy {
+    ///
+    /// Returns true if the search can stop at the first block that fits
+    ///
+    fn stop_at_first_fit() -> bool;
+
+    ///
+    /// Returns true if a block leaving `remaining_size` bytes unused is a better fit
+    /// than the best block found so far, which left `best_remaining_size` bytes unused
+    ///
+    fn is_better_fit(remaining_size: usize, best_remaining_size: usize) -> bool;
+}
+
+///
+/// Uses the first block that is large enough, which keeps allocations fast
+/// but tends to fragment the beginning of the memory
+///
+pub struct FirstFit;
+
+impl FitPolicy for FirstFit {
+    fn stop_at_first_fit() -> bool { true }
+    fn is_better_fit(_remaining_size: usize, _best_remaining_size: usize) -> bool { false }
+}
+
+///
+/// Searches all free blocks for the one leaving the least memory unused,
+/// which keeps fragmentation low at the cost of slower allocations
+///
+pub struct BestFit;
+
+impl FitPolicy for BestFit {
+    fn stop_at_first_fit() -> bool { false }
+    fn is_better_fit(remaining_size: usize, best_remaining_size: usize) -> bool { remaining_size < best_remaining_size }
+}
+
+///
+/// The FreeListAllocatorStorage is a type that is used to
+/// expose a safe API for user allocations where the Allocator
+/// itself is just mutating the Storage backing it
+///
+struct FreeListAllocatorStorage {
+    pub use_internal_mem:   bool,
+    pub region:             VirtualRegion,
+    pub mem_end:            *mut u8,
+    pub free_list:          *mut FreeBlock,
+    pub stats:              StatsCounters,
+}
+
+impl FreeListAllocatorStorage {
+    ///
+    /// Creates a new free list allocator storage and allocates the memory
+    /// block requested by the allocator from the virtual memory API
+    ///
+    fn new(size: usize) -> Result<FreeListAllocatorStorage, AllocError> {
+
+        let mut region = VirtualRegion::reserve(size).ok_or(AllocError::ReserveFailed { size })?;
+        let physical_address_space = region.commit(0, size).ok_or(AllocError::CommitFailed { size })?;
+
+        // Block sizes are kept at a multiple of the FreeBlock alignment, so that
+        // every block split off of another one can hold a properly aligned FreeBlock
+        let usable_size = math_util::round_to_previous_multiple(size, FREE_BLOCK_ALIGNMENT);
+
+        let mut storage = FreeListAllocatorStorage {
+            use_internal_mem:   true,
+            region,
+            mem_end:            unsafe { physical_address_space.offset(usable_size as isize) },
+            free_list:          std::ptr::null_mut(),
+            stats:              StatsCounters::default(),
+        };
+
+        storage.reset_free_list();
+        Ok(storage)
+    }
+
+    ///
+    /// Turns the whole memory of the allocator into one single free block
+    ///
+    fn reset_free_list(&mut self) {
+        let mem_begin = self.region.base();
+        let usable_size = self.mem_end as usize - mem_begin as usize;
+
+        if usable_size < MIN_BLOCK_SIZE {
+            self.free_list = std::ptr::null_mut();
+            return;
+        }
+
+        unsafe {
+            let block = mem_begin as *mut FreeBlock;
+            (*block).size = usable_size;
+            (*block).next = std::ptr::null_mut();
+            self.free_list = block;
+        }
+    }
+
+    ///
+    /// Returns the size of the largest free block, or 0 if there is none
+    ///
+    fn largest_free_block(&self) -> usize {
+        let mut largest_size = 0;
+        let mut current = self.free_list;
+
+        unsafe {
+            while !current.is_null() {
+                largest_size = std::cmp::max(largest_size, (*current).size);
+                current = (*current).next;
+            }
+        }
+
+        largest_size
+    }
+
+    ///
+    /// Inserts the block into the address sorted free list and merges it
+    /// with its direct neighbours if they are free as well
+    ///
+    unsafe fn insert_free_block(&mut self, block_ptr: *mut u8, block_size: usize) {
+        let block = block_ptr as *mut FreeBlock;
+        (*block).size = block_size;
+
+        let mut previous: *mut FreeBlock = std::ptr::null_mut();
+        let mut current = self.free_list;
+
+        while !current.is_null() && current < block {
+            previous = current;
+            current = (*current).next;
+        }
+
+        {
+            let block_not_freed_twice = current != block && (previous.is_null() || (previous as *mut u8).offset((*previous).size as isize) <= block_ptr);
+            debug_assert!(block_not_freed_twice, "MemoryBlock was already freed");
+        }
+
+        (*block).next = current;
+
+        if previous.is_null() {
+            self.free_list = block;
+        }
+        else {
+            (*previous).next = block;
+        }
+
+        // Coalesce with the following block first, the preceding block might absorb both afterwards
+        let touches_next_block = !current.is_null() && block_ptr.offset((*block).size as isize) == current as *mut u8;
+        if touches_next_block {
+            (*block).size += (*current).size;
+            (*block).next = (*current).next;
+        }
+
+        let touches_previous_block = !previous.is_null() && (previous as *mut u8).offset((*previous).size as isize) == block_ptr;
+        if touches_previous_block {
+            (*previous).size += (*block).size;
+            (*previous).next = (*block).next;
+        }
+    }
+}
+
+///
+/// Returns the user pointer an allocation would get in `block` and the size of the block
+/// that is required to place it there, including padding and the allocation header
+///
+unsafe fn fit_allocation(block: *mut FreeBlock, size: usize, alignment: usize, offset: usize) -> (*mut u8, usize) {
+    let block_begin = block as *mut u8;
+    let offset_before_alignment = (offset + ALLOCATION_META_SIZE) as isize;
+
+    let aligned_ptr = pointer_util::align_top(block_begin.offset(offset_before_alignment), alignment) as *mut u8;
+    let user_ptr = aligned_ptr.offset(-(offset as isize));
+
+    let used_size = user_ptr as usize + size - block_begin as usize;
+    let required_size = math_util::round_to_next_multiple(used_size, FREE_BLOCK_ALIGNMENT);
+
+    (user_ptr, if required_size < MIN_BLOCK_SIZE { MIN_BLOCK_SIZE } else { required_size })
+}
+
+///
+/// The FreeListAllocator is a general purpose allocator, allocations of arbitrary size
+/// can be freed in arbitrary order. Unused memory is tracked in a list of free blocks
+/// sorted by address. Allocations split the block chosen by the FitPolicy `P` and freed
+/// blocks get merged with adjacent free blocks to fight fragmentation.
+///
+pub struct FreeListAllocator<P: FitPolicy = FirstFit> {
+    storage: RefCell<FreeListAllocatorStorage>,
+    _policy: PhantomData<P>,
+}
+
+// The allocator exclusively owns its memory and the free blocks pointing into it,
+// so it can be moved to another thread as a whole
+unsafe impl<P: FitPolicy> Send for FreeListAllocator<P> {}
+
+impl<P: FitPolicy> BasicAllocator for FreeListAllocator<P> {
+    type AllocatorImplementation = FreeListAllocator<P>;
+
+    fn try_new(size: usize) -> Result<Self::AllocatorImplementation, AllocError> {
+        debug_assert!(size > 0usize, "Size is not allowed to be 0");
+
+        Ok(FreeListAllocator {
+            storage: RefCell::new(FreeListAllocatorStorage::new(size)?),
+            _policy: PhantomData,
+        })
+    }
+}
+
+impl<P: FitPolicy> Allocator for FreeListAllocator<P> {
+    fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
+        self.try_alloc_raw(size, alignment, offset).ok()
+    }
+
+    ///
+    /// A failed request reports the largest free block minus the allocation header as
+    /// available, which is the biggest request without alignment padding that still fits
+    ///
+    fn try_alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Result<MemoryBlock, AllocError> {
+        let mut storage = self.storage.borrow_mut();
+
+        if !pointer_util::is_pot(alignment) {
+            storage.stats.on_failed_request();
+            return Err(AllocError::InvalidAlignment { alignment });
+        }
+
+        unsafe {
+            let mut best_fit: Option<(*mut FreeBlock, *mut FreeBlock, *mut u8, usize)> = None;
+            let mut previous: *mut FreeBlock = std::ptr::null_mut();
+            let mut current = storage.free_list;
+
+            while !current.is_null() {
+                let (user_ptr, required_size) = fit_allocation(current, size, alignment, offset);
+
+                if required_size <= (*current).size {
+                    let remaining_size = (*current).size - required_size;
+                    let is_better_fit = match best_fit {
+                        Some((best_block, _, _, best_required_size)) => P::is_better_fit(remaining_size, (*best_block).size - best_required_size),
+                        None => true,
+                    };
+
+                    if is_better_fit {
+                        best_fit = Some((current, previous, user_ptr, required_size));
+                    }
+
+                    if P::stop_at_first_fit() || remaining_size == 0 {
+                        break;
+                    }
+                }
+
+                previous = current;
+                current = (*current).next;
+            }
+
+            let (block, previous, user_ptr, mut required_size) = match best_fit {
+                Some(fit) => fit,
+                None => {
+                    storage.stats.on_failed_request();
+                    return Err(AllocError::OutOfMemory {
+                        requested: size,
+                        available: storage.largest_free_block().saturating_sub(ALLOCATION_META_SIZE),
+                    });
+                },
+            };
+
+            // Split the block if the rest can still hold a free block, otherwise the
+            // allocation takes the whole block to not lose track of the remainder
+            let remaining_size = (*block).size - required_size;
+            let next_free_block = if remaining_size >= MIN_BLOCK_SIZE {
+                let split_block = (block as *mut u8).offset(required_size as isize) as *mut FreeBlock;
+                (*split_block).size = remaining_size;
+                (*split_block).next = (*block).next;
+                split_block
+            }
+            else {
+                required_size = (*block).size;
+                (*block).next
+            };
+
+            if previous.is_null() {
+                storage.free_list = next_free_block;
+            }
+            else {
+                (*previous).next = next_free_block;
+            }
+
+            let as_alloc_header = &mut *(user_ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *mut AllocationHeader);
+            as_alloc_header.allocation_size = size as u32;
+            as_alloc_header.block_offset = (user_ptr as usize - block as usize) as u32;
+            as_alloc_header.block_size = required_size as u32;
+            storage.stats.on_alloc(size, required_size);
+
+            Ok(MemoryBlock::new(user_ptr))
+        }
+    }
+
+    fn dealloc_raw(&self, memory: MemoryBlock) {
+        let raw_mem = memory.ptr;
+
+        unsafe {
+            let mut storage = self.storage.borrow_mut();
+
+            {
+                let ptr_in_range = raw_mem >= storage.region.base() && raw_mem < storage.mem_end;
+                debug_assert!(ptr_in_range, "AllocatorMem was not allocated by this allocator");
+            }
+
+            let alloc_header = &*(raw_mem.offset(-(ALLOCATION_META_SIZE as isize)) as *const AllocationHeader);
+            let block_ptr = raw_mem.offset(-(alloc_header.block_offset as isize));
+            let block_size = alloc_header.block_size as usize;
+
+            storage.stats.on_dealloc(alloc_header.allocation_size as usize, block_size);
+            storage.insert_free_block(block_ptr, block_size);
+        }
+    }
+
+    fn reset(&self) {
+        let mut storage = self.storage.borrow_mut();
+        storage.reset_free_list();
+        storage.stats.on_reset();
+    }
+
+    fn get_allocation_size(&self, memory: &MemoryBlock) -> usize {
+        let alloc_header: &AllocationHeader;
+
+        unsafe {
+            alloc_header = &*(memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *const AllocationHeader);
+        }
+
+        alloc_header.allocation_size as usize
+    }
+
+    ///
+    /// The overhead covers the headers, the alignment padding and the rest of
+    /// blocks that were too small to be split off as a free block of their own
+    ///
+    fn stats(&self) -> AllocatorStats {
+        let storage = self.storage.borrow();
+        storage.stats.snapshot(storage.mem_end as usize - storage.region.base() as usize, None)
+    }
+}
+
+impl<P: FitPolicy> OwningAllocator for FreeListAllocator<P> {
+    fn owns(&self, memory: &MemoryBlock) -> bool {
+        let storage = self.storage.borrow();
+        let ptr = memory.ptr as *const u8;
+        ptr >= storage.region.base() as *const u8 && ptr < storage.mem_end as *const u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KB: usize = 1024;
+    const MB: usize = KB * 1024;
+
+    fn free_block_sizes<P: FitPolicy>(allocator: &FreeListAllocator<P>) -> Vec<usize> {
+        let storage = allocator.storage.borrow();
+        let mut sizes = Vec::new();
+        let mut current = storage.free_list;
+
+        while !current.is_null() {
+            unsafe {
+                sizes.push((*current).size);
+                current = (*current).next;
+            }
+        }
+
+        sizes
+    }
+
+    #[test]
+    fn single_allocation() {
+        let free_list_alloc: FreeListAllocator = FreeListAllocator::new(10 * MB);
+        let mem = free_list_alloc.alloc_raw(MB, 1, 0);
+        assert!(mem.is_some());
+    }
+
+    #[test]
+    fn single_allocation_aligned_with_offset() {
+        let free_list_alloc: FreeListAllocator = FreeListAllocator::new(10 * MB);
+        let mem = free_list_alloc.alloc_raw(MB + 8, 16, 4).unwrap();
+
+        let offsetted_ptr = unsafe { mem.ptr.offset(4) };
+        assert!(pointer_util::is_aligned_to(offsetted_ptr, 16), "User pointer was not properly aligned");
+    }
+
+    #[test]
+    fn return_right_allocation_size() {
+        let free_list_alloc: FreeListAllocator = FreeListAllocator::new(10 * MB);
+        let mem_0 = free_list_alloc.alloc_raw(MB * 2, 1, 0).unwrap();
+        let mem_1 = free_list_alloc.alloc_raw(333, 8, 0).unwrap();
+
+        assert_eq!(free_list_alloc.get_allocation_size(&mem_0), MB * 2);
+        assert_eq!(free_list_alloc.get_allocation_size(&mem_1), 333);
+    }
+
+    #[test]
+    fn returns_none_when_out_of_memory() {
+        let free_list_alloc: FreeListAllocator = FreeListAllocator::new(KB);
+        assert!(free_list_alloc.alloc_raw(KB, 1, 0).is_none());
+
+        let mem = free_list_alloc.alloc_raw(KB / 2, 1, 0).unwrap();
+        assert!(free_list_alloc.alloc_raw(KB / 2, 1, 0).is_none());
+
+        free_list_alloc.dealloc_raw(mem);
+        assert!(free_list_alloc.alloc_raw(KB / 2, 1, 0).is_some(), "Freed memory was not reused");
+    }
+
+    #[test]
+    fn failed_request_reports_largest_free_block() {
+        let free_list_alloc: FreeListAllocator = FreeListAllocator::new(KB);
+        let _mem_0 = free_list_alloc.alloc_raw(100, 8, 0).unwrap();
+        let mem_1 = free_list_alloc.alloc_raw(200, 8, 0).unwrap();
+        let _mem_2 = free_list_alloc.alloc_raw(300, 8, 0).unwrap();
+        free_list_alloc.dealloc_raw(mem_1);
+
+        let largest_free_block = *free_block_sizes(&free_list_alloc).iter().max().unwrap();
+        let available = match free_list_alloc.try_alloc_raw(KB, 8, 0) {
+            Err(AllocError::OutOfMemory { available, .. }) => available,
+            _ => panic!("Expected OutOfMemory"),
+        };
+
+        assert_eq!(available, largest_free_block - ALLOCATION_META_SIZE);
+        assert!(free_list_alloc.alloc_raw(available + 1, 1, 0).is_none());
+        assert!(free_list_alloc.alloc_raw(available, 1, 0).is_some(), "Reported memory is not available");
+        assert_eq!(free_list_alloc.try_alloc_raw(8, 3, 0).unwrap_err(), AllocError::InvalidAlignment { alignment: 3 });
+    }
+
+    #[test]
+    fn free_in_arbitrary_order_coalesces() {
+        let free_list_alloc: FreeListAllocator = FreeListAllocator::new(10 * KB);
+        let initial_free_blocks = free_block_sizes(&free_list_alloc);
+
+        let mem_0 = free_list_alloc.alloc_raw(100, 8, 0).unwrap();
+        let mem_1 = free_list_alloc.alloc_raw(200, 8, 0).unwrap();
+        let mem_2 = free_list_alloc.alloc_raw(300, 8, 0).unwrap();
+        let mem_3 = free_list_alloc.alloc_raw(400, 8, 0).unwrap();
+
+        free_list_alloc.dealloc_raw(mem_1);
+        free_list_alloc.dealloc_raw(mem_3);
+        assert_eq!(free_block_sizes(&free_list_alloc).len(), 2, "Freed block was not merged with the trailing free block");
+
+        free_list_alloc.dealloc_raw(mem_0);
+        free_list_alloc.dealloc_raw(mem_2);
+        assert_eq!(free_block_sizes(&free_list_alloc), initial_free_blocks, "Freed blocks were not coalesced");
+    }
+
+    #[test]
+    fn reuses_freed_block_after_split() {
+        let free_list_alloc: FreeListAllocator = FreeListAllocator::new(10 * KB);
+        let mem_0 = free_list_alloc.alloc_raw(KB, 8, 0).unwrap();
+        let _mem_1 = free_list_alloc.alloc_raw(KB, 8, 0).unwrap();
+        let mem_0_ptr = mem_0.ptr;
+
+        free_list_alloc.dealloc_raw(mem_0);
+        let mem_2 = free_list_alloc.alloc_raw(KB / 2, 8, 0).unwrap();
+        let mem_3 = free_list_alloc.alloc_raw(KB / 4, 8, 0).unwrap();
+
+        assert_eq!(mem_2.ptr, mem_0_ptr, "First fit did not reuse the freed block");
+        assert!(mem_3.ptr > mem_2.ptr && (mem_3.ptr as usize) < mem_0_ptr as usize + KB, "Remainder of the split block was not reused");
+    }
+
+    #[test]
+    fn best_fit_picks_smallest_block() {
+        let free_list_alloc: FreeListAllocator<BestFit> = FreeListAllocator::new(10 * KB);
+        let large_hole = free_list_alloc.alloc_raw(2 * KB, 8, 0).unwrap();
+        let _separator_0 = free_list_alloc.alloc_raw(16, 8, 0).unwrap();
+        let small_hole = free_list_alloc.alloc_raw(KB, 8, 0).unwrap();
+        let _separator_1 = free_list_alloc.alloc_raw(16, 8, 0).unwrap();
+        let small_hole_ptr = small_hole.ptr;
+
+        free_list_alloc.dealloc_raw(large_hole);
+        free_list_alloc.dealloc_raw(small_hole);
+
+        let mem = free_list_alloc.alloc_raw(KB - 64, 8, 0).unwrap();
+        assert_eq!(mem.ptr, small_hole_ptr, "Best fit did not pick the smallest fitting block");
+    }
+
+    #[test]
+    fn first_fit_picks_first_block() {
+        let free_list_alloc: FreeListAllocator<FirstFit> = FreeListAllocator::new(10 * KB);
+        let large_hole = free_list_alloc.alloc_raw(2 * KB, 8, 0).unwrap();
+        let _separator_0 = free_list_alloc.alloc_raw(16, 8, 0).unwrap();
+        let small_hole = free_list_alloc.alloc_raw(KB, 8, 0).unwrap();
+        let _separator_1 = free_list_alloc.alloc_raw(16, 8, 0).unwrap();
+        let large_hole_ptr = large_hole.ptr;
+
+        free_list_alloc.dealloc_raw(large_hole);
+        free_list_alloc.dealloc_raw(small_hole);
+
+        let mem = free_list_alloc.alloc_raw(KB - 64, 8, 0).unwrap();
+        assert_eq!(mem.ptr, large_hole_ptr, "First fit did not pick the first fitting block");
+    }
+
+    #[test]
+    fn reset_whole_allocator() {
+        let free_list_alloc: FreeListAllocator = FreeListAllocator::new(10 * KB);
+        let initial_free_blocks = free_block_sizes(&free_list_alloc);
+
+        let mem_0 = free_list_alloc.alloc_raw(KB, 8, 0).unwrap();
+        free_list_alloc.alloc_raw(KB, 8, 0).unwrap();
+        let mem_0_ptr = mem_0.ptr;
+        free_list_alloc.reset();
+
+        assert_eq!(free_block_sizes(&free_list_alloc), initial_free_blocks);
+        assert_eq!(free_list_alloc.alloc_raw(KB, 8, 0).unwrap().ptr, mem_0_ptr);
+    }
+
+    #[test]
+    fn allocate_safely() {
+        struct Data {
+            pub result: f32,
+            pub id: usize,
+        }
+
+        let free_list_alloc: FreeListAllocator = FreeListAllocator::new(KB);
+
+        {
+            let data_box_0 = free_list_alloc.alloc(Data { result: 1.0, id: 1 }, 8, 0).unwrap();
+            let mut data_box_1 = free_list_alloc.alloc(Data { result: 2.0, id: 2 }, 8, 0).unwrap();
+            data_box_1.id = 3;
+
+            assert_eq!(data_box_0.result, 1.0);
+            assert_eq!(data_box_0.id, 1);
+            assert_eq!(data_box_1.result, 2.0);
+            assert_eq!(data_box_1.id, 3);
+        }
+
+        assert_eq!(free_block_sizes(&free_list_alloc).len(), 1, "AllocatorBoxes did not return their blocks");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,247 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use spark_core::{ pointer_util, math_util };
+
+use super::super::virtual_mem::{ self, VirtualRegion };
+use super::base::{ Allocator, MemoryBlock, BasicAllocator, OwningAllocator };
+use super::alloc_error::AllocError;
+use super::allocator_stats::{ AllocatorStats, StatsCounters };
+
+///
+/// A GuardedAllocation keeps the pages backing a single allocation
+/// alive and remembers the size requested by the user
+///
+struct GuardedAllocation {
+    pub _region:            VirtualRegion,
+    pub allocation_size:    usize,
+    pub data_size:          usize,
+}
+
+///
+/// The GuardPageAllocatorStorage is a type that is used to
+/// expose a safe API for user allocations where the Allocator
+/// itself is just mutating the Storage backing it
+///
+struct GuardPageAllocatorStorage {
+    pub capacity:           usize,
+    pub allocations:        HashMap<usize, GuardedAllocation>,
+    pub stats:              StatsCounters,
+}
+
+///
+/// The GuardPageAllocator is a debugging allocator that backs every allocation with
+/// pages of its own and places it flush against a trailing no-access guard page. An
+/// overrun therefore faults right at the offending write, instead of being detected
+/// by a canary when the memory is freed. Allocations with an alignment bigger than
+/// one byte can leave a gap of less than `alignment` bytes in front of the guard page.
+/// As every allocation costs at least one committed page and one guard page, this
+/// allocator is meant to hunt down memory stomps and not for production use.
+///
+pub struct GuardPageAllocator {
+    storage: RefCell<GuardPageAllocatorStorage>,
+}
+
+impl BasicAllocator for GuardPageAllocator {
+    type AllocatorImplementation = GuardPageAllocator;
+
+    ///
+    /// `size` limits the amount of bytes that can be allocated at the same time,
+    /// the pages needed to back and guard the allocations are not accounted for
+    ///
+    fn try_new(size: usize) -> Result<Self::AllocatorImplementation, AllocError> {
+        debug_assert!(size > 0usize, "Size is not allowed to be 0");
+
+        Ok(GuardPageAllocator {
+            storage: RefCell::new(GuardPageAllocatorStorage {
+                capacity:       size,
+                allocations:    HashMap::new(),
+                stats:          StatsCounters::default(),
+            }),
+        })
+    }
+}
+
+impl Allocator for GuardPageAllocator {
+    fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
+        debug_assert!(pointer_util::is_pot(alignment), "Alignment needs to be a power of two");
+
+        let mut storage = self.storage.borrow_mut();
+
+        let allocation_overflows = storage.stats.used_bytes + size > storage.capacity;
+        if allocation_overflows {
+            storage.stats.on_failed_request();
+            return None;
+        }
+
+        // The trailing guard page is only reserved and never committed, which
+        // makes it inaccessible without an additional protection change
+        let page_size = virtual_mem::get_page_size();
+        let data_size = math_util::round_to_next_multiple(size + alignment, page_size);
+        let region = VirtualRegion::reserve(data_size + page_size)
+            .and_then(|mut region| region.commit(0, data_size).map(|_| region));
+
+        let region = match region {
+            Some(region) => region,
+            None => {
+                storage.stats.on_failed_request();
+                return None;
+            },
+        };
+
+        let user_ptr = unsafe {
+            let guard_page = region.base().offset(data_size as isize);
+            let aligned_ptr = pointer_util::align_bottom(guard_page.offset(offset as isize - size as isize), alignment) as *mut u8;
+            aligned_ptr.offset(-(offset as isize))
+        };
+
+        storage.stats.on_alloc(size, data_size);
+        storage.allocations.insert(user_ptr as usize, GuardedAllocation {
+            _region:         region,
+            allocation_size: size,
+            data_size,
+        });
+
+        Some(MemoryBlock::new(user_ptr))
+    }
+
+    ///
+    /// Hands the pages of the allocation back to the OS, accessing
+    /// the memory afterwards faults as well
+    ///
+    fn dealloc_raw(&self, memory: MemoryBlock) {
+        let mut storage = self.storage.borrow_mut();
+
+        match storage.allocations.remove(&(memory.ptr as usize)) {
+            Some(allocation) => storage.stats.on_dealloc(allocation.allocation_size, allocation.data_size),
+            None => debug_assert!(false, "MemoryBlock was not allocated by this allocator"),
+        }
+    }
+
+    fn reset(&self) {
+        let mut storage = self.storage.borrow_mut();
+        storage.allocations.clear();
+        storage.stats.on_reset();
+    }
+
+    fn get_allocation_size(&self, memory: &MemoryBlock) -> usize {
+        let storage = self.storage.borrow();
+
+        match storage.allocations.get(&(memory.ptr as usize)) {
+            Some(allocation) => allocation.allocation_size,
+            None => {
+                debug_assert!(false, "MemoryBlock was not allocated by this allocator");
+                0
+            },
+        }
+    }
+
+    ///
+    /// The overhead covers the committed pages not used by the allocations, the guard
+    /// pages are only reserved and do not count. As `size` only limits the bytes
+    /// requested by the user, the overhead can exceed the capacity
+    ///
+    fn stats(&self) -> AllocatorStats {
+        let storage = self.storage.borrow();
+        storage.stats.snapshot(storage.capacity, None)
+    }
+}
+
+impl OwningAllocator for GuardPageAllocator {
+    fn owns(&self, memory: &MemoryBlock) -> bool {
+        self.storage.borrow().allocations.contains_key(&(memory.ptr as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+    use super::*;
+
+    #[test]
+    fn single_allocation() {
+        let guard_alloc = GuardPageAllocator::new(1024);
+        let mem = guard_alloc.alloc_raw(100, 1, 0);
+        assert!(mem.is_some());
+    }
+
+    #[test]
+    fn allocation_is_flush_against_guard_page() {
+        let guard_alloc = GuardPageAllocator::new(1024);
+        let mem = guard_alloc.alloc_raw(100, 1, 0).unwrap();
+
+        let allocation_end = unsafe { mem.ptr.offset(100) };
+        assert!(pointer_util::is_aligned_to(allocation_end, virtual_mem::get_page_size()), "Allocation did not end at a page boundary");
+    }
+
+    #[test]
+    fn single_allocation_aligned_with_offset() {
+        let guard_alloc = GuardPageAllocator::new(1024);
+        let mem = guard_alloc.alloc_raw(100, 16, 4).unwrap();
+
+        let offsetted_ptr = unsafe { mem.ptr.offset(4) };
+        assert!(pointer_util::is_aligned_to(offsetted_ptr, 16), "User pointer was not properly aligned");
+
+        let allocation_end = unsafe { mem.ptr.offset(100) } as usize;
+        let guard_page = math_util::round_to_next_multiple(allocation_end, virtual_mem::get_page_size());
+        assert!(guard_page - allocation_end < 16, "Aligned allocation was not placed as close as possible to the guard page");
+    }
+
+    #[test]
+    fn return_right_allocation_size() {
+        let guard_alloc = GuardPageAllocator::new(10 * 1024);
+        let mem_0 = guard_alloc.alloc_raw(100, 1, 0).unwrap();
+        let mem_1 = guard_alloc.alloc_raw(5000, 8, 0).unwrap();
+
+        assert_eq!(guard_alloc.get_allocation_size(&mem_0), 100);
+        assert_eq!(guard_alloc.get_allocation_size(&mem_1), 5000);
+    }
+
+    #[test]
+    fn returns_none_when_budget_is_exhausted() {
+        let guard_alloc = GuardPageAllocator::new(1024);
+        let mem_0 = guard_alloc.alloc_raw(1000, 1, 0).unwrap();
+        assert!(guard_alloc.alloc_raw(100, 1, 0).is_none());
+
+        guard_alloc.dealloc_raw(mem_0);
+        assert!(guard_alloc.alloc_raw(100, 1, 0).is_some(), "Deallocation did not return the budget to the allocator");
+    }
+
+    #[test]
+    fn allocations_are_usable() {
+        let guard_alloc = GuardPageAllocator::new(1024);
+        let mem = guard_alloc.alloc_raw(std::mem::size_of::<u64>() * 4, 8, 0).unwrap();
+
+        let data = unsafe { std::slice::from_raw_parts_mut(mem.ptr as *mut u64, 4) };
+        for (idx, value) in data.iter_mut().enumerate() {
+            *value = idx as u64;
+        }
+
+        assert_eq!(data, &[0, 1, 2, 3]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn overrun_faults_at_offending_write() {
+        extern crate libc;
+
+        let guard_alloc = GuardPageAllocator::new(1024);
+        let mem = guard_alloc.alloc_raw(100, 1, 0).unwrap();
+
+        unsafe {
+            let pid = libc::fork();
+            assert!(pid >= 0, "Could not fork the test process");
+
+            if pid == 0 {
+                // The last byte of the allocation is fine, the first one past it has to fault
+                std::ptr::write_volatile(mem.ptr.offset(99), 0xCC);
+                std::ptr::write_volatile(mem.ptr.offset(100), 0xCC);
+                libc::_exit(0);
+            }
+
+            let mut status = 0;
+            libc::waitpid(pid, &mut status, 0);
+            assert!(libc::WIFSIGNALED(status), "Writing past the allocation did not fault");
+            assert_eq!(libc::WTERMSIG(status), libc::SIGSEGV);
+        }
+    }
+}
\ No newline at end of file
@@ -1,9 +1,11 @@
 use std;
 use std::cell::RefCell;
 
-use spark_core::pointer_util;
-use super::super::virtual_mem;
-use super::base::{ Allocator, MemoryBlock, BasicAllocator };
+use spark_core::{ pointer_util, math_util };
+use super::super::virtual_mem::{ VirtualRegion, PageMode };
+use super::base::{ Allocator, MemoryBlock, BasicAllocator, Marker, MarkerAllocator, OwningAllocator };
+use super::alloc_error::AllocError;
+use super::allocator_stats::{ AllocatorStats, StatsCounters };
 
 
 ///
@@ -13,9 +15,15 @@ use super::base::{ Allocator, MemoryBlock, BasicAllocator };
 ///
 struct LinearAllocatorStorage {
     pub use_internal_mem:   bool,
-    pub mem_begin:          *mut u8,
+    pub region:             VirtualRegion,
     pub mem_end:            *mut u8,
+    pub committed_end:      *mut u8,
     pub current_ptr:        *mut u8,
+    pub low_water_mark:     Option<usize>,
+    pub scope_depth:        usize,
+    pub stats:              StatsCounters,
+    #[cfg(feature = "epoch_check")]
+    pub epoch:              u32,
 }
 
 ///
@@ -25,6 +33,8 @@ struct LinearAllocatorStorage {
 ///
 struct AllocationHeader {
     pub allocation_size: u32,
+    #[cfg(feature = "epoch_check")]
+    pub epoch:           u32,
 }
 
 const ALLOCATION_META_SIZE: usize = std::mem::size_of::<AllocationHeader>();
@@ -34,24 +44,70 @@ impl LinearAllocatorStorage {
     /// Creates a new linear allocator storage and allocates the memory
     /// block requested by the allocator from the virtual memory API
     ///
-    fn new(size: usize) -> LinearAllocatorStorage {
+    fn new(size: usize, page_mode: PageMode) -> Result<LinearAllocatorStorage, AllocError> {
 
-        let virtual_mem = match virtual_mem::reserve_address_space(size) {
-            Some(address) => address,
-            None => std::ptr::null_mut(),
-        };
+        let mut region = VirtualRegion::reserve_with_page_mode(size, page_mode).ok_or(AllocError::ReserveFailed { size })?;
+        let physical_address_space = region.commit(0, size).ok_or(AllocError::CommitFailed { size })?;
+        let physical_address_space_end = unsafe { physical_address_space.offset(size as isize) };
 
-        let physical_address_space = match virtual_mem::commit_physical_memory(virtual_mem, size) {
-            Some(address) => address,
-            None => std::ptr::null_mut(),
-        };
-
-        LinearAllocatorStorage {
+        Ok(LinearAllocatorStorage {
             use_internal_mem: true,
-            mem_begin: physical_address_space,
-            mem_end: unsafe { physical_address_space.offset(size as isize) },
+            region,
+            mem_end: physical_address_space_end,
+            committed_end: physical_address_space_end,
             current_ptr: physical_address_space,
+            low_water_mark: None,
+            scope_depth: 0,
+            stats: StatsCounters::default(),
+            #[cfg(feature = "epoch_check")]
+            epoch: 0,
+        })
+    }
+
+    ///
+    /// Creates a new linear allocator storage that only reserves the address
+    /// space, physical memory is committed once allocations reach into it
+    ///
+    fn new_lazy(reserve_size: usize, low_water_mark: Option<usize>) -> Result<LinearAllocatorStorage, AllocError> {
+
+        let region = VirtualRegion::reserve(reserve_size).ok_or(AllocError::ReserveFailed { size: reserve_size })?;
+        let virtual_address_space = region.base();
+
+        Ok(LinearAllocatorStorage {
+            use_internal_mem: true,
+            region,
+            mem_end: unsafe { virtual_address_space.offset(reserve_size as isize) },
+            committed_end: virtual_address_space,
+            current_ptr: virtual_address_space,
+            low_water_mark,
+            scope_depth: 0,
+            stats: StatsCounters::default(),
+            #[cfg(feature = "epoch_check")]
+            epoch: 0,
+        })
+    }
+
+    ///
+    /// Commits the pages between the end of the committed memory and `required_end`,
+    /// returns false if the OS could not provide the physical memory
+    ///
+    fn commit_up_to(&mut self, required_end: *mut u8) -> bool {
+        if required_end <= self.committed_end {
+            return true;
         }
+
+        let committed_bytes = self.committed_end as usize - self.region.base() as usize;
+        let bytes_to_commit = required_end as usize - self.committed_end as usize;
+
+        if self.region.commit(committed_bytes, bytes_to_commit).is_none() {
+            return false;
+        }
+
+        // The region commits whole pages, but must not move the committed end past `mem_end`
+        let committed_end = unsafe { self.region.base().offset(self.region.committed_size() as isize) };
+        self.committed_end = if committed_end < self.mem_end { committed_end } else { self.mem_end };
+
+        true
     }
 }
 
@@ -65,25 +121,69 @@ pub struct LinearAllocator {
     storage: RefCell<LinearAllocatorStorage>,
 }
 
+// The pointers of the storage only point into the region owned by the allocator,
+// so it can be moved to another thread as a whole
+unsafe impl Send for LinearAllocator {}
+
 impl LinearAllocator {
     pub fn new(size: usize) -> LinearAllocator {
+        LinearAllocator::with_page_mode(size, PageMode::Default)
+    }
+
+    ///
+    /// Creates a linear allocator whose memory is backed by pages of the given mode,
+    /// falling back to smaller pages if the system cannot provide them
+    ///
+    pub fn with_page_mode(size: usize, page_mode: PageMode) -> LinearAllocator {
+        LinearAllocator::try_with_page_mode(size, page_mode).expect("Could not create the allocator")
+    }
+
+    ///
+    /// Like `with_page_mode`, but returns an error if the memory could not be reserved or committed
+    ///
+    pub fn try_with_page_mode(size: usize, page_mode: PageMode) -> Result<LinearAllocator, AllocError> {
         debug_assert!(size > 0usize, "Size is not allowed to be 0");
 
-        LinearAllocator {
-            storage: RefCell::new(LinearAllocatorStorage::new(size)),
-        }
+        Ok(LinearAllocator {
+            storage: RefCell::new(LinearAllocatorStorage::new(size, page_mode)?),
+        })
+    }
+
+    ///
+    /// Creates a linear allocator that reserves `reserve_size` bytes of address space,
+    /// but commits pages only when allocations cross into them. This way an allocator can
+    /// be given a huge arena without paying for resident memory up front. If a low-water
+    /// mark is given, `reset` decommits all pages above the mark again
+    ///
+    pub fn with_lazy_commit(reserve_size: usize, low_water_mark: Option<usize>) -> LinearAllocator {
+        LinearAllocator::try_with_lazy_commit(reserve_size, low_water_mark).expect("Could not create the allocator")
+    }
+
+    ///
+    /// Like `with_lazy_commit`, but returns an error if the address space could not be reserved
+    ///
+    pub fn try_with_lazy_commit(reserve_size: usize, low_water_mark: Option<usize>) -> Result<LinearAllocator, AllocError> {
+        debug_assert!(reserve_size > 0usize, "Size is not allowed to be 0");
+
+        Ok(LinearAllocator {
+            storage: RefCell::new(LinearAllocatorStorage::new_lazy(reserve_size, low_water_mark)?),
+        })
+    }
+
+    ///
+    /// Returns the amount of physical memory currently committed for the allocator
+    ///
+    pub fn committed_size(&self) -> usize {
+        let storage = self.storage.borrow();
+        storage.committed_end as usize - storage.region.base() as usize
     }
 }
 
 impl BasicAllocator for LinearAllocator {
     type AllocatorImplementation = LinearAllocator;
 
-    fn new(size: usize) -> Self::AllocatorImplementation {
-        debug_assert!(size > 0usize, "Size is not allowed to be 0");
-
-        LinearAllocator {
-            storage: RefCell::new(LinearAllocatorStorage::new(size)),
-        }
+    fn try_new(size: usize) -> Result<Self::AllocatorImplementation, AllocError> {
+        LinearAllocator::try_with_page_mode(size, PageMode::Default)
     }
 }
 
@@ -98,9 +198,18 @@ impl Allocator for LinearAllocator {
     fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) 
         -> Option<MemoryBlock>
     {
-        debug_assert!(pointer_util::is_pot(alignment), "Alignment needs to be a power of two");
+        self.try_alloc_raw(size, alignment, offset).ok()
+    }
 
+    fn try_alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Result<MemoryBlock, AllocError> {
         let mut allocator_storage = self.storage.borrow_mut();
+
+        if !pointer_util::is_pot(alignment) {
+            allocator_storage.stats.on_failed_request();
+            return Err(AllocError::InvalidAlignment { alignment });
+        }
+
+        let previous_ptr = allocator_storage.current_ptr;
         let offset_before_alignment = offset + ALLOCATION_META_SIZE;
 
         unsafe {
@@ -109,38 +218,79 @@ impl Allocator for LinearAllocator {
             allocator_storage.current_ptr = allocator_storage.current_ptr.offset(offset_before_alignment as isize);
             allocator_storage.current_ptr = pointer_util::align_top(allocator_storage.current_ptr, alignment) as *mut u8;
 
-            // If we overflow we cannot fulfill this allocation and return None
-            let allocation_overflows = allocator_storage.current_ptr.offset((size - offset) as isize) > allocator_storage.mem_end;
+            // If we overflow we cannot fulfill this allocation and return an error
+            let allocation_end = allocator_storage.current_ptr.offset((size - offset) as isize);
+            let allocation_overflows = allocation_end > allocator_storage.mem_end;
             if  allocation_overflows {
-                return None;
+                allocator_storage.current_ptr = previous_ptr;
+                allocator_storage.stats.on_failed_request();
+                return Err(AllocError::OutOfMemory {
+                    requested: size,
+                    available: allocator_storage.mem_end as usize - previous_ptr as usize,
+                });
+            }
+
+            let uncommitted_size = (allocation_end as usize).saturating_sub(allocator_storage.committed_end as usize);
+            if !allocator_storage.commit_up_to(allocation_end) {
+                allocator_storage.current_ptr = previous_ptr;
+                allocator_storage.stats.on_failed_request();
+                return Err(AllocError::CommitFailed { size: uncommitted_size });
             }
 
             allocator_storage.current_ptr = allocator_storage.current_ptr.offset(-(offset_before_alignment as isize));            
 
             let mut user_ptr = allocator_storage.current_ptr;
 
-            std::ptr::write(user_ptr as *mut u32, size as u32);
+            let as_alloc_header = &mut *(user_ptr as *mut AllocationHeader);
+            as_alloc_header.allocation_size = size as u32;
+            #[cfg(feature = "epoch_check")]
+            {
+                as_alloc_header.epoch = allocator_storage.epoch;
+            }
+
             user_ptr = user_ptr.offset(ALLOCATION_META_SIZE as isize);
             allocator_storage.current_ptr = allocator_storage.current_ptr.offset((size + ALLOCATION_META_SIZE) as isize);
 
-            Some(MemoryBlock::new(user_ptr))
+            let occupied_size = allocator_storage.current_ptr as usize - previous_ptr as usize;
+            allocator_storage.stats.on_alloc(size, occupied_size);
+
+            Ok(MemoryBlock::new(user_ptr))
         }
     }
 
     ///
     /// `dealloc` yields a no-op in this LinearAllocator
     ///
-    fn dealloc_raw(&self, _memory: MemoryBlock) {}
+    fn dealloc_raw(&self, memory: MemoryBlock) {
+        self.validate_block(&memory);
+    }
 
     ///
     /// To free issued allocations one has to call `reset` to return the
     /// allocator to its initial state. Be careful, at the moment this function
     /// does invalidate ALL user managed MemoryBlockBlocks, without any
-    /// safety mechanism for the user holding it
+    /// safety mechanism for the user holding it. Lazily committing allocators
+    /// with a low-water mark decommit all pages above the mark
     ///
     fn reset(&self) {
         let mut storage = self.storage.borrow_mut();
-        storage.current_ptr = storage.mem_begin;
+        storage.current_ptr = storage.region.base();
+        storage.stats.on_reset();
+
+        #[cfg(feature = "epoch_check")]
+        {
+            storage.epoch = storage.epoch.wrapping_add(1);
+        }
+
+        if let Some(low_water_mark) = storage.low_water_mark {
+            let retained_bytes = math_util::round_to_next_multiple(low_water_mark, storage.region.page_size());
+            let committed_bytes = storage.committed_end as usize - storage.region.base() as usize;
+
+            if committed_bytes > retained_bytes {
+                storage.region.decommit(retained_bytes, committed_bytes - retained_bytes);
+                storage.committed_end = unsafe { storage.region.base().offset(retained_bytes as isize) };
+            }
+        }
     }
 
     ///
@@ -148,6 +298,8 @@ impl Allocator for LinearAllocator {
     ///
     fn get_allocation_size(&self, memory: &MemoryBlock) -> usize
     {
+        self.validate_block(memory);
+
         let alloc_header: &mut AllocationHeader;
 
         unsafe {
@@ -157,6 +309,115 @@ impl Allocator for LinearAllocator {
 
         alloc_header.allocation_size as usize
     }
+
+    ///
+    /// Grows or shrinks the most recent allocation in place, other blocks are shrunk in
+    /// place as well, but have to be moved to the top of the allocator to grow
+    ///
+    fn realloc_raw(&self, memory: &MemoryBlock, new_size: usize, alignment: usize) -> Option<MemoryBlock> {
+        debug_assert!(pointer_util::is_pot(alignment), "Alignment needs to be a power of two");
+
+        let old_size = self.get_allocation_size(memory);
+
+        if pointer_util::is_aligned_to(memory.ptr, alignment) {
+            let mut storage = self.storage.borrow_mut();
+            let alloc_header = unsafe { &mut *(memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *mut AllocationHeader) };
+            let block_end = unsafe { memory.ptr.offset(old_size as isize) };
+            let new_block_end = unsafe { memory.ptr.offset(new_size as isize) };
+
+            let is_topmost_allocation = block_end == storage.current_ptr;
+            if is_topmost_allocation && new_block_end <= storage.mem_end && storage.commit_up_to(new_block_end) {
+                alloc_header.allocation_size = new_size as u32;
+                storage.current_ptr = new_block_end;
+                storage.stats.on_resize(old_size, old_size, new_size, new_size);
+                return Some(MemoryBlock::new(memory.ptr));
+            }
+
+            if new_size <= old_size {
+                alloc_header.allocation_size = new_size as u32;
+                storage.stats.on_resize(old_size, old_size, new_size, old_size);
+                return Some(MemoryBlock::new(memory.ptr));
+            }
+        }
+
+        let new_memory = self.alloc_raw(new_size, alignment, 0)?;
+        unsafe { std::ptr::copy_nonoverlapping(memory.ptr, new_memory.ptr, std::cmp::min(old_size, new_size)) };
+
+        // The old block is not reused before the next reset, it is only counted as overhead from now on
+        self.storage.borrow_mut().stats.on_dealloc(old_size, 0);
+
+        Some(new_memory)
+    }
+
+    #[cfg(feature = "epoch_check")]
+    fn validate_block(&self, memory: &MemoryBlock) {
+        let alloc_header = unsafe { &*(memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *const AllocationHeader) };
+        let block_is_current = alloc_header.epoch == self.storage.borrow().epoch;
+        assert!(block_is_current, "MemoryBlock was invalidated by a reset of its allocator");
+    }
+
+    ///
+    /// As `dealloc` is a no-op, blocks count as live allocations until the allocator is
+    /// reset or freed to a marker
+    ///
+    fn stats(&self) -> AllocatorStats {
+        let storage = self.storage.borrow();
+        storage.stats.snapshot(storage.mem_end as usize - storage.region.base() as usize, None)
+    }
+}
+
+impl MarkerAllocator for LinearAllocator {
+    fn get_marker(&self) -> Marker {
+        let storage = self.storage.borrow();
+
+        Marker {
+            offset: storage.current_ptr as usize - storage.region.base() as usize,
+            allocation_id: 0,
+            scope_depth: storage.scope_depth,
+            used_bytes: storage.stats.used_bytes,
+            live_allocations: storage.stats.live_allocations,
+        }
+    }
+
+    ///
+    /// Frees all allocations issued after `marker` was taken, MemoryBlocks
+    /// allocated before remain valid
+    ///
+    fn free_to_marker(&self, marker: Marker) {
+        let mut storage = self.storage.borrow_mut();
+
+        {
+            let current_offset = storage.current_ptr as usize - storage.region.base() as usize;
+            let marker_below_current_ptr = marker.offset <= current_offset;
+            debug_assert!(marker_below_current_ptr, "Marker lies above the current top of the allocator");
+        }
+
+        storage.current_ptr = unsafe { storage.region.base().offset(marker.offset as isize) };
+        storage.stats.rewind(marker.used_bytes, marker.offset, marker.live_allocations);
+    }
+
+    fn begin_scope(&self) -> Marker {
+        self.storage.borrow_mut().scope_depth += 1;
+        self.get_marker()
+    }
+
+    fn end_scope(&self, marker: Marker) {
+        {
+            let scope_is_innermost = marker.scope_depth == self.storage.borrow().scope_depth;
+            debug_assert!(scope_is_innermost, "Allocator scopes have to be released in nested order");
+        }
+
+        self.storage.borrow_mut().scope_depth -= 1;
+        self.free_to_marker(marker);
+    }
+}
+
+impl OwningAllocator for LinearAllocator {
+    fn owns(&self, memory: &MemoryBlock) -> bool {
+        let storage = self.storage.borrow();
+        let ptr = memory.ptr as *const u8;
+        ptr >= storage.region.base() as *const u8 && ptr < storage.mem_end as *const u8
+    }
 }
 
 #[cfg(test)]
@@ -231,7 +492,7 @@ mod tests
             pub id: usize,
         }
         
-        let linear_alloc: LinearAllocator = LinearAllocator::new(std::mem::size_of::<Data>() + 4);
+        let linear_alloc: LinearAllocator = LinearAllocator::new(std::mem::size_of::<Data>() + ALLOCATION_META_SIZE);
 
         {
             let mut data_box = linear_alloc.alloc(Data { result: 1.0, id: 1 }, 1, 0).unwrap();
@@ -250,4 +511,223 @@ mod tests
         let data_box = linear_alloc.alloc(Data { result: 1.0, id: 1 }, 1, 0);
         assert!(data_box.is_none(), "Second allocation did not fail, LinearAllocator does not allow freeing hence should be OOM");
     }
+
+    #[test]
+    fn allocate_with_huge_pages() {
+        let linear_alloc: LinearAllocator = LinearAllocator::with_page_mode(10 * MB, PageMode::Huge);
+        let mem_raw_0 = linear_alloc.alloc_raw(4 * MB, 16, 0).unwrap();
+        let mem_raw_1 = linear_alloc.alloc_raw(4 * MB, 16, 0).unwrap();
+        assert!(linear_alloc.alloc_raw(4 * MB, 16, 0).is_none(), "Allocator exceeded the requested size");
+
+        unsafe {
+            std::ptr::write_bytes(mem_raw_0.ptr, 0xCC, 4 * MB);
+            std::ptr::write_bytes(mem_raw_1.ptr, 0xCC, 4 * MB);
+        }
+
+        let storage = linear_alloc.storage.borrow();
+        assert!(storage.region.committed_size() >= 10 * MB);
+        assert_eq!(storage.region.committed_size() % storage.region.page_size(), 0);
+    }
+
+    #[test]
+    fn lazy_commit_on_demand() {
+        let linear_alloc: LinearAllocator = LinearAllocator::with_lazy_commit(4 * KB * MB, None);
+        assert_eq!(linear_alloc.committed_size(), 0);
+
+        let mem_raw_0 = linear_alloc.alloc_raw(MB, 16, 0).unwrap();
+        let committed_after_first = linear_alloc.committed_size();
+        assert!(committed_after_first >= MB + ALLOCATION_META_SIZE && committed_after_first < 2 * MB, "Allocator did not commit just enough pages");
+
+        let mem_raw_1 = linear_alloc.alloc_raw(3 * MB, 16, 0).unwrap();
+        assert!(linear_alloc.committed_size() >= 4 * MB, "Allocator did not commit further pages");
+
+        unsafe {
+            std::ptr::write_bytes(mem_raw_0.ptr, 0xCC, MB);
+            std::ptr::write_bytes(mem_raw_1.ptr, 0xCC, 3 * MB);
+        }
+
+        linear_alloc.reset();
+        assert!(linear_alloc.committed_size() >= 4 * MB, "Allocator without low-water mark decommitted on reset");
+    }
+
+    #[test]
+    fn lazy_commit_reset_to_low_water_mark() {
+        let linear_alloc: LinearAllocator = LinearAllocator::with_lazy_commit(4 * KB * MB, Some(MB));
+        linear_alloc.alloc_raw(8 * MB, 16, 0).unwrap();
+        assert!(linear_alloc.committed_size() > 8 * MB);
+
+        linear_alloc.reset();
+        assert_eq!(linear_alloc.committed_size(), MB);
+
+        // Memory above the low-water mark has to be committed again when reused
+        let mem_raw = linear_alloc.alloc_raw(2 * MB, 16, 0).unwrap();
+        unsafe { std::ptr::write_bytes(mem_raw.ptr, 0xCC, 2 * MB) };
+        assert!(linear_alloc.committed_size() > 2 * MB);
+    }
+
+    #[test]
+    fn lazy_commit_does_not_exceed_reservation() {
+        let linear_alloc: LinearAllocator = LinearAllocator::with_lazy_commit(MB, None);
+        let mem_raw_0 = linear_alloc.alloc_raw(MB / 2, 1, 0).unwrap();
+        assert!(linear_alloc.alloc_raw(MB, 1, 0).is_none());
+
+        // A failed allocation does not move the allocator forward
+        let mem_raw_1 = linear_alloc.alloc_raw(MB / 4, 1, 0).unwrap();
+        assert_eq!(mem_raw_1.ptr as usize - mem_raw_0.ptr as usize, MB / 2 + ALLOCATION_META_SIZE);
+    }
+
+    #[test]
+    fn free_to_marker() {
+        let linear_alloc: LinearAllocator = LinearAllocator::new(10 * MB);
+        let mem_raw_0 = linear_alloc.alloc_raw(MB, 4, 0).unwrap();
+        let marker = linear_alloc.get_marker();
+        let mem_raw_1 = linear_alloc.alloc_raw(MB, 4, 0).unwrap();
+        linear_alloc.alloc_raw(MB, 4, 0).unwrap();
+
+        linear_alloc.free_to_marker(marker);
+
+        let mem_raw_2 = linear_alloc.alloc_raw(MB, 4, 0).unwrap();
+        assert_eq!(mem_raw_1.ptr, mem_raw_2.ptr);
+        assert_eq!(linear_alloc.get_allocation_size(&mem_raw_0), MB, "Allocation before the marker was corrupted");
+    }
+
+    #[test]
+    fn scope_rewinds_on_drop() {
+        let linear_alloc: LinearAllocator = LinearAllocator::new(10 * MB);
+        let mem_raw_0 = linear_alloc.alloc_raw(MB, 4, 0).unwrap();
+
+        {
+            let outer_scope = linear_alloc.scope();
+            outer_scope.alloc_raw(MB, 4, 0).unwrap();
+
+            {
+                let inner_scope = outer_scope.scope();
+                inner_scope.alloc_raw(MB, 4, 0).unwrap();
+                let scratch = inner_scope.alloc(0xCCu64, 8, 0).unwrap();
+                assert_eq!(*scratch, 0xCC);
+            }
+
+            outer_scope.alloc_raw(MB, 4, 0).unwrap();
+        }
+
+        let mem_raw_1 = linear_alloc.alloc_raw(MB, 4, 0).unwrap();
+        assert_eq!(mem_raw_1.ptr as usize - mem_raw_0.ptr as usize, MB + ALLOCATION_META_SIZE, "Scope did not free its allocations");
+    }
+
+    #[test]
+    #[should_panic(expected = "Allocator scopes have to be released in nested order")]
+    fn scopes_released_out_of_order() {
+        let linear_alloc: LinearAllocator = LinearAllocator::new(10 * MB);
+        let outer_scope = linear_alloc.scope();
+        let _inner_scope = linear_alloc.scope();
+
+        std::mem::drop(outer_scope);
+    }
+
+    #[cfg(feature = "epoch_check")]
+    #[test]
+    #[should_panic(expected = "MemoryBlock was invalidated by a reset of its allocator")]
+    fn stale_block_after_reset() {
+        let linear_alloc: LinearAllocator = LinearAllocator::new(10 * MB);
+        let mem_raw = linear_alloc.alloc_raw(MB, 4, 0).unwrap();
+        linear_alloc.reset();
+
+        linear_alloc.get_allocation_size(&mem_raw);
+    }
+
+    #[cfg(feature = "epoch_check")]
+    #[test]
+    fn stale_box_after_reset() {
+        let linear_alloc: LinearAllocator = LinearAllocator::new(10 * MB);
+        let data_box = linear_alloc.alloc(0xCCu64, 8, 0).unwrap();
+        assert_eq!(*data_box, 0xCC);
+        linear_alloc.reset();
+
+        let deref_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| *data_box));
+        assert!(deref_result.is_err(), "Dereferencing a stale AllocatorBox did not assert");
+
+        // Dropping the box would deallocate the stale block again
+        std::mem::forget(data_box);
+    }
+
+    #[test]
+    fn realloc_grows_most_recent_in_place() {
+        let linear_alloc = LinearAllocator::new(KB);
+
+        let first_mem = linear_alloc.alloc_raw(64, 8, 0).unwrap();
+        unsafe { *(first_mem.ptr as *mut u64) = 0xDEADBEEF };
+        let last_mem = linear_alloc.alloc_raw(64, 8, 0).unwrap();
+
+        let grown_mem = linear_alloc.realloc_raw(&last_mem, 512, 8).unwrap();
+        assert_eq!(grown_mem.ptr, last_mem.ptr, "Most recent allocation was moved");
+
+        let moved_mem = linear_alloc.realloc_raw(&first_mem, 128, 8).unwrap();
+        assert!(moved_mem.ptr as usize >= grown_mem.ptr as usize + 512, "Moved block overlaps the grown block");
+        assert_eq!(unsafe { *(moved_mem.ptr as *const u64) }, 0xDEADBEEF);
+    }
+
+    #[test]
+    fn realloc_commits_lazily() {
+        let linear_alloc = LinearAllocator::with_lazy_commit(MB, None);
+
+        let mem = linear_alloc.alloc_raw(64, 8, 0).unwrap();
+        let grown_mem = linear_alloc.realloc_raw(&mem, 256 * KB, 8).unwrap();
+
+        assert_eq!(grown_mem.ptr, mem.ptr);
+        assert!(linear_alloc.committed_size() >= 256 * KB);
+        unsafe { *grown_mem.ptr.offset((256 * KB - 1) as isize) = 0xFF };
+    }
+
+    #[test]
+    fn try_alloc_reports_errors() {
+        let linear_alloc = LinearAllocator::new(KB);
+
+        linear_alloc.try_alloc_raw(256, 1, 0).unwrap();
+        match linear_alloc.try_alloc_raw(KB, 8, 0) {
+            Err(AllocError::OutOfMemory { requested, available }) => {
+                assert_eq!(requested, KB);
+                assert_eq!(available, KB - 256 - ALLOCATION_META_SIZE);
+            },
+            _ => panic!("Expected OutOfMemory"),
+        }
+
+        assert_eq!(linear_alloc.try_alloc_raw(16, 3, 0).unwrap_err(), AllocError::InvalidAlignment { alignment: 3 });
+        assert!(linear_alloc.try_alloc(42u64, 8, 0).is_ok(), "Failed requests changed the allocator");
+    }
+
+    #[test]
+    fn try_new_reports_failed_reserve() {
+        let huge_size = 1usize << 62;
+        assert_eq!(LinearAllocator::try_new(huge_size).err(), Some(AllocError::ReserveFailed { size: huge_size }));
+    }
+
+    #[test]
+    fn stats_track_usage() {
+        let linear_alloc = LinearAllocator::new(KB);
+
+        linear_alloc.alloc_raw(100, 1, 0).unwrap();
+        let marker = linear_alloc.get_marker();
+        linear_alloc.alloc_raw(200, 1, 0).unwrap();
+        assert!(linear_alloc.alloc_raw(KB, 1, 0).is_none());
+
+        let stats = linear_alloc.stats();
+        assert_eq!(stats.capacity, KB);
+        assert_eq!(stats.used_bytes, 300);
+        assert_eq!(stats.overhead_bytes, 2 * ALLOCATION_META_SIZE);
+        assert_eq!(stats.live_allocations, 2);
+        assert_eq!(stats.failed_requests, 1);
+        assert_eq!(stats.free_blocks, None);
+
+        linear_alloc.free_to_marker(marker);
+        let stats = linear_alloc.stats();
+        assert_eq!(stats.used_bytes, 100);
+        assert_eq!(stats.live_allocations, 1);
+        assert_eq!(stats.high_water_mark, 300 + 2 * ALLOCATION_META_SIZE);
+
+        linear_alloc.reset();
+        let stats = linear_alloc.stats();
+        assert_eq!(stats.occupied_bytes(), 0);
+        assert_eq!(stats.available_bytes(), KB);
+        assert_eq!(stats.high_water_mark, 300 + 2 * ALLOCATION_META_SIZE, "Reset lowered the high-water mark");
+    }
 }
\ No newline at end of file
@@ -1,5 +1,19 @@
 pub mod base;
+pub mod alloc_error;
+pub mod allocator_stats;
 pub mod linear_allocator;
 pub mod stack_allocator;
 pub mod double_ended_stack_allocator;
-pub mod pool_allocator;
\ No newline at end of file
+pub mod pool_allocator;
+pub mod concurrent_pool_allocator;
+pub mod free_list_allocator;
+pub mod tlsf_allocator;
+pub mod buddy_allocator;
+pub mod small_object_allocator;
+pub mod guard_page_allocator;
+pub mod thread_local_arena;
+pub mod frame_allocator;
+pub mod system_allocator;
+pub mod fallback_allocator;
+pub mod segregator;
+pub mod std_alloc_adapter;
\ No newline at end of file
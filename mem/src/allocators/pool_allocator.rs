@@ -2,8 +2,10 @@ use std;
 use std::cell::RefCell;
 use spark_core::{ pointer_util, freelist, math_util };
 
-use super::super::virtual_mem;
-use super::base::{ Allocator, MemoryBlock, TypedAllocator };
+use super::super::virtual_mem::{ VirtualRegion, PageMode };
+use super::base::{ Allocator, MemoryBlock, TypedAllocator, OwningAllocator };
+use super::alloc_error::AllocError;
+use super::allocator_stats::{ AllocatorStats, StatsCounters };
 
 ///
 /// The AllocationHeader struct describes meta-data
@@ -12,6 +14,8 @@ use super::base::{ Allocator, MemoryBlock, TypedAllocator };
 ///
 struct AllocationHeader {
     pub allocation_size: u32,
+    #[cfg(feature = "epoch_check")]
+    pub epoch:           u32,
 }
 
 const ALLOCATION_META_SIZE: usize = std::mem::size_of::<AllocationHeader>();
@@ -25,105 +29,267 @@ fn calculate_minimal_block_size(max_size: usize, max_alignment: usize) -> usize
     }
 }
 
+///
+/// The PoolCapacity decides how many blocks a pool hands out before it runs dry
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolCapacity {
+    ///
+    /// Hands out at least `element_count` blocks, the padding needed to align
+    /// the first block can leave room for an additional one
+    ///
+    AtLeast,
+    ///
+    /// Hands out exactly `element_count` blocks
+    ///
+    Exact,
+    ///
+    /// Commits another chunk of `element_count` blocks whenever the pool runs dry
+    ///
+    Growable,
+}
+
+///
+/// A chunk of virtual memory the blocks of a pool are carved from
+///
+struct PoolChunk {
+    pub region:             VirtualRegion,
+    pub first_block_ptr:    *mut u8,
+    pub mem_end:            *mut u8,
+}
+
 struct PoolAllocatorStorage {
     pub use_internal_mem:       bool,
-    pub mem_begin:              *mut u8,
-    pub mem_end:                *mut u8,
-    pub first_block_ptr:        *mut u8,
+    pub chunks:                 Vec<PoolChunk>,
+    pub chunk_size:             usize,
+    pub element_count:          usize,
+    pub capacity:               PoolCapacity,
+    pub page_mode:              PageMode,
+    pub offset:                 usize,
     pub max_element_size:       usize,
     pub max_element_alignment:  usize,
     pub min_block_size:         usize,
     pub free_list:              freelist::FreeList,
+    pub stats:                  StatsCounters,
+    #[cfg(feature = "epoch_check")]
+    pub epoch:                  u32,
 }
 
 impl PoolAllocatorStorage {
-    fn new(size: usize,
+    fn new(chunk_size: usize,
+        element_count: usize,
         min_block_size: usize,
         max_element_size: usize,
         max_element_alignment: usize,
-        offset: usize
-        ) -> PoolAllocatorStorage {
-        
-        let virtual_mem = match virtual_mem::reserve_address_space(size) {
-            Some(address) => address,
-            None => std::ptr::null_mut(),
-        };
+        offset: usize,
+        capacity: PoolCapacity,
+        page_mode: PageMode
+        ) -> Result<PoolAllocatorStorage, AllocError> {
 
-        let physical_address_space = match virtual_mem::commit_physical_memory(virtual_mem, size) {
-            Some(address) => address,
-            None => std::ptr::null_mut(),
+        let mut storage = PoolAllocatorStorage {
+            use_internal_mem:   true,
+            chunks:             Vec::new(),
+            chunk_size,
+            element_count,
+            capacity,
+            page_mode,
+            offset,
+            max_element_size,
+            max_element_alignment,
+            min_block_size,
+            free_list:          freelist::FreeList::new(),
+            stats:              StatsCounters::default(),
+            #[cfg(feature = "epoch_check")]
+            epoch:              0,
         };
 
-        let physical_address_space_end =  unsafe { physical_address_space.offset(size as isize) };
-        
+        storage.add_chunk()?;
+        Ok(storage)
+    }
+
+    ///
+    /// Reserves and commits another chunk of memory and threads its blocks into the free list
+    ///
+    fn add_chunk(&mut self) -> Result<(), AllocError> {
+        let mut region = VirtualRegion::reserve_with_page_mode(self.chunk_size, self.page_mode).ok_or(AllocError::ReserveFailed { size: self.chunk_size })?;
+        let physical_address_space = region.commit(0, self.chunk_size).ok_or(AllocError::CommitFailed { size: self.chunk_size })?;
+
         let first_block_ptr = unsafe {
-            let allocation_meta_offset = (offset + ALLOCATION_META_SIZE) as isize;
-            let aligned_ptr  = pointer_util::align_top(physical_address_space.offset(allocation_meta_offset), max_element_alignment) as *mut u8;
+            let allocation_meta_offset = (self.offset + ALLOCATION_META_SIZE) as isize;
+            let aligned_ptr  = pointer_util::align_top(physical_address_space.offset(allocation_meta_offset), self.max_element_alignment) as *mut u8;
             let before_aligned_ptr = aligned_ptr.offset(-allocation_meta_offset);
 
             before_aligned_ptr
         };
 
-        PoolAllocatorStorage {
-            use_internal_mem:   true,
-            mem_begin:          physical_address_space,
-            mem_end:            physical_address_space_end,
+        let mem_end = unsafe {
+            match self.capacity {
+                PoolCapacity::AtLeast => physical_address_space.offset(self.chunk_size as isize),
+                PoolCapacity::Exact | PoolCapacity::Growable => first_block_ptr.offset((self.element_count * self.min_block_size) as isize),
+            }
+        };
+
+        // Blocks start with the header in front of the aligned element, so they are not pointer
+        // aligned in general. The free list stores its links unaligned for that reason
+        self.free_list.add_range(first_block_ptr, mem_end, self.min_block_size);
+        self.chunks.push(PoolChunk {
+            region,
             first_block_ptr,
-            max_element_size,
-            max_element_alignment,
-            min_block_size,
-            free_list:          freelist::FreeList::new_from(first_block_ptr, physical_address_space_end, min_block_size),
-        }
-    }  
+            mem_end,
+        });
+
+        Ok(())
+    }
+
+    ///
+    /// Returns the amount of blocks all chunks committed so far can hand out
+    ///
+    fn block_count(&self) -> usize {
+        self.chunks.iter()
+            .map(|chunk| (chunk.mem_end as usize - chunk.first_block_ptr as usize) / self.min_block_size)
+            .sum()
+    }
 }
 
 pub struct PoolAllocator {
     storage: RefCell<PoolAllocatorStorage>,
 }
 
-impl TypedAllocator for PoolAllocator {
-    type AllocatorImplementation = PoolAllocator;
+// The chunks and their free lists are owned by the allocator alone,
+// so it can be moved to another thread as a whole
+unsafe impl Send for PoolAllocator {}
+
+impl PoolAllocator {
+    ///
+    /// Creates a pool allocator whose memory is backed by pages of the given mode,
+    /// falling back to smaller pages if the system cannot provide them
+    ///
+    pub fn with_page_mode(max_element_size: usize, element_count: usize, max_element_alignment: usize, offset: usize, page_mode: PageMode) -> PoolAllocator {
+        PoolAllocator::try_with_page_mode(max_element_size, element_count, max_element_alignment, offset, page_mode).expect("Could not create the allocator")
+    }
+
+    ///
+    /// Like `with_page_mode`, but returns an error if the memory could not be reserved or committed
+    ///
+    pub fn try_with_page_mode(max_element_size: usize, element_count: usize, max_element_alignment: usize, offset: usize, page_mode: PageMode) -> Result<PoolAllocator, AllocError> {
+        PoolAllocator::create(max_element_size, element_count, max_element_alignment, offset, PoolCapacity::AtLeast, page_mode)
+    }
+
+    ///
+    /// Creates a pool allocator that either hands out exactly `element_count` blocks
+    /// or grows by chunks of `element_count` blocks, depending on the capacity
+    ///
+    pub fn with_capacity(max_element_size: usize, element_count: usize, max_element_alignment: usize, offset: usize, capacity: PoolCapacity) -> PoolAllocator {
+        PoolAllocator::try_with_capacity(max_element_size, element_count, max_element_alignment, offset, capacity).expect("Could not create the allocator")
+    }
+
+    ///
+    /// Like `with_capacity`, but returns an error if the memory could not be reserved or committed
+    ///
+    pub fn try_with_capacity(max_element_size: usize, element_count: usize, max_element_alignment: usize, offset: usize, capacity: PoolCapacity) -> Result<PoolAllocator, AllocError> {
+        PoolAllocator::create(max_element_size, element_count, max_element_alignment, offset, capacity, PageMode::Default)
+    }
+
+    fn create(max_element_size: usize,
+        element_count: usize,
+        max_element_alignment: usize,
+        offset: usize,
+        capacity: PoolCapacity,
+        page_mode: PageMode
+        ) -> Result<PoolAllocator, AllocError> {
+
+        debug_assert!(element_count > 0usize, "Element count is not allowed to be 0");
 
-    fn new(max_element_size: usize, element_count: usize, max_element_alignment: usize, offset: usize) -> Self::AllocatorImplementation {
         let block_min_size = calculate_minimal_block_size(max_element_size + ALLOCATION_META_SIZE, max_element_alignment);
         let required_memory_size = (element_count * block_min_size) + max_element_alignment;
 
-        PoolAllocator {
+        Ok(PoolAllocator {
             storage: RefCell::new(PoolAllocatorStorage::new(
                 required_memory_size,
+                element_count,
                 block_min_size,
                 max_element_size,
                 max_element_alignment,
-                offset)
+                offset,
+                capacity,
+                page_mode)?
             ),
+        })
+    }
+
+    ///
+    /// Returns the amount of chunks the pool committed so far
+    ///
+    pub fn chunk_count(&self) -> usize {
+        self.storage.borrow().chunks.len()
+    }
+
+    ///
+    /// Returns the first address of the first chunk managed by this pool
+    ///
+    pub(crate) fn mem_begin(&self) -> *const u8 {
+        match self.storage.borrow().chunks.first() {
+            Some(chunk) => chunk.region.base() as *const u8,
+            None => std::ptr::null(),
         }
     }
 }
 
+impl TypedAllocator for PoolAllocator {
+    type AllocatorImplementation = PoolAllocator;
+
+    fn try_new(max_element_size: usize, element_count: usize, max_element_alignment: usize, offset: usize) -> Result<Self::AllocatorImplementation, AllocError> {
+        PoolAllocator::try_with_page_mode(max_element_size, element_count, max_element_alignment, offset, PageMode::Default)
+    }
+}
+
 impl Allocator for PoolAllocator {    
-    fn alloc_raw(&self, size: usize, alignment: usize, _offset: usize) -> Option<MemoryBlock> {
-        let storage = self.storage.borrow_mut();
+    fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
+        self.try_alloc_raw(size, alignment, offset).ok()
+    }
 
-        {
-            let size_lesser_or_equal_max_element_size = size <= storage.max_element_size;
-            debug_assert!(size_lesser_or_equal_max_element_size, "Alloc size has to be less or equal max element size");
-            let alignment_lesser_or_equal_max_element_alignment = alignment <= storage.max_element_alignment;
-            debug_assert!(alignment_lesser_or_equal_max_element_alignment, "Alloc alignment has to be less or equal max element alignment");
+    fn try_alloc_raw(&self, size: usize, alignment: usize, _offset: usize) -> Result<MemoryBlock, AllocError> {
+        let mut storage = self.storage.borrow_mut();
+
+        if size > storage.max_element_size {
+            storage.stats.on_failed_request();
+            return Err(AllocError::ExceedsMaxElementSize { requested: size, max_element_size: storage.max_element_size });
+        }
+
+        if !pointer_util::is_pot(alignment) || alignment > storage.max_element_alignment {
+            storage.stats.on_failed_request();
+            return Err(AllocError::InvalidAlignment { alignment });
         }
         
         let mut ptr = storage.free_list.get_block();
+
+        if ptr.is_null() && storage.capacity == PoolCapacity::Growable {
+            if let Err(error) = storage.add_chunk() {
+                storage.stats.on_failed_request();
+                return Err(error);
+            }
+            ptr = storage.free_list.get_block();
+        }
         
         if ptr.is_null() {
-            return None;
+            storage.stats.on_failed_request();
+            return Err(AllocError::OutOfMemory { requested: size, available: 0 });
         }
 
         unsafe {
             let allocation_header = &mut *(ptr as *mut AllocationHeader);
             allocation_header.allocation_size = size as u32;
+            #[cfg(feature = "epoch_check")]
+            {
+                allocation_header.epoch = storage.epoch;
+            }
             ptr = ptr.offset(ALLOCATION_META_SIZE as isize);
         }
 
-        Some(MemoryBlock::new(ptr))
+        let min_block_size = storage.min_block_size;
+        storage.stats.on_alloc(size, min_block_size);
+
+        Ok(MemoryBlock::new(ptr))
     }
 
     fn dealloc_raw(&self, memory: MemoryBlock) {
@@ -131,21 +297,37 @@ impl Allocator for PoolAllocator {
             // TODO: Asserts
         }
 
-        let storage = self.storage.borrow_mut();
+        self.validate_block(&memory);
+
+        let allocation_size = self.get_allocation_size(&memory);
+        let mut storage = self.storage.borrow_mut();
         let original_ptr = unsafe { memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) };
         storage.free_list.return_block(original_ptr);
+
+        let min_block_size = storage.min_block_size;
+        storage.stats.on_dealloc(allocation_size, min_block_size);
     }
 
     fn reset(&self) {
         let mut storage = self.storage.borrow_mut();
-        storage.free_list = freelist::FreeList::new_from(
-            storage.first_block_ptr,
-            storage.mem_end,
-            storage.min_block_size
-        );
+
+        // Chunks are threaded in reverse to hand out the blocks of the first chunk first
+        let free_list = freelist::FreeList::new();
+        for chunk in storage.chunks.iter().rev() {
+            free_list.add_range(chunk.first_block_ptr, chunk.mem_end, storage.min_block_size);
+        }
+
+        storage.free_list = free_list;
+        storage.stats.on_reset();
+        #[cfg(feature = "epoch_check")]
+        {
+            storage.epoch = storage.epoch.wrapping_add(1);
+        }
     }
 
     fn get_allocation_size(&self, memory: &MemoryBlock) -> usize {
+        self.validate_block(memory);
+
         let alloc_header: &mut AllocationHeader;
 
         unsafe {
@@ -155,6 +337,31 @@ impl Allocator for PoolAllocator {
 
         alloc_header.allocation_size as usize
     }
+
+    #[cfg(feature = "epoch_check")]
+    fn validate_block(&self, memory: &MemoryBlock) {
+        let alloc_header = unsafe { &*(memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *const AllocationHeader) };
+        let block_is_current = alloc_header.epoch == self.storage.borrow().epoch;
+        assert!(block_is_current, "MemoryBlock was invalidated by a reset of its allocator");
+    }
+
+    ///
+    /// Every allocation occupies a whole block, the part of it the allocation
+    /// does not use is counted as overhead
+    ///
+    fn stats(&self) -> AllocatorStats {
+        let storage = self.storage.borrow();
+        let block_count = storage.block_count();
+        storage.stats.snapshot(block_count * storage.min_block_size, Some(block_count - storage.stats.live_allocations))
+    }
+}
+
+impl OwningAllocator for PoolAllocator {
+    fn owns(&self, memory: &MemoryBlock) -> bool {
+        let storage = self.storage.borrow();
+        let ptr = memory.ptr as *const u8;
+        storage.chunks.iter().any(|chunk| ptr >= chunk.region.base() as *const u8 && ptr < chunk.mem_end as *const u8)
+    }
 }
 
 #[cfg(test)]
@@ -250,7 +457,7 @@ mod tests {
             0
         );
 
-        // We can allocate more than 10 Particles bc of page size rounging when allocating virt-mem
+        // With PoolCapacity::AtLeast the alignment padding could leave room for more than 10 Particles (use PoolCapacity::Exact to rule that out)
         // In fact we are leaking memory inside of this loop bc an MemoryBlock is in the responsibility
         // of the user - and bc each get dropped at the end of the scope we leak the mem in the allocator
         // hence triggering the oom in the last allocation request (a later implemented AllocatorBox will
@@ -308,4 +515,118 @@ mod tests {
             assert!(vec_1_part.speed == idx + 5, "Particle speed from vec 1 was corrupted");
         }
     }
+
+    #[test]
+    fn exact_capacity_hands_out_element_count_blocks() {
+        // Element and header fill a 16 byte block exactly, which leaves room for an extra block with PoolCapacity::AtLeast
+        let element_size = 16 - ALLOCATION_META_SIZE;
+        let pool_alloc = PoolAllocator::with_capacity(element_size, 10, 16, element_size, PoolCapacity::Exact);
+
+        for _ in 0 .. 10 {
+            assert!(pool_alloc.alloc_raw(element_size, 16, element_size).is_some());
+        }
+
+        assert!(pool_alloc.alloc_raw(element_size, 16, element_size).is_none());
+
+        let at_least_alloc = PoolAllocator::with_capacity(element_size, 10, 16, element_size, PoolCapacity::AtLeast);
+        for _ in 0 .. 11 {
+            assert!(at_least_alloc.alloc_raw(element_size, 16, element_size).is_some());
+        }
+    }
+
+    #[test]
+    fn growable_pool_adds_chunks() {
+        let pool_alloc = PoolAllocator::with_capacity(std::mem::size_of::<Particle>(), 10, 16, 0, PoolCapacity::Growable);
+
+        let mut particles = Vec::new();
+        for i in 0 .. 35 {
+            let part_mem = pool_alloc.alloc_raw(std::mem::size_of::<Particle>(), 16, 0).unwrap();
+            let particle: &mut Particle = unsafe { &mut *(part_mem.ptr as *mut Particle) };
+            particle.speed = i;
+            assert!(pool_alloc.owns(&part_mem));
+            particles.push(part_mem);
+        }
+
+        assert_eq!(pool_alloc.chunk_count(), 4);
+        for (idx, part_mem) in particles.iter().enumerate() {
+            let particle: &Particle = unsafe { &*(part_mem.ptr as *const Particle) };
+            assert!(particle.speed == idx, "Adding a chunk corrupted earlier allocations");
+        }
+    }
+
+    #[test]
+    fn reset_rebuilds_free_list_across_chunks() {
+        let pool_alloc = PoolAllocator::with_capacity(std::mem::size_of::<Particle>(), 10, 16, 0, PoolCapacity::Growable);
+
+        let first_ptr = pool_alloc.alloc_raw(std::mem::size_of::<Particle>(), 16, 0).unwrap().ptr;
+        for _ in 1 .. 30 {
+            pool_alloc.alloc_raw(std::mem::size_of::<Particle>(), 16, 0).unwrap();
+        }
+
+        pool_alloc.reset();
+
+        assert_eq!(pool_alloc.alloc_raw(std::mem::size_of::<Particle>(), 16, 0).unwrap().ptr, first_ptr);
+        for _ in 1 .. 30 {
+            pool_alloc.alloc_raw(std::mem::size_of::<Particle>(), 16, 0).unwrap();
+        }
+
+        assert_eq!(pool_alloc.chunk_count(), 3, "Reset did not reuse the blocks of all chunks");
+    }
+
+    #[test]
+    fn slice_of_components() {
+        let pool_alloc = PoolAllocator::new(std::mem::size_of::<Particle>() * 4, 2, std::mem::align_of::<Particle>(), 0);
+
+        {
+            let particles = pool_alloc.alloc_slice_with(4, |i| Particle { lifetime: i as f32, speed: i * 2 }).unwrap();
+            let other_particles = pool_alloc.alloc_slice_with(3, |i| Particle { lifetime: 0.5, speed: i }).unwrap();
+
+            assert_eq!(particles[3].lifetime, 3.0);
+            assert_eq!(particles[3].speed, 6);
+            assert_eq!(other_particles.len(), 3);
+            assert!(pool_alloc.alloc_slice_with(1, |_| Particle { lifetime: 0.0, speed: 0 }).is_none());
+        }
+
+        assert!(pool_alloc.alloc_slice_with(4, |_| Particle { lifetime: 0.0, speed: 0 }).is_some(), "Slices did not return their blocks");
+    }
+
+    #[test]
+    fn try_alloc_reports_errors() {
+        let pool_alloc = PoolAllocator::with_capacity(std::mem::size_of::<Particle>(), 1, 8, 0, PoolCapacity::Exact);
+
+        assert_eq!(
+            pool_alloc.try_alloc_raw(64, 8, 0).unwrap_err(),
+            AllocError::ExceedsMaxElementSize { requested: 64, max_element_size: std::mem::size_of::<Particle>() }
+        );
+        assert_eq!(pool_alloc.try_alloc_raw(8, 16, 0).unwrap_err(), AllocError::InvalidAlignment { alignment: 16 });
+
+        pool_alloc.try_alloc_raw(8, 8, 0).unwrap();
+        assert_eq!(pool_alloc.try_alloc_raw(8, 8, 0).unwrap_err(), AllocError::OutOfMemory { requested: 8, available: 0 });
+    }
+
+    #[test]
+    fn stats_report_free_blocks() {
+        let pool_alloc = PoolAllocator::with_capacity(std::mem::size_of::<Particle>(), 4, 8, 0, PoolCapacity::Exact);
+        let block_size = pool_alloc.storage.borrow().min_block_size;
+
+        let first_mem = pool_alloc.alloc_raw(8, 8, 0).unwrap();
+        for _ in 1 .. 4 {
+            pool_alloc.alloc_raw(std::mem::size_of::<Particle>(), 8, 0).unwrap();
+        }
+        assert!(pool_alloc.alloc_raw(8, 8, 0).is_none());
+        assert!(pool_alloc.alloc_raw(64, 8, 0).is_none());
+
+        let stats = pool_alloc.stats();
+        assert_eq!(stats.capacity, 4 * block_size);
+        assert_eq!(stats.used_bytes, 8 + 3 * std::mem::size_of::<Particle>());
+        assert_eq!(stats.occupied_bytes(), 4 * block_size);
+        assert_eq!(stats.free_blocks, Some(0));
+        assert_eq!(stats.failed_requests, 2);
+
+        pool_alloc.dealloc_raw(first_mem);
+        let stats = pool_alloc.stats();
+        assert_eq!(stats.free_blocks, Some(1));
+        assert_eq!(stats.live_allocations, 3);
+        assert_eq!(stats.high_water_mark, 4 * block_size);
+    }
 }
\ No newline at end of file
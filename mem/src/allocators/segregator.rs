@@ -0,0 +1,172 @@
+use super::base::{ Allocator, MemoryBlock, OwningAllocator };
+use super::alloc_error::AllocError;
+use super::allocator_stats::AllocatorStats;
+
+///
+/// The Segregator combinator routes requests by their size. Requests of up to THRESHOLD bytes
+/// are served by the small allocator, e.g. a PoolAllocator, bigger ones by the large allocator.
+/// Blocks are routed back by asking the small allocator whether it owns the block, as the
+/// size of a block is not known when it is freed.
+///
+pub struct Segregator<const THRESHOLD: usize, S: OwningAllocator, L: Allocator> {
+    small:  S,
+    large:  L,
+}
+
+impl<const THRESHOLD: usize, S: OwningAllocator, L: Allocator> Segregator<THRESHOLD, S, L> {
+    pub fn new(small: S, large: L) -> Segregator<THRESHOLD, S, L> {
+        Segregator {
+            small,
+            large,
+        }
+    }
+
+    ///
+    /// Returns the biggest request in bytes that is served by the small allocator
+    ///
+    pub fn threshold(&self) -> usize {
+        THRESHOLD
+    }
+
+    pub fn small(&self) -> &S {
+        &self.small
+    }
+
+    pub fn large(&self) -> &L {
+        &self.large
+    }
+}
+
+impl<const THRESHOLD: usize, S: OwningAllocator, L: Allocator> Allocator for Segregator<THRESHOLD, S, L> {
+    fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
+        if size <= THRESHOLD {
+            self.small.alloc_raw(size, alignment, offset)
+        }
+        else {
+            self.large.alloc_raw(size, alignment, offset)
+        }
+    }
+
+    fn try_alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Result<MemoryBlock, AllocError> {
+        if size <= THRESHOLD {
+            self.small.try_alloc_raw(size, alignment, offset)
+        }
+        else {
+            self.large.try_alloc_raw(size, alignment, offset)
+        }
+    }
+
+    fn dealloc_raw(&self, memory: MemoryBlock) {
+        if self.small.owns(&memory) {
+            self.small.dealloc_raw(memory);
+        }
+        else {
+            self.large.dealloc_raw(memory);
+        }
+    }
+
+    fn reset(&self) {
+        self.small.reset();
+        self.large.reset();
+    }
+
+    fn get_allocation_size(&self, memory: &MemoryBlock) -> usize {
+        if self.small.owns(memory) {
+            self.small.get_allocation_size(memory)
+        }
+        else {
+            self.large.get_allocation_size(memory)
+        }
+    }
+
+    fn validate_block(&self, memory: &MemoryBlock) {
+        if self.small.owns(memory) {
+            self.small.validate_block(memory);
+        }
+        else {
+            self.large.validate_block(memory);
+        }
+    }
+
+    fn stats(&self) -> AllocatorStats {
+        self.small.stats().combine(&self.large.stats())
+    }
+}
+
+impl<const THRESHOLD: usize, S: OwningAllocator, L: OwningAllocator> OwningAllocator for Segregator<THRESHOLD, S, L> {
+    fn owns(&self, memory: &MemoryBlock) -> bool {
+        self.small.owns(memory) || self.large.owns(memory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::base::{ BasicAllocator, TypedAllocator };
+    use super::super::pool_allocator::PoolAllocator;
+    use super::super::free_list_allocator::FreeListAllocator;
+    use super::super::fallback_allocator::Fallback;
+    use super::super::stack_allocator::StackAllocator;
+    use super::super::system_allocator::SystemAllocator;
+
+    const KB: usize = 1024;
+
+    type SmallOrLarge = Segregator<64, PoolAllocator, FreeListAllocator>;
+
+    #[test]
+    fn routes_by_size() {
+        let segregator: SmallOrLarge = Segregator::new(PoolAllocator::new(64, 32, 16, 0), FreeListAllocator::new(16 * KB));
+
+        let small_mem = segregator.alloc_raw(64, 8, 0).unwrap();
+        let large_mem = segregator.alloc_raw(65, 8, 0).unwrap();
+
+        assert!(segregator.small().owns(&small_mem));
+        assert!(segregator.large().owns(&large_mem));
+        assert_eq!(segregator.get_allocation_size(&small_mem), 64);
+        assert_eq!(segregator.get_allocation_size(&large_mem), 65);
+    }
+
+    #[test]
+    fn dealloc_routes_to_owner() {
+        let segregator: SmallOrLarge = Segregator::new(PoolAllocator::new(64, 1, 16, 0), FreeListAllocator::new(16 * KB));
+
+        let small_mem = segregator.alloc_raw(32, 8, 0).unwrap();
+        let large_mem = segregator.alloc_raw(KB, 8, 0).unwrap();
+        assert!(segregator.alloc_raw(32, 8, 0).is_none(), "Small request was served by the large allocator");
+
+        let small_ptr = small_mem.ptr;
+        segregator.dealloc_raw(small_mem);
+        segregator.dealloc_raw(large_mem);
+        assert_eq!(segregator.alloc_raw(32, 8, 0).unwrap().ptr, small_ptr, "Block was not returned to the pool");
+    }
+
+    #[test]
+    fn reports_error_of_routed_allocator() {
+        let segregator: SmallOrLarge = Segregator::new(PoolAllocator::new(64, 32, 16, 0), FreeListAllocator::new(16 * KB));
+
+        assert_eq!(segregator.try_alloc_raw(32, 32, 0).unwrap_err(), AllocError::InvalidAlignment { alignment: 32 });
+        assert!(segregator.try_alloc_raw(KB, 32, 0).is_ok(), "Large request was routed to the pool");
+    }
+
+    #[test]
+    fn nested_combinators() {
+        let allocator: Segregator<64, PoolAllocator, Fallback<StackAllocator, SystemAllocator>> = Segregator::new(
+            PoolAllocator::new(64, 32, 16, 0),
+            Fallback::new(StackAllocator::new(KB), SystemAllocator::new())
+        );
+
+        let small_mem = allocator.alloc_raw(16, 8, 0).unwrap();
+        let stack_mem = allocator.alloc_raw(512, 8, 0).unwrap();
+        let system_mem = allocator.alloc_raw(2 * KB, 8, 0).unwrap();
+
+        assert!(allocator.small().owns(&small_mem));
+        assert!(allocator.large().primary().owns(&stack_mem));
+        assert!(allocator.large().secondary().owns(&system_mem));
+        assert_eq!(allocator.get_allocation_size(&system_mem), 2 * KB);
+
+        allocator.dealloc_raw(system_mem);
+        allocator.dealloc_raw(stack_mem);
+        allocator.dealloc_raw(small_mem);
+        assert_eq!(allocator.large().secondary().allocation_count(), 0);
+    }
+}
\ No newline at end of file
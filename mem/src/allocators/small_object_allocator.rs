@@ -0,0 +1,369 @@
+use std;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use spark_core::pointer_util;
+
+use super::pool_allocator::PoolAllocator;
+use super::base::{ Allocator, MemoryBlock, BasicAllocator, TypedAllocator, OwningAllocator };
+use super::alloc_error::AllocError;
+use super::allocator_stats::AllocatorStats;
+
+const DEFAULT_SIZE_CLASSES: [usize; 6] = [8, 16, 32, 64, 128, 256];
+const DEFAULT_POOL_SIZE: usize = 64 * 1024;
+
+// Size classes are aligned to their size, up to this alignment
+const MAX_SIZE_CLASS_ALIGNMENT: usize = 16;
+
+///
+/// A SizeClass serves all requests up to its element size with a set of
+/// equally sized pools, a new pool is added once all of them are exhausted
+///
+struct SizeClass {
+    pub element_size:       usize,
+    pub element_alignment:  usize,
+    pub elements_per_pool:  usize,
+    pub pools:              Vec<PoolAllocator>,
+}
+
+///
+/// The SmallObjectAllocatorStorage is a type that is used to
+/// expose a safe API for user allocations where the Allocator
+/// itself is just mutating the Storage backing it
+///
+struct SmallObjectAllocatorStorage {
+    pub size_classes:   Vec<SizeClass>,
+    // Maps the first address of every pool to its size class and index inside of it
+    pub pool_lookup:    BTreeMap<usize, (usize, usize)>,
+    pub failed_requests: usize,
+}
+
+impl SmallObjectAllocatorStorage {
+    ///
+    /// Returns the pool owning `memory`, or None if it was not allocated from a size class
+    ///
+    fn find_pool(&self, memory: &MemoryBlock) -> Option<&PoolAllocator> {
+        match self.pool_lookup.range(.. memory.ptr as usize + 1).next_back() {
+            Some((_, &(class_idx, pool_idx))) => {
+                let pool = &self.size_classes[class_idx].pools[pool_idx];
+                if pool.owns(memory) { Some(pool) } else { None }
+            },
+            None => None,
+        }
+    }
+}
+
+///
+/// The SmallObjectAllocator routes every request to the smallest size class it fits
+/// into, each size class being a growing set of PoolAllocators. Requests that are larger
+/// than the biggest size class, or need a stronger alignment or an offset, fall through to
+/// the backing allocator. On deallocation the owning size class is looked up from the
+/// address of the block, so no additional meta-data is stored alongside of allocations.
+///
+pub struct SmallObjectAllocator<B: Allocator> {
+    storage: RefCell<SmallObjectAllocatorStorage>,
+    backing: B,
+}
+
+impl<B: Allocator> SmallObjectAllocator<B> {
+    ///
+    /// Creates a small object allocator with size classes from 8 to 256 bytes
+    ///
+    pub fn new(backing: B) -> SmallObjectAllocator<B> {
+        SmallObjectAllocator::with_size_classes(&DEFAULT_SIZE_CLASSES, DEFAULT_POOL_SIZE, backing)
+    }
+
+    ///
+    /// Creates a small object allocator with the given ascending size classes. Every
+    /// pool of a size class provides room for `pool_size / size_class` elements
+    ///
+    pub fn with_size_classes(size_classes: &[usize], pool_size: usize, backing: B) -> SmallObjectAllocator<B> {
+        {
+            let classes_are_ascending = size_classes.windows(2).all(|pair| pair[0] < pair[1]);
+            debug_assert!(classes_are_ascending, "Size classes have to be in ascending order");
+            let classes_are_not_empty = size_classes.iter().all(|&size_class| size_class > 0);
+            debug_assert!(classes_are_not_empty, "Size classes are not allowed to be 0");
+        }
+
+        let size_classes = size_classes.iter().map(|&size_class| {
+            SizeClass {
+                element_size:       size_class,
+                element_alignment:  std::cmp::min(size_class.next_power_of_two(), MAX_SIZE_CLASS_ALIGNMENT),
+                elements_per_pool:  std::cmp::max(pool_size / size_class, 1),
+                pools:              Vec::new(),
+            }
+        }).collect();
+
+        SmallObjectAllocator {
+            storage: RefCell::new(SmallObjectAllocatorStorage {
+                size_classes,
+                pool_lookup: BTreeMap::new(),
+                failed_requests: 0,
+            }),
+            backing,
+        }
+    }
+
+    ///
+    /// Returns the amount of pools the size class serving `size` bytes allocated so far
+    ///
+    pub fn pool_count(&self, size: usize) -> usize {
+        let storage = self.storage.borrow();
+        match storage.size_classes.iter().find(|class| size <= class.element_size) {
+            Some(class) => class.pools.len(),
+            None => 0,
+        }
+    }
+
+    ///
+    /// Returns the allocator serving requests that do not fit into any size class
+    ///
+    pub fn backing(&self) -> &B {
+        &self.backing
+    }
+}
+
+impl<B> BasicAllocator for SmallObjectAllocator<B>
+where B: Allocator + BasicAllocator<AllocatorImplementation = B>,
+{
+    type AllocatorImplementation = SmallObjectAllocator<B>;
+
+    ///
+    /// Creates a small object allocator with the default size classes,
+    /// backed by an allocator of the given size
+    ///
+    fn try_new(size: usize) -> Result<Self::AllocatorImplementation, AllocError> {
+        Ok(SmallObjectAllocator::new(B::try_new(size)?))
+    }
+}
+
+impl<B: Allocator> Allocator for SmallObjectAllocator<B> {
+    fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
+        self.try_alloc_raw(size, alignment, offset).ok()
+    }
+
+    ///
+    /// Requests falling through to the backing allocator report its errors, adding
+    /// a pool to an exhausted size class fails if the OS cannot provide the memory
+    ///
+    fn try_alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Result<MemoryBlock, AllocError> {
+        if !pointer_util::is_pot(alignment) {
+            self.storage.borrow_mut().failed_requests += 1;
+            return Err(AllocError::InvalidAlignment { alignment });
+        }
+
+        let class_idx = if offset == 0 {
+            let storage = self.storage.borrow();
+            storage.size_classes.iter().position(|class| size <= class.element_size && alignment <= class.element_alignment)
+        }
+        else {
+            None
+        };
+
+        let class_idx = match class_idx {
+            Some(class_idx) => class_idx,
+            None => return self.backing.try_alloc_raw(size, alignment, offset),
+        };
+
+        let mut storage = self.storage.borrow_mut();
+
+        // Recently added pools are the most likely to have free elements left
+        let ptr = storage.size_classes[class_idx].pools.iter().rev()
+            .filter_map(|pool| pool.alloc_raw(size, alignment, 0).map(|block| block.ptr))
+            .next();
+
+        if let Some(ptr) = ptr {
+            return Ok(MemoryBlock::new(ptr));
+        }
+
+        let pool = {
+            let class = &storage.size_classes[class_idx];
+            PoolAllocator::try_new(class.element_size, class.elements_per_pool, class.element_alignment, 0)
+        };
+
+        let pool = match pool {
+            Ok(pool) => pool,
+            Err(error) => {
+                storage.failed_requests += 1;
+                return Err(error);
+            },
+        };
+
+        let ptr = pool.try_alloc_raw(size, alignment, 0).map(|block| block.ptr);
+        storage.size_classes[class_idx].pools.push(pool);
+
+        let pool_idx = storage.size_classes[class_idx].pools.len() - 1;
+        let pool_begin = storage.size_classes[class_idx].pools[pool_idx].mem_begin() as usize;
+        storage.pool_lookup.insert(pool_begin, (class_idx, pool_idx));
+
+        if ptr.is_err() {
+            storage.failed_requests += 1;
+        }
+
+        ptr.map(MemoryBlock::new)
+    }
+
+    fn dealloc_raw(&self, memory: MemoryBlock) {
+        let storage = self.storage.borrow();
+        match storage.find_pool(&memory) {
+            Some(pool) => pool.dealloc_raw(memory),
+            None => self.backing.dealloc_raw(memory),
+        }
+    }
+
+    ///
+    /// Resets all pools and the backing allocator, pools that were added keep their memory
+    ///
+    fn reset(&self) {
+        let storage = self.storage.borrow();
+        for class in &storage.size_classes {
+            for pool in &class.pools {
+                pool.reset();
+            }
+        }
+
+        self.backing.reset();
+    }
+
+    fn get_allocation_size(&self, memory: &MemoryBlock) -> usize {
+        let storage = self.storage.borrow();
+        match storage.find_pool(memory) {
+            Some(pool) => pool.get_allocation_size(memory),
+            None => self.backing.get_allocation_size(memory),
+        }
+    }
+
+    fn validate_block(&self, memory: &MemoryBlock) {
+        let storage = self.storage.borrow();
+        match storage.find_pool(memory) {
+            Some(pool) => pool.validate_block(memory),
+            None => self.backing.validate_block(memory),
+        }
+    }
+
+    ///
+    /// Sums up the stats of all pools and the backing allocator. Pools running dry are not
+    /// counted as failed requests, as the request is then served by another pool
+    ///
+    fn stats(&self) -> AllocatorStats {
+        let storage = self.storage.borrow();
+        let own_stats = AllocatorStats { failed_requests: storage.failed_requests, ..AllocatorStats::default() };
+
+        storage.size_classes.iter()
+            .flat_map(|class| class.pools.iter())
+            .map(|pool| AllocatorStats { failed_requests: 0, ..pool.stats() })
+            .fold(own_stats, |total, pool_stats| total.combine(&pool_stats))
+            .combine(&self.backing.stats())
+    }
+}
+
+impl<B: OwningAllocator> OwningAllocator for SmallObjectAllocator<B> {
+    fn owns(&self, memory: &MemoryBlock) -> bool {
+        self.storage.borrow().find_pool(memory).is_some() || self.backing.owns(memory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::free_list_allocator::FreeListAllocator;
+
+    const KB: usize = 1024;
+    const MB: usize = KB * 1024;
+
+    fn small_object_allocator() -> SmallObjectAllocator<FreeListAllocator> {
+        SmallObjectAllocator::new(FreeListAllocator::new(MB))
+    }
+
+    #[test]
+    fn single_allocation() {
+        let small_alloc = small_object_allocator();
+        let mem = small_alloc.alloc_raw(24, 8, 0);
+        assert!(mem.is_some());
+        assert!(pointer_util::is_aligned_to(mem.unwrap().ptr, 8));
+        assert_eq!(small_alloc.pool_count(24), 1);
+    }
+
+    #[test]
+    fn routes_to_size_classes() {
+        let small_alloc = small_object_allocator();
+        let mem_0 = small_alloc.alloc_raw(8, 8, 0).unwrap();
+        let mem_1 = small_alloc.alloc_raw(100, 16, 0).unwrap();
+
+        assert_eq!(small_alloc.pool_count(8), 1);
+        assert_eq!(small_alloc.pool_count(16), 0);
+        assert_eq!(small_alloc.pool_count(128), 1);
+        assert_eq!(small_alloc.get_allocation_size(&mem_0), 8);
+        assert_eq!(small_alloc.get_allocation_size(&mem_1), 100);
+    }
+
+    #[test]
+    fn large_allocations_fall_through_to_backing() {
+        let small_alloc = small_object_allocator();
+        let mem_0 = small_alloc.alloc_raw(KB, 8, 0).unwrap();
+        let mem_1 = small_alloc.alloc_raw(16, 8, 4).unwrap();
+
+        for &size_class in DEFAULT_SIZE_CLASSES.iter() {
+            assert_eq!(small_alloc.pool_count(size_class), 0, "Request was served by a size class");
+        }
+
+        assert_eq!(small_alloc.get_allocation_size(&mem_0), KB);
+        let mem_0_ptr = mem_0.ptr;
+        small_alloc.dealloc_raw(mem_0);
+        small_alloc.dealloc_raw(mem_1);
+        assert_eq!(small_alloc.backing().alloc_raw(KB, 8, 0).unwrap().ptr, mem_0_ptr);
+    }
+
+    #[test]
+    fn exhausted_size_class_grows() {
+        let small_alloc: SmallObjectAllocator<FreeListAllocator> = SmallObjectAllocator::with_size_classes(&[16, 64], 16 * 4, FreeListAllocator::new(MB));
+
+        let mut blocks = Vec::new();
+        for i in 0 .. 100usize {
+            let mem = small_alloc.alloc_raw(16, 8, 0).unwrap();
+            unsafe { *(mem.ptr as *mut usize) = i; }
+            blocks.push(mem);
+        }
+
+        assert!(small_alloc.pool_count(16) > 1, "Size class did not add another pool");
+        for (i, mem) in blocks.iter().enumerate() {
+            assert_eq!(unsafe { *(mem.ptr as *const usize) }, i, "Growing a size class corrupted earlier allocations");
+        }
+    }
+
+    #[test]
+    fn dealloc_finds_owning_pool() {
+        let small_alloc: SmallObjectAllocator<FreeListAllocator> = SmallObjectAllocator::with_size_classes(&[16, 64], 16 * 4, FreeListAllocator::new(MB));
+
+        let mut blocks = Vec::new();
+        for _ in 0 .. 20 {
+            blocks.push(small_alloc.alloc_raw(16, 8, 0).unwrap());
+            blocks.push(small_alloc.alloc_raw(48, 8, 0).unwrap());
+        }
+
+        let pool_count = small_alloc.pool_count(16) + small_alloc.pool_count(64);
+        let mut freed_ptrs: Vec<*mut u8> = blocks.iter().map(|mem| mem.ptr).collect();
+        for mem in blocks.drain(..) {
+            small_alloc.dealloc_raw(mem);
+        }
+
+        for _ in 0 .. 20 {
+            let mem_0 = small_alloc.alloc_raw(16, 8, 0).unwrap();
+            let mem_1 = small_alloc.alloc_raw(48, 8, 0).unwrap();
+            assert!(freed_ptrs.contains(&mem_0.ptr) && freed_ptrs.contains(&mem_1.ptr), "Freed blocks were not reused");
+            freed_ptrs.retain(|&ptr| ptr != mem_0.ptr && ptr != mem_1.ptr);
+        }
+
+        assert_eq!(small_alloc.pool_count(16) + small_alloc.pool_count(64), pool_count);
+    }
+
+    #[test]
+    fn reset_whole_allocator() {
+        let small_alloc = small_object_allocator();
+        let mem_0 = small_alloc.alloc_raw(32, 8, 0).unwrap();
+        small_alloc.alloc_raw(32, 8, 0).unwrap();
+        let mem_0_ptr = mem_0.ptr;
+        small_alloc.reset();
+
+        assert_eq!(small_alloc.alloc_raw(32, 8, 0).unwrap().ptr, mem_0_ptr);
+    }
+}
\ No newline at end of file
@@ -2,8 +2,10 @@ use std;
 use std::cell::RefCell;
 use spark_core::pointer_util;
 
-use super::super::virtual_mem;
-use super::base::{ Allocator, MemoryBlock, BasicAllocator };
+use super::super::virtual_mem::{ VirtualRegion, PageMode };
+use super::base::{ Allocator, MemoryBlock, BasicAllocator, Marker, MarkerAllocator, OwningAllocator };
+use super::alloc_error::AllocError;
+use super::allocator_stats::{ AllocatorStats, StatsCounters };
 
 ///
 /// The AllocationHeader struct describes meta-data
@@ -13,8 +15,10 @@ use super::base::{ Allocator, MemoryBlock, BasicAllocator };
 struct AllocationHeader {
     pub allocation_offset:  u32,
     pub allocation_size:    u32,
-    #[cfg(stack_alloc_lifo_check)]
+    #[cfg(feature = "stack_alloc_lifo_check")]
     pub allocation_id:      u32,
+    #[cfg(feature = "epoch_check")]
+    pub epoch:              u32,
 }
 
 const ALLOCATION_META_SIZE: usize = std::mem::size_of::<AllocationHeader>();
@@ -26,11 +30,15 @@ const ALLOCATION_META_SIZE: usize = std::mem::size_of::<AllocationHeader>();
 ///
 struct StackAllocatorStorage {
     pub use_internal_mem:   bool,
-    pub mem_begin:          *mut u8,
+    pub region:             VirtualRegion,
     pub mem_end:            *mut u8,
     pub current_ptr:        *mut u8,
-    #[cfg(stack_alloc_lifo_check)]
+    pub scope_depth:        usize,
+    pub stats:              StatsCounters,
+    #[cfg(feature = "stack_alloc_lifo_check")]
     pub allocation_id:      u32,
+    #[cfg(feature = "epoch_check")]
+    pub epoch:              u32,
 }
 
 impl StackAllocatorStorage {
@@ -38,26 +46,23 @@ impl StackAllocatorStorage {
     /// Creates a new stack allocator storage and allocates the memory
     /// block requested by the allocator from the virtual memory API
     ///
-    fn new(size: usize) -> StackAllocatorStorage {
+    fn new(size: usize, page_mode: PageMode) -> Result<StackAllocatorStorage, AllocError> {
 
-        let virtual_mem = match virtual_mem::reserve_address_space(size) {
-            Some(address) => address,
-            None => std::ptr::null_mut(),
-        };
-
-        let physical_address_space = match virtual_mem::commit_physical_memory(virtual_mem, size) {
-            Some(address) => address,
-            None => std::ptr::null_mut(),
-        };
+        let mut region = VirtualRegion::reserve_with_page_mode(size, page_mode).ok_or(AllocError::ReserveFailed { size })?;
+        let physical_address_space = region.commit(0, size).ok_or(AllocError::CommitFailed { size })?;
 
-        StackAllocatorStorage {
+        Ok(StackAllocatorStorage {
             use_internal_mem: true,
-            mem_begin: physical_address_space,
+            region,
             mem_end: unsafe { physical_address_space.offset(size as isize) },
             current_ptr: physical_address_space,
-            #[cfg(stack_alloc_lifo_check)]
+            scope_depth: 0,
+            stats: StatsCounters::default(),
+            #[cfg(feature = "stack_alloc_lifo_check")]
             allocation_id: 0,
-        }
+            #[cfg(feature = "epoch_check")]
+            epoch: 0,
+        })
     }
 }
 
@@ -65,24 +70,54 @@ pub struct StackAllocator {
     storage: RefCell<StackAllocatorStorage>,
 }
 
-impl BasicAllocator for StackAllocator {
-    type AllocatorImplementation = StackAllocator;
+// The stack pointers only point into the region owned by the allocator,
+// so it can be moved to another thread as a whole
+unsafe impl Send for StackAllocator {}
+
+impl StackAllocator {
+    ///
+    /// Creates a stack allocator whose memory is backed by pages of the given mode,
+    /// falling back to smaller pages if the system cannot provide them
+    ///
+    pub fn with_page_mode(size: usize, page_mode: PageMode) -> StackAllocator {
+        StackAllocator::try_with_page_mode(size, page_mode).expect("Could not create the allocator")
+    }
 
-    fn new(size: usize) -> Self::AllocatorImplementation {
+    ///
+    /// Like `with_page_mode`, but returns an error if the memory could not be reserved or committed
+    ///
+    pub fn try_with_page_mode(size: usize, page_mode: PageMode) -> Result<StackAllocator, AllocError> {
         debug_assert!(size > 0usize, "Size is not allowed to be 0");
 
-        StackAllocator {
-            storage: RefCell::new(StackAllocatorStorage::new(size)),
-        }
+        Ok(StackAllocator {
+            storage: RefCell::new(StackAllocatorStorage::new(size, page_mode)?),
+        })
+    }
+}
+
+impl BasicAllocator for StackAllocator {
+    type AllocatorImplementation = StackAllocator;
+
+    fn try_new(size: usize) -> Result<Self::AllocatorImplementation, AllocError> {
+        StackAllocator::try_with_page_mode(size, PageMode::Default)
     }
 }
 
 impl Allocator for StackAllocator {
     fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
-        debug_assert!(pointer_util::is_pot(alignment), "Alignment needs to be a power of two");
+        self.try_alloc_raw(size, alignment, offset).ok()
+    }
 
+    fn try_alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Result<MemoryBlock, AllocError> {
         let mut allocator_storage = self.storage.borrow_mut();
-        let current_ptr_offset = allocator_storage.current_ptr as usize - allocator_storage.mem_begin as usize;
+
+        if !pointer_util::is_pot(alignment) {
+            allocator_storage.stats.on_failed_request();
+            return Err(AllocError::InvalidAlignment { alignment });
+        }
+
+        let previous_ptr = allocator_storage.current_ptr;
+        let current_ptr_offset = allocator_storage.current_ptr as usize - allocator_storage.region.base() as usize;
         let offset_before_alignment = offset + ALLOCATION_META_SIZE;
 
         unsafe {
@@ -91,10 +126,15 @@ impl Allocator for StackAllocator {
             allocator_storage.current_ptr = allocator_storage.current_ptr.offset(offset_before_alignment as isize);
             allocator_storage.current_ptr = pointer_util::align_top(allocator_storage.current_ptr, alignment) as *mut u8;
 
-            // If we overflow we cannot fulfill this allocation and return None
+            // If we overflow we cannot fulfill this allocation and return an error
             let allocation_overflows = allocator_storage.current_ptr.offset((size - offset) as isize) > allocator_storage.mem_end;
             if  allocation_overflows {
-                return None;
+                allocator_storage.current_ptr = previous_ptr;
+                allocator_storage.stats.on_failed_request();
+                return Err(AllocError::OutOfMemory {
+                    requested: size,
+                    available: allocator_storage.mem_end as usize - previous_ptr as usize,
+                });
             }
 
             allocator_storage.current_ptr = allocator_storage.current_ptr.offset(-(offset_before_alignment as isize));
@@ -105,20 +145,29 @@ impl Allocator for StackAllocator {
             // Write allocation meta data
             as_alloc_header.allocation_offset = current_ptr_offset as u32;
             as_alloc_header.allocation_size = size as u32;
-            #[cfg(stack_alloc_lifo_check)]
+            #[cfg(feature = "stack_alloc_lifo_check")]
             {
                 allocator_storage.allocation_id += 1;
                 as_alloc_header.allocation_id = allocator_storage.allocation_id;
             }
+            #[cfg(feature = "epoch_check")]
+            {
+                as_alloc_header.epoch = allocator_storage.epoch;
+            }
 
             user_ptr = user_ptr.offset(ALLOCATION_META_SIZE as isize);
             allocator_storage.current_ptr = allocator_storage.current_ptr.offset((size + ALLOCATION_META_SIZE) as isize);
 
-            Some(MemoryBlock::new(user_ptr))
+            let occupied_size = allocator_storage.current_ptr as usize - previous_ptr as usize;
+            allocator_storage.stats.on_alloc(size, occupied_size);
+
+            Ok(MemoryBlock::new(user_ptr))
         }
     }
 
     fn dealloc_raw(&self, memory: MemoryBlock) {
+        self.validate_block(&memory);
+
         let raw_mem = memory.ptr;
 
         unsafe {
@@ -126,27 +175,38 @@ impl Allocator for StackAllocator {
             let alloc_header = &mut *(raw_mem.offset(-(ALLOCATION_META_SIZE as isize)) as *mut AllocationHeader);
             
 
-            #[cfg(stack_alloc_lifo_check)]
+            #[cfg(feature = "stack_alloc_lifo_check")]
             {
                 let was_freed_in_lifo_fashion = alloc_header.allocation_id == storage.allocation_id;
-                debug_assert!(was_freed_in_lifo_fashion, "Stack allocator does only support LIFO fashioned freeing");
+                assert!(was_freed_in_lifo_fashion, "Stack allocator does only support LIFO fashioned freeing");
                 storage.allocation_id -= 1;
             }
 
-            storage.current_ptr = storage.mem_begin.offset(alloc_header.allocation_offset as isize);
+            let previous_ptr = storage.current_ptr;
+            storage.current_ptr = storage.region.base().offset(alloc_header.allocation_offset as isize);
+
+            let occupied_size = previous_ptr as usize - storage.current_ptr as usize;
+            storage.stats.on_dealloc(alloc_header.allocation_size as usize, occupied_size);
         }
     }
 
     fn reset(&self) {
         let mut storage = self.storage.borrow_mut();
-        storage.current_ptr = storage.mem_begin;
-        #[cfg(stack_alloc_lifo_check)]
+        storage.current_ptr = storage.region.base();
+        storage.stats.on_reset();
+        #[cfg(feature = "stack_alloc_lifo_check")]
         {
             storage.allocation_id = 0;
         }
+        #[cfg(feature = "epoch_check")]
+        {
+            storage.epoch = storage.epoch.wrapping_add(1);
+        }
     }
 
     fn get_allocation_size(&self, memory: &MemoryBlock) -> usize {
+        self.validate_block(memory);
+
         let alloc_header: &mut AllocationHeader;
 
         unsafe {
@@ -156,6 +216,115 @@ impl Allocator for StackAllocator {
 
         alloc_header.allocation_size as usize
     }
+
+    ///
+    /// Grows or shrinks the topmost allocation in place. Other blocks are shrunk in place
+    /// as well, but cannot grow without breaking the LIFO order of the stack. None is
+    /// returned for them and the block stays valid
+    ///
+    fn realloc_raw(&self, memory: &MemoryBlock, new_size: usize, alignment: usize) -> Option<MemoryBlock> {
+        debug_assert!(pointer_util::is_pot(alignment), "Alignment needs to be a power of two");
+
+        let old_size = self.get_allocation_size(memory);
+
+        if pointer_util::is_aligned_to(memory.ptr, alignment) {
+            let mut storage = self.storage.borrow_mut();
+            let alloc_header = unsafe { &mut *(memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *mut AllocationHeader) };
+            let block_end = unsafe { memory.ptr.offset(old_size as isize) };
+            let new_block_end = unsafe { memory.ptr.offset(new_size as isize) };
+
+            let is_topmost_allocation = block_end == storage.current_ptr;
+            if is_topmost_allocation && new_block_end <= storage.mem_end {
+                alloc_header.allocation_size = new_size as u32;
+                storage.current_ptr = new_block_end;
+                storage.stats.on_resize(old_size, old_size, new_size, new_size);
+                return Some(MemoryBlock::new(memory.ptr));
+            }
+
+            if new_size <= old_size {
+                alloc_header.allocation_size = new_size as u32;
+                storage.stats.on_resize(old_size, old_size, new_size, old_size);
+                return Some(MemoryBlock::new(memory.ptr));
+            }
+        }
+
+        None
+    }
+
+    #[cfg(feature = "epoch_check")]
+    fn validate_block(&self, memory: &MemoryBlock) {
+        let alloc_header = unsafe { &*(memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *const AllocationHeader) };
+        let block_is_current = alloc_header.epoch == self.storage.borrow().epoch;
+        assert!(block_is_current, "MemoryBlock was invalidated by a reset of its allocator");
+    }
+
+    fn stats(&self) -> AllocatorStats {
+        let storage = self.storage.borrow();
+        storage.stats.snapshot(storage.mem_end as usize - storage.region.base() as usize, None)
+    }
+}
+
+impl MarkerAllocator for StackAllocator {
+    fn get_marker(&self) -> Marker {
+        let storage = self.storage.borrow();
+
+        #[cfg(feature = "stack_alloc_lifo_check")]
+        let allocation_id = storage.allocation_id;
+        #[cfg(not(feature = "stack_alloc_lifo_check"))]
+        let allocation_id = 0;
+
+        Marker {
+            offset: storage.current_ptr as usize - storage.region.base() as usize,
+            allocation_id,
+            scope_depth: storage.scope_depth,
+            used_bytes: storage.stats.used_bytes,
+            live_allocations: storage.stats.live_allocations,
+        }
+    }
+
+    ///
+    /// Frees all allocations issued after `marker` was taken at once instead
+    /// of deallocating them one by one in LIFO order
+    ///
+    fn free_to_marker(&self, marker: Marker) {
+        let mut storage = self.storage.borrow_mut();
+
+        {
+            let current_offset = storage.current_ptr as usize - storage.region.base() as usize;
+            let marker_below_current_ptr = marker.offset <= current_offset;
+            debug_assert!(marker_below_current_ptr, "Marker lies above the current top of the allocator");
+        }
+
+        storage.current_ptr = unsafe { storage.region.base().offset(marker.offset as isize) };
+        storage.stats.rewind(marker.used_bytes, marker.offset, marker.live_allocations);
+        #[cfg(feature = "stack_alloc_lifo_check")]
+        {
+            storage.allocation_id = marker.allocation_id;
+        }
+    }
+
+    fn begin_scope(&self) -> Marker {
+        self.storage.borrow_mut().scope_depth += 1;
+        self.get_marker()
+    }
+
+    fn end_scope(&self, marker: Marker) {
+        {
+            let scope_is_innermost = marker.scope_depth == self.storage.borrow().scope_depth;
+            debug_assert!(scope_is_innermost, "Allocator scopes have to be released in nested order");
+        }
+
+        self.storage.borrow_mut().scope_depth -= 1;
+        self.free_to_marker(marker);
+    }
+}
+
+impl OwningAllocator for StackAllocator {
+    fn owns(&self, memory: &MemoryBlock) -> bool {
+        let storage = self.storage.borrow();
+        let ptr = memory.ptr as *const u8;
+        ptr >= storage.region.base() as *const u8 && ptr < storage.mem_end as *const u8
+    }
 }
 
 #[cfg(test)]
@@ -281,7 +450,7 @@ mod tests
             pub id: usize,
         }
         
-        let stack_allocator: StackAllocator = StackAllocator::new(std::mem::size_of::<Data>() + 8);
+        let stack_allocator: StackAllocator = StackAllocator::new(std::mem::size_of::<Data>() + ALLOCATION_META_SIZE);
         {
             let mut data_box = stack_allocator.alloc(Data { result: 1.0, id: 1 }, 1, 0).unwrap();
             let data = &mut *data_box;
@@ -300,4 +469,222 @@ mod tests
         assert!(data_box.is_some(), "Second allocation failed, hence first AllocatorBox did not deallocate its MemoryBlock");
     }
 
+    #[test]
+    fn free_to_marker() {
+        let stack_allocator = StackAllocator::new(10 * MB);
+        stack_allocator.alloc_raw(256, 16, 0).unwrap();
+        let marker = stack_allocator.get_marker();
+        let mem_raw_0 = stack_allocator.alloc_raw(256, 16, 0).unwrap();
+        stack_allocator.alloc_raw(256, 16, 0).unwrap();
+        stack_allocator.alloc_raw(256, 16, 0).unwrap();
+
+        stack_allocator.free_to_marker(marker);
+
+        let mem_raw_1 = stack_allocator.alloc_raw(256, 16, 0).unwrap();
+        assert_eq!(mem_raw_0.ptr, mem_raw_1.ptr, "Allocator was not rewound to the marker");
+    }
+
+    #[test]
+    fn scope_rewinds_on_drop() {
+        let stack_allocator = StackAllocator::new(10 * MB);
+        let marker = stack_allocator.get_marker();
+
+        {
+            let scope = stack_allocator.scope();
+            scope.alloc_raw(256, 16, 0).unwrap();
+            scope.alloc_raw(256, 16, 0).unwrap();
+        }
+
+        assert_eq!(stack_allocator.get_marker(), marker, "Scope did not free its allocations");
+    }
+
+    #[cfg(feature = "stack_alloc_lifo_check")]
+    #[test]
+    #[should_panic(expected = "Stack allocator does only support LIFO fashioned freeing")]
+    fn assert_out_of_order_deallocation() {
+        let stack_allocator = StackAllocator::new(10 * MB);
+        let raw_mem_0 = stack_allocator.alloc_raw(256, 16, 0).unwrap();
+        let _raw_mem_1 = stack_allocator.alloc_raw(256, 16, 0).unwrap();
+        stack_allocator.dealloc_raw(raw_mem_0);
+    }
+
+    #[cfg(feature = "stack_alloc_lifo_check")]
+    #[test]
+    fn lifo_deallocation_after_reset_and_marker() {
+        let stack_allocator = StackAllocator::new(10 * MB);
+        stack_allocator.alloc_raw(256, 16, 0).unwrap();
+        stack_allocator.reset();
+
+        let raw_mem_0 = stack_allocator.alloc_raw(256, 16, 0).unwrap();
+        let marker = stack_allocator.get_marker();
+        stack_allocator.alloc_raw(256, 16, 0).unwrap();
+        stack_allocator.free_to_marker(marker);
+
+        let raw_mem_1 = stack_allocator.alloc_raw(256, 16, 0).unwrap();
+        stack_allocator.dealloc_raw(raw_mem_1);
+        stack_allocator.dealloc_raw(raw_mem_0);
+    }
+
+    #[cfg(feature = "epoch_check")]
+    #[test]
+    #[should_panic(expected = "MemoryBlock was invalidated by a reset of its allocator")]
+    fn dealloc_stale_block_after_reset() {
+        let stack_allocator = StackAllocator::new(10 * MB);
+        let raw_mem = stack_allocator.alloc_raw(256, 16, 0).unwrap();
+        stack_allocator.reset();
+
+        stack_allocator.dealloc_raw(raw_mem);
+    }
+
+    #[test]
+    fn realloc_grows_topmost_in_place() {
+        let stack_alloc = StackAllocator::new(KB);
+
+        let mem = stack_alloc.alloc_raw(64, 8, 0).unwrap();
+        unsafe { *(mem.ptr as *mut u64) = 0xDEADBEEF };
+
+        let grown_mem = stack_alloc.realloc_raw(&mem, 256, 8).unwrap();
+        assert_eq!(grown_mem.ptr, mem.ptr, "Topmost allocation was moved");
+        assert_eq!(stack_alloc.get_allocation_size(&grown_mem), 256);
+        assert_eq!(unsafe { *(grown_mem.ptr as *const u64) }, 0xDEADBEEF);
+
+        let next_mem = stack_alloc.alloc_raw(8, 8, 0).unwrap();
+        assert!(next_mem.ptr as usize >= grown_mem.ptr as usize + 256, "Grown block overlaps the next allocation");
+        assert!(stack_alloc.realloc_raw(&grown_mem, 2 * KB, 8).is_none());
+    }
+
+    #[test]
+    fn realloc_does_not_grow_buried_block() {
+        let stack_alloc = StackAllocator::new(KB);
+
+        let buried_mem = stack_alloc.alloc_raw(64, 8, 0).unwrap();
+        unsafe { *(buried_mem.ptr as *mut u64) = 0xDEADBEEF };
+        let top_mem = stack_alloc.alloc_raw(64, 8, 0).unwrap();
+        let used_bytes = stack_alloc.stats().used_bytes;
+
+        assert!(stack_alloc.realloc_raw(&buried_mem, 128, 8).is_none(), "Buried block was grown");
+        assert_eq!(stack_alloc.get_allocation_size(&buried_mem), 64);
+        assert_eq!(unsafe { *(buried_mem.ptr as *const u64) }, 0xDEADBEEF);
+        assert_eq!(stack_alloc.stats().used_bytes, used_bytes);
+
+        let shrunk_mem = stack_alloc.realloc_raw(&buried_mem, 16, 8).unwrap();
+        assert_eq!(shrunk_mem.ptr, buried_mem.ptr);
+        assert_eq!(stack_alloc.get_allocation_size(&shrunk_mem), 16);
+
+        stack_alloc.dealloc_raw(top_mem);
+        stack_alloc.dealloc_raw(shrunk_mem);
+        assert_eq!(stack_alloc.stats().live_allocations, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "stack_alloc_lifo_check")]
+    fn realloc_keeps_lifo_order() {
+        let stack_alloc = StackAllocator::new(KB);
+
+        let buried_mem = stack_alloc.alloc_raw(64, 8, 0).unwrap();
+        let top_mem = stack_alloc.alloc_raw(64, 8, 0).unwrap();
+
+        assert!(stack_alloc.realloc_raw(&buried_mem, 128, 8).is_none());
+        let buried_mem = stack_alloc.realloc_raw(&buried_mem, 32, 8).unwrap();
+        let top_mem = stack_alloc.realloc_raw(&top_mem, 128, 8).unwrap();
+
+        // Freeing in LIFO order asserts if the resized blocks got ids of their own
+        stack_alloc.dealloc_raw(top_mem);
+        stack_alloc.dealloc_raw(buried_mem);
+        assert!(stack_alloc.alloc_raw(KB - 64, 8, 0).is_some());
+    }
+
+    #[test]
+    fn slice_allocations() {
+        let stack_alloc = StackAllocator::new(KB);
+
+        let zeroes = stack_alloc.alloc_slice(16, 0u32).unwrap();
+        let squares = stack_alloc.alloc_slice_with(8, |i| i * i).unwrap();
+        let copied = stack_alloc.alloc_slice_copy(&[1.0f32, 2.0, 3.0]).unwrap();
+
+        assert!(zeroes.iter().all(|&value| value == 0) && zeroes.len() == 16);
+        assert_eq!(&*squares, &[0, 1, 4, 9, 16, 25, 36, 49]);
+        assert_eq!(&*copied, &[1.0, 2.0, 3.0]);
+        assert!(pointer_util::is_aligned_to(squares.as_ptr() as *const u8, std::mem::align_of::<usize>()));
+    }
+
+    #[test]
+    fn slice_elements_are_dropped() {
+        use std::rc::Rc;
+
+        let stack_alloc = StackAllocator::new(KB);
+        let counter = Rc::new(());
+
+        {
+            let mut handles = stack_alloc.alloc_slice(10, counter.clone()).unwrap();
+            handles[3] = Rc::new(());
+            assert_eq!(Rc::strong_count(&counter), 10);
+        }
+
+        assert_eq!(Rc::strong_count(&counter), 1, "Elements of the slice were not dropped");
+        assert!(stack_alloc.alloc_raw(KB - 64, 1, 0).is_some(), "Slice memory was not freed");
+    }
+
+    #[test]
+    fn slice_init_panic_frees_memory() {
+        use std::rc::Rc;
+        use std::panic::{ self, AssertUnwindSafe };
+
+        let stack_alloc = StackAllocator::new(KB);
+        let counter = Rc::new(());
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            stack_alloc.alloc_slice_with(10, |i| if i < 5 { counter.clone() } else { panic!("Initialization failed") })
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(Rc::strong_count(&counter), 1, "Initialized elements were not dropped");
+        assert!(stack_alloc.alloc_raw(KB - 64, 1, 0).is_some(), "Slice memory was not freed");
+    }
+
+    #[test]
+    fn failed_allocation_keeps_stack_intact() {
+        let stack_alloc = StackAllocator::new(KB);
+
+        stack_alloc.alloc_raw(256, 8, 0).unwrap();
+        let (current_ptr, expected_available) = {
+            let storage = stack_alloc.storage.borrow();
+            (storage.current_ptr, storage.mem_end as usize - storage.current_ptr as usize)
+        };
+        // The header of the next allocation is padded to keep the user pointer aligned
+        let expected_ptr = unsafe { pointer_util::align_top(current_ptr.offset(ALLOCATION_META_SIZE as isize), 8) as *mut u8 };
+
+        match stack_alloc.try_alloc_raw(KB, 8, 0) {
+            Err(AllocError::OutOfMemory { available, .. }) => assert_eq!(available, expected_available),
+            _ => panic!("Expected OutOfMemory"),
+        }
+
+        assert_eq!(stack_alloc.alloc_raw(8, 8, 0).unwrap().ptr, expected_ptr, "Failed request moved the top of the stack");
+    }
+
+    #[test]
+    fn stats_track_usage() {
+        let stack_alloc = StackAllocator::new(KB);
+
+        let first_mem = stack_alloc.alloc_raw(60, 1, 0).unwrap();
+        let second_mem = stack_alloc.alloc_raw(32, 16, 0).unwrap();
+        let top_offset = second_mem.ptr as usize + 32 - (first_mem.ptr as usize - ALLOCATION_META_SIZE);
+
+        let stats = stack_alloc.stats();
+        assert_eq!(stats.used_bytes, 92);
+        assert_eq!(stats.occupied_bytes(), top_offset, "Alignment padding was not counted as overhead");
+        assert!(stats.overhead_bytes >= 2 * ALLOCATION_META_SIZE);
+        assert_eq!(stats.live_allocations, 2);
+
+        stack_alloc.dealloc_raw(second_mem);
+        let stats = stack_alloc.stats();
+        assert_eq!(stats.used_bytes, 60);
+        assert_eq!(stats.overhead_bytes, ALLOCATION_META_SIZE);
+        assert_eq!(stats.live_allocations, 1);
+
+        stack_alloc.dealloc_raw(first_mem);
+        let stats = stack_alloc.stats();
+        assert_eq!(stats.occupied_bytes(), 0);
+        assert_eq!(stats.high_water_mark, top_offset);
+    }
 }
\ No newline at end of file
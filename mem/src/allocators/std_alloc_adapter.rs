@@ -0,0 +1,203 @@
+use std::alloc::{ GlobalAlloc, Layout };
+use std::cell::UnsafeCell;
+#[cfg(feature = "allocator_api")]
+use std::{ ptr::NonNull, alloc::AllocError };
+
+use super::base::{ Allocator, MemoryBlock };
+use super::super::sync_policy::base::SyncPolicy;
+use super::super::sync_policy::spin_lock_policy::SpinLockPolicy;
+
+///
+/// Exposes an allocator to the standard library. A `Layout` maps onto the size and
+/// alignment of `alloc_raw`, the offset is always 0. With the `allocator_api` feature the
+/// adapter implements the unstable `Allocator` trait too, which lets collections like
+/// `Vec::new_in` allocate their memory from it
+///
+pub struct AllocatorAdapter<'a, A: 'a + Allocator> {
+    allocator: &'a A,
+}
+
+impl<'a, A: Allocator> AllocatorAdapter<'a, A> {
+    pub fn new(allocator: &'a A) -> AllocatorAdapter<'a, A> {
+        AllocatorAdapter {
+            allocator,
+        }
+    }
+
+    pub fn allocator(&self) -> &'a A {
+        self.allocator
+    }
+}
+
+// Derived impls would require the allocator to be Copy as well
+impl<'a, A: Allocator> Clone for AllocatorAdapter<'a, A> {
+    fn clone(&self) -> Self { *self }
+}
+
+impl<'a, A: Allocator> Copy for AllocatorAdapter<'a, A> {}
+
+unsafe impl<'a, A: Allocator> GlobalAlloc for AllocatorAdapter<'a, A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocator.alloc_raw(layout.size(), layout.align(), 0) {
+            Some(block) => block.ptr,
+            None => std::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        self.allocator.dealloc_raw(MemoryBlock::new(ptr));
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+unsafe impl<'a, A: Allocator> std::alloc::Allocator for AllocatorAdapter<'a, A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        // Zero sized requests do not touch the allocator, a dangling pointer satisfies them
+        if layout.size() == 0 {
+            let dangling = unsafe { NonNull::new_unchecked(layout.align() as *mut u8) };
+            return Ok(NonNull::slice_from_raw_parts(dangling, 0));
+        }
+
+        match self.allocator.alloc_raw(layout.size(), layout.align(), 0) {
+            Some(block) => {
+                let ptr = unsafe { NonNull::new_unchecked(block.ptr) };
+                Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+            },
+            None => Err(AllocError),
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            self.allocator.dealloc_raw(MemoryBlock::new(ptr.as_ptr()));
+        }
+    }
+}
+
+///
+/// Makes an allocator usable with `#[global_allocator]`. The allocator is created by `init`
+/// on the first request and every request is serialized by a spin lock, as the allocators
+/// are not thread-safe themselves. The allocator must not use the global heap on its own,
+/// which would end up in the adapter again while the lock is held
+///
+pub struct GlobalAllocatorAdapter<A: Allocator> {
+    init:       fn() -> A,
+    allocator:  UnsafeCell<Option<A>>,
+    lock:       SpinLockPolicy,
+}
+
+// The allocator is used by whichever thread holds the lock, so it has to be Send
+unsafe impl<A: Allocator + Send> Sync for GlobalAllocatorAdapter<A> {}
+
+impl<A: Allocator> GlobalAllocatorAdapter<A> {
+    pub const fn new(init: fn() -> A) -> GlobalAllocatorAdapter<A> {
+        GlobalAllocatorAdapter {
+            init,
+            allocator:  UnsafeCell::new(None),
+            lock:       SpinLockPolicy::new(),
+        }
+    }
+
+    ///
+    /// Runs `f` with exclusive access to the allocator, creating it if needed. The lock
+    /// is released even if `init` or the allocator panic
+    ///
+    fn with_allocator<R, F: FnOnce(&A) -> R>(&self, f: F) -> R {
+        self.lock.synchronized(|| {
+            let allocator = unsafe { &mut *self.allocator.get() };
+            if allocator.is_none() {
+                *allocator = Some((self.init)());
+            }
+
+            f(allocator.as_ref().unwrap())
+        })
+    }
+}
+
+unsafe impl<A: Allocator> GlobalAlloc for GlobalAllocatorAdapter<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with_allocator(|allocator| AllocatorAdapter::new(allocator).alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with_allocator(|allocator| AllocatorAdapter::new(allocator).dealloc(ptr, layout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::base::BasicAllocator;
+    use super::super::linear_allocator::LinearAllocator;
+    use super::super::free_list_allocator::FreeListAllocator;
+    use spark_core::pointer_util;
+
+    const KB: usize = 1024;
+
+    #[test]
+    fn layout_maps_onto_alloc_raw() {
+        let linear_alloc = LinearAllocator::new(4 * KB);
+        let adapter = AllocatorAdapter::new(&linear_alloc);
+
+        let layout = Layout::from_size_align(100, 64).unwrap();
+        let ptr = unsafe { adapter.alloc(layout) };
+
+        assert!(!ptr.is_null());
+        assert!(pointer_util::is_aligned_to(ptr, 64), "Alignment of the layout was not respected");
+        assert_eq!(linear_alloc.get_allocation_size(&MemoryBlock::new(ptr)), 100);
+    }
+
+    #[test]
+    fn returns_null_when_out_of_memory() {
+        let linear_alloc = LinearAllocator::new(KB);
+        let adapter = AllocatorAdapter::new(&linear_alloc);
+
+        let ptr = unsafe { adapter.alloc(Layout::from_size_align(8 * KB, 8).unwrap()) };
+        assert!(ptr.is_null());
+    }
+
+    static GLOBAL_FREE_LIST: GlobalAllocatorAdapter<FreeListAllocator> = GlobalAllocatorAdapter::new(|| FreeListAllocator::new(64 * KB));
+
+    #[test]
+    fn global_adapter_creates_allocator_lazily() {
+        let layout = Layout::from_size_align(KB, 16).unwrap();
+
+        unsafe {
+            let ptr_0 = GLOBAL_FREE_LIST.alloc(layout);
+            assert!(!ptr_0.is_null());
+            GLOBAL_FREE_LIST.dealloc(ptr_0, layout);
+
+            let ptr_1 = GLOBAL_FREE_LIST.alloc(layout);
+            assert_eq!(ptr_0, ptr_1, "Freed memory was not returned to the allocator");
+            GLOBAL_FREE_LIST.dealloc(ptr_1, layout);
+        }
+    }
+
+    #[test]
+    fn global_adapter_releases_lock_on_panic() {
+        let adapter: GlobalAllocatorAdapter<FreeListAllocator> = GlobalAllocatorAdapter::new(|| panic!("Allocator could not be created"));
+        let layout = Layout::from_size_align(KB, 16).unwrap();
+
+        for _ in 0 .. 2 {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe { adapter.alloc(layout) }));
+            assert!(result.is_err(), "Allocation did not fail");
+        }
+    }
+
+    #[cfg(feature = "allocator_api")]
+    #[test]
+    fn collections_allocate_through_adapter() {
+        let free_list_alloc: FreeListAllocator = FreeListAllocator::new(64 * KB);
+        let adapter = AllocatorAdapter::new(&free_list_alloc);
+
+        let mut numbers = Vec::new_in(adapter);
+        for i in 0 .. 1000usize {
+            numbers.push(i);
+        }
+
+        let boxed = Box::new_in(42usize, adapter);
+        assert_eq!(*boxed, 42);
+        assert_eq!(numbers.iter().sum::<usize>(), 999 * 1000 / 2);
+        assert_eq!(free_list_alloc.get_allocation_size(&MemoryBlock::new(numbers.as_mut_ptr() as *mut u8)), numbers.capacity() * std::mem::size_of::<usize>());
+    }
+}
\ No newline at end of file
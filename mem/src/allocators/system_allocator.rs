@@ -0,0 +1,166 @@
+use std::alloc::{ GlobalAlloc, Layout, System };
+use std::cell::RefCell;
+use std::collections::HashMap;
+use spark_core::math_util;
+
+use super::base::{ Allocator, MemoryBlock, OwningAllocator };
+use super::allocator_stats::{ AllocatorStats, StatsCounters };
+
+///
+/// A SystemAllocation remembers what has to be handed back
+/// to the system heap once the user frees the block
+///
+struct SystemAllocation {
+    pub raw_ptr:            *mut u8,
+    pub layout:             Layout,
+    pub allocation_size:    usize,
+}
+
+///
+/// The SystemAllocator forwards all requests to the system heap. It keeps track of its
+/// allocations, so it can tell the size of a block, whether it owns a block and free all
+/// blocks on `reset`. This makes it a last resort for combinators like the Fallback.
+///
+pub struct SystemAllocator {
+    storage: RefCell<HashMap<usize, SystemAllocation>>,
+    stats: RefCell<StatsCounters>,
+}
+
+// The allocations come from the global allocator and are owned by this allocator alone,
+// so it can be moved to another thread as a whole
+unsafe impl Send for SystemAllocator {}
+
+impl SystemAllocator {
+    pub fn new() -> SystemAllocator {
+        SystemAllocator {
+            storage: RefCell::new(HashMap::new()),
+            stats: RefCell::new(StatsCounters::default()),
+        }
+    }
+
+    ///
+    /// Returns the amount of blocks that are currently allocated from the system heap
+    ///
+    pub fn allocation_count(&self) -> usize {
+        self.storage.borrow().len()
+    }
+}
+
+impl Default for SystemAllocator {
+    fn default() -> SystemAllocator {
+        SystemAllocator::new()
+    }
+}
+
+impl Allocator for SystemAllocator {
+    fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
+        // The block is shifted so that the address `offset` bytes into it is aligned
+        let padding = math_util::round_to_next_multiple(offset, alignment) - offset;
+        let layout = match Layout::from_size_align(padding + offset + size.max(1), alignment) {
+            Ok(layout) => layout,
+            Err(_) => {
+                self.stats.borrow_mut().on_failed_request();
+                return None;
+            },
+        };
+
+        let raw_ptr = unsafe { System.alloc(layout) };
+        if raw_ptr.is_null() {
+            self.stats.borrow_mut().on_failed_request();
+            return None;
+        }
+
+        self.stats.borrow_mut().on_alloc(size, layout.size());
+
+        let user_ptr = unsafe { raw_ptr.offset(padding as isize) };
+        self.storage.borrow_mut().insert(user_ptr as usize, SystemAllocation {
+            raw_ptr,
+            layout,
+            allocation_size: size,
+        });
+
+        Some(MemoryBlock::new(user_ptr))
+    }
+
+    fn dealloc_raw(&self, memory: MemoryBlock) {
+        match self.storage.borrow_mut().remove(&(memory.ptr as usize)) {
+            Some(allocation) => {
+                self.stats.borrow_mut().on_dealloc(allocation.allocation_size, allocation.layout.size());
+                unsafe { System.dealloc(allocation.raw_ptr, allocation.layout) };
+            },
+            None => debug_assert!(false, "AllocatorMem was not allocated by this allocator"),
+        }
+    }
+
+    ///
+    /// Frees all blocks that are still allocated from the system heap
+    ///
+    fn reset(&self) {
+        for (_, allocation) in self.storage.borrow_mut().drain() {
+            unsafe { System.dealloc(allocation.raw_ptr, allocation.layout) };
+        }
+
+        self.stats.borrow_mut().on_reset();
+    }
+
+    fn get_allocation_size(&self, memory: &MemoryBlock) -> usize {
+        match self.storage.borrow().get(&(memory.ptr as usize)) {
+            Some(allocation) => allocation.allocation_size,
+            None => {
+                debug_assert!(false, "AllocatorMem was not allocated by this allocator");
+                0
+            },
+        }
+    }
+
+    ///
+    /// The capacity is only limited by the system heap and reported as `usize::MAX`
+    ///
+    fn stats(&self) -> AllocatorStats {
+        self.stats.borrow().snapshot(usize::MAX, None)
+    }
+}
+
+impl OwningAllocator for SystemAllocator {
+    fn owns(&self, memory: &MemoryBlock) -> bool {
+        self.storage.borrow().contains_key(&(memory.ptr as usize))
+    }
+}
+
+impl Drop for SystemAllocator {
+    fn drop(&mut self) {
+        self.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spark_core::pointer_util;
+
+    #[test]
+    fn aligned_allocation_with_offset() {
+        let system_alloc = SystemAllocator::new();
+
+        let mem = system_alloc.alloc_raw(100, 64, 4).unwrap();
+        assert!(pointer_util::is_aligned_to(unsafe { mem.ptr.offset(4) }, 64));
+        assert_eq!(system_alloc.get_allocation_size(&mem), 100);
+        assert!(system_alloc.owns(&mem));
+
+        system_alloc.dealloc_raw(mem);
+        assert_eq!(system_alloc.allocation_count(), 0);
+    }
+
+    #[test]
+    fn reset_frees_all_blocks() {
+        let system_alloc = SystemAllocator::new();
+
+        for _ in 0 .. 10 {
+            system_alloc.alloc_raw(256, 8, 0).unwrap();
+        }
+
+        assert_eq!(system_alloc.allocation_count(), 10);
+        system_alloc.reset();
+        assert_eq!(system_alloc.allocation_count(), 0);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,338 @@
+use std::cell::{ Cell, RefCell };
+use std::collections::HashMap;
+use std::mem::ManuallyDrop;
+use std::ops::{ Deref, DerefMut };
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+use super::base::{ Allocator, AllocatorBox, MemoryBlock };
+use super::linear_allocator::LinearAllocator;
+
+// Every ThreadLocalArena gets an id of its own, so threads can tell their arenas apart
+static NEXT_ARENA_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Caches the ThreadArena of the current thread for every ThreadLocalArena it used
+    static THREAD_ARENAS: RefCell<HashMap<usize, *const ThreadArena>> = RefCell::new(HashMap::new());
+}
+
+///
+/// The arenas of a single thread, one LinearAllocator per buffered frame
+///
+struct ThreadArena {
+    allocators: Vec<LinearAllocator>,
+    frame:      Cell<usize>,
+}
+
+// A ThreadArena is only used by the thread that created it, other threads merely drop it.
+// This keeps the ThreadLocalArena Sync, it never hands out the ThreadArena of another thread
+unsafe impl Send for ThreadArena {}
+
+///
+/// The ThreadLocalArena provides frame scratch memory to any number of threads. Every thread
+/// lazily gets LinearAllocators of its own the first time it allocates, so allocations never
+/// contend with other threads. `end_frame` starts a new frame, which releases the memory of
+/// all threads at once. With a buffer count of two the arenas are double-buffered and the
+/// data of the previous frame stays alive for one more frame, e.g. until the GPU consumed it.
+///
+/// An arena is reset by its own thread the first time it is used in a new frame, so ending a
+/// frame never touches the memory of another thread. Data allocated in a frame must not be
+/// used once `buffer_count` further frames have been ended, the handles returned by `alloc`
+/// assert this on every access.
+///
+/// The arenas of a thread are owned by the ThreadLocalArena and are only released when it is
+/// dropped, a thread that exits keeps its arenas alive until then. Short-lived threads should
+/// therefore share a ThreadLocalArena that is dropped along with them.
+///
+pub struct ThreadLocalArena {
+    id:             usize,
+    arena_size:     usize,
+    buffer_count:   usize,
+    frame:          AtomicUsize,
+    thread_arenas:  Mutex<Vec<Box<ThreadArena>>>,
+}
+
+impl ThreadLocalArena {
+    ///
+    /// Creates a single-buffered arena, every thread gets `arena_size` bytes per frame
+    ///
+    pub fn new(arena_size: usize) -> ThreadLocalArena {
+        ThreadLocalArena::with_buffer_count(arena_size, 1)
+    }
+
+    ///
+    /// Creates a double-buffered arena that keeps the data of the previous frame alive
+    ///
+    pub fn double_buffered(arena_size: usize) -> ThreadLocalArena {
+        ThreadLocalArena::with_buffer_count(arena_size, 2)
+    }
+
+    ///
+    /// Creates an arena that keeps the data of a frame alive for `buffer_count` frames
+    ///
+    pub fn with_buffer_count(arena_size: usize, buffer_count: usize) -> ThreadLocalArena {
+        debug_assert!(arena_size > 0usize, "Size is not allowed to be 0");
+        debug_assert!(buffer_count > 0usize, "Buffer count is not allowed to be 0");
+
+        ThreadLocalArena {
+            id:             NEXT_ARENA_ID.fetch_add(1, Ordering::Relaxed),
+            arena_size,
+            buffer_count,
+            frame:          AtomicUsize::new(0),
+            thread_arenas:  Mutex::new(Vec::new()),
+        }
+    }
+
+    ///
+    /// Returns the allocator of the calling thread for the current frame. Values allocated
+    /// from it directly are not checked against their frame, unlike the ones from `alloc`
+    ///
+    pub fn allocator(&self) -> &LinearAllocator {
+        let thread_arena = self.thread_arena();
+        let frame = self.frame.load(Ordering::Acquire);
+        let allocator = &thread_arena.allocators[frame % self.buffer_count];
+
+        // The allocator last served the frame `buffer_count` frames ago, its data is no longer alive
+        if thread_arena.frame.get() != frame {
+            thread_arena.frame.set(frame);
+            allocator.reset();
+        }
+
+        allocator
+    }
+
+    pub fn alloc<T>(&self, value: T, alignment: usize) -> Option<ArenaBox<T>> {
+        let frame = self.frame();
+        match self.allocator().alloc(value, alignment, 0) {
+            Some(instance) => Some(ArenaBox {
+                instance:   ManuallyDrop::new(instance),
+                frame,
+                arena:      self,
+            }),
+            None => None,
+        }
+    }
+
+    pub fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
+        self.allocator().alloc_raw(size, alignment, offset)
+    }
+
+    ///
+    /// Ends the current frame, which is meant to be called at a barrier all threads are
+    /// waiting on. The memory of the frame ending `buffer_count` frames ago is released
+    ///
+    pub fn end_frame(&self) {
+        self.frame.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame.load(Ordering::Acquire)
+    }
+
+    ///
+    /// Returns true if data allocated in `frame` is still alive
+    ///
+    pub fn is_frame_alive(&self, frame: usize) -> bool {
+        let current_frame = self.frame();
+        frame <= current_frame && current_frame - frame < self.buffer_count
+    }
+
+    ///
+    /// Returns the amount of threads that allocated from this arena so far, including
+    /// threads that already exited
+    ///
+    pub fn thread_count(&self) -> usize {
+        self.thread_arenas.lock().unwrap().len()
+    }
+
+    fn thread_arena(&self) -> &ThreadArena {
+        let cached_arena = THREAD_ARENAS.with(|arenas| arenas.borrow().get(&self.id).cloned());
+
+        let thread_arena = match cached_arena {
+            Some(thread_arena) => thread_arena,
+            None => {
+                let thread_arena = Box::new(ThreadArena {
+                    allocators: (0 .. self.buffer_count).map(|_| LinearAllocator::new(self.arena_size)).collect(),
+                    frame:      Cell::new(self.frame.load(Ordering::Acquire)),
+                });
+
+                // The box keeps the address stable while the vector grows
+                let thread_arena_ptr = &*thread_arena as *const ThreadArena;
+                self.thread_arenas.lock().unwrap().push(thread_arena);
+                THREAD_ARENAS.with(|arenas| arenas.borrow_mut().insert(self.id, thread_arena_ptr));
+
+                thread_arena_ptr
+            }
+        };
+
+        // ThreadArenas live as long as the ThreadLocalArena and ids are never reused
+        unsafe { &*thread_arena }
+    }
+}
+
+///
+/// An ArenaBox owns a value allocated from a ThreadLocalArena and remembers the frame it was
+/// allocated in. The arena of the thread is reset by its next allocation in a later frame, so
+/// accessing the value after its window of `buffer_count` frames passed asserts in all builds.
+/// Values whose frame was already released are not dropped
+///
+pub struct ArenaBox<'a, T: 'a> {
+    instance:   ManuallyDrop<AllocatorBox<'a, T, LinearAllocator>>,
+    frame:      usize,
+    arena:      &'a ThreadLocalArena,
+}
+
+impl<'a, T> ArenaBox<'a, T> {
+    ///
+    /// Returns the frame the value was allocated in
+    ///
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    fn validate_frame(&self) {
+        let frame_is_alive = self.arena.is_frame_alive(self.frame);
+        assert!(frame_is_alive, "ArenaBox was used after its frame was released");
+    }
+}
+
+impl<'a, T> Deref for ArenaBox<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.validate_frame();
+        &self.instance
+    }
+}
+
+impl<'a, T> DerefMut for ArenaBox<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.validate_frame();
+        &mut self.instance
+    }
+}
+
+impl<'a, T> Drop for ArenaBox<'a, T> {
+    fn drop(&mut self) {
+        if self.arena.is_frame_alive(self.frame) {
+            unsafe { ManuallyDrop::drop(&mut self.instance) };
+        }
+    }
+}
+
+impl Drop for ThreadLocalArena {
+    fn drop(&mut self) {
+        // Only the entry of the dropping thread can be removed, the others are never looked up again
+        let _ = THREAD_ARENAS.try_with(|arenas| arenas.borrow_mut().remove(&self.id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{ Arc, Barrier };
+    use std::thread;
+
+    const KB: usize = 1024;
+
+    #[test]
+    fn threads_get_arenas_of_their_own() {
+        let arena = Arc::new(ThreadLocalArena::new(64 * KB));
+        let barrier = Arc::new(Barrier::new(4));
+
+        let threads: Vec<_> = (0 .. 4).map(|thread_idx| {
+            let arena = arena.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                let value = arena.alloc(thread_idx, 8).unwrap();
+                barrier.wait();
+                assert_eq!(*value, thread_idx, "Arena was shared with another thread");
+                arena.allocator() as *const LinearAllocator as usize
+            })
+        }).collect();
+
+        let mut allocators: Vec<usize> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        allocators.sort();
+        allocators.dedup();
+
+        assert_eq!(allocators.len(), 4);
+        assert_eq!(arena.thread_count(), 4);
+    }
+
+    #[test]
+    fn end_frame_releases_memory() {
+        let arena = ThreadLocalArena::new(64 * KB);
+
+        let first_ptr = arena.alloc_raw(100, 8, 0).unwrap().ptr;
+        arena.alloc_raw(100, 8, 0).unwrap();
+        arena.end_frame();
+
+        assert_eq!(arena.frame(), 1);
+        assert_eq!(arena.alloc_raw(100, 8, 0).unwrap().ptr, first_ptr, "Arena was not reset by the new frame");
+    }
+
+    #[test]
+    fn double_buffered_keeps_previous_frame_alive() {
+        let arena = ThreadLocalArena::double_buffered(64 * KB);
+
+        let frame_0_ptr = arena.alloc_raw(100, 8, 0).unwrap().ptr;
+        unsafe { *(frame_0_ptr as *mut u32) = 0xDEADBEEF };
+        arena.end_frame();
+
+        let frame_1_ptr = arena.alloc_raw(100, 8, 0).unwrap().ptr;
+        unsafe { *(frame_1_ptr as *mut u32) = 0xCAFEBABE };
+        assert_eq!(unsafe { *(frame_0_ptr as *const u32) }, 0xDEADBEEF, "Data of the previous frame was overwritten");
+        arena.end_frame();
+
+        let frame_2_ptr = arena.alloc_raw(100, 8, 0).unwrap().ptr;
+        assert_eq!(frame_2_ptr, frame_0_ptr, "Buffer of two frames ago was not reused");
+        assert_eq!(unsafe { *(frame_1_ptr as *const u32) }, 0xCAFEBABE);
+    }
+
+    #[test]
+    #[should_panic(expected = "ArenaBox was used after its frame was released")]
+    fn assert_on_data_used_after_window() {
+        let arena = ThreadLocalArena::double_buffered(64 * KB);
+
+        let value = arena.alloc(42u32, 4).unwrap();
+        arena.end_frame();
+        assert_eq!(*value, 42);
+        arena.end_frame();
+
+        // The arena of frame 0 is handed out again
+        arena.alloc(7u32, 4).unwrap();
+        assert_eq!(*value, 42);
+    }
+
+    #[test]
+    fn frames_across_threads_with_barrier() {
+        let arena = Arc::new(ThreadLocalArena::new(4 * KB));
+        let barrier = Arc::new(Barrier::new(4));
+
+        let threads: Vec<_> = (0 .. 4).map(|_| {
+            let arena = arena.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                for _ in 0 .. 100 {
+                    // Without a reset per frame the arenas would run out of memory after a few frames
+                    for i in 0 .. 30usize {
+                        let value = arena.alloc(i, 8).unwrap();
+                        assert_eq!(*value, i);
+                    }
+
+                    if barrier.wait().is_leader() {
+                        arena.end_frame();
+                    }
+
+                    barrier.wait();
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(arena.frame(), 100);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,731 @@
+use std;
+use std::cell::RefCell;
+use spark_core::{ pointer_util, math_util };
+
+use super::super::virtual_mem::VirtualRegion;
+use super::base::{ Allocator, MemoryBlock, BasicAllocator, OwningAllocator };
+use super::alloc_error::AllocError;
+use super::allocator_stats::{ AllocatorStats, StatsCounters };
+
+// Every first-level class is split into 2^SL_INDEX_COUNT_LOG2 linearly spaced second-level classes
+const SL_INDEX_COUNT_LOG2: usize = 4;
+const SL_INDEX_COUNT: usize = 1 << SL_INDEX_COUNT_LOG2;
+
+// Block sizes are multiples of the alignment all block headers share
+const ALIGN_SIZE_LOG2: usize = 3;
+const ALIGN_SIZE: usize = 1 << ALIGN_SIZE_LOG2;
+
+// Blocks smaller than SMALL_BLOCK_SIZE all live in the first first-level class
+const FL_INDEX_MAX: usize = 32;
+const FL_INDEX_SHIFT: usize = SL_INDEX_COUNT_LOG2 + ALIGN_SIZE_LOG2;
+const FL_INDEX_COUNT: usize = FL_INDEX_MAX - FL_INDEX_SHIFT + 1;
+const SMALL_BLOCK_SIZE: usize = 1 << FL_INDEX_SHIFT;
+
+const BLOCK_FREE_BIT: usize = 1;
+
+///
+/// Every block of memory starts with a BlockHeader. `prev_phys_block` and `size`
+/// are always valid, `size` holds the size of the payload behind the first two
+/// fields and marks free blocks with its lowest bit. The free list links are only
+/// valid for free blocks and overlap the payload of used blocks
+///
+#[repr(C)]
+struct BlockHeader {
+    pub prev_phys_block:    *mut BlockHeader,
+    pub size:               usize,
+    pub next_free:          *mut BlockHeader,
+    pub prev_free:          *mut BlockHeader,
+}
+
+// Only prev_phys_block and size are overhead, the links live inside of the payload
+const BLOCK_OVERHEAD: usize = 2 * std::mem::size_of::<usize>();
+const MIN_BLOCK_SIZE: usize = std::mem::size_of::<BlockHeader>() - BLOCK_OVERHEAD;
+const MAX_BLOCK_SIZE: usize = (1 << (FL_INDEX_MAX - 1)) - 1;
+
+impl BlockHeader {
+    #[inline]
+    fn size(&self) -> usize { self.size & !BLOCK_FREE_BIT }
+
+    #[inline]
+    fn set_size(&mut self, size: usize) { self.size = size | (self.size & BLOCK_FREE_BIT); }
+
+    #[inline]
+    fn is_free(&self) -> bool { self.size & BLOCK_FREE_BIT != 0 }
+
+    #[inline]
+    fn set_free(&mut self, is_free: bool) {
+        if is_free { self.size |= BLOCK_FREE_BIT; } else { self.size &= !BLOCK_FREE_BIT; }
+    }
+
+    #[inline]
+    fn payload(&mut self) -> *mut u8 {
+        unsafe { (self as *mut BlockHeader as *mut u8).offset(BLOCK_OVERHEAD as isize) }
+    }
+
+    #[inline]
+    fn next_phys_block(&mut self) -> *mut BlockHeader {
+        unsafe { self.payload().offset(self.size() as isize) as *mut BlockHeader }
+    }
+}
+
+///
+/// The AllocationHeader struct describes meta-data
+/// the allocator needs to store alongside of the
+/// allocations.
+///
+struct AllocationHeader {
+    pub block_offset:       u32,
+    pub allocation_size:    u32,
+}
+
+const ALLOCATION_META_SIZE: usize = std::mem::size_of::<AllocationHeader>();
+
+///
+/// Returns the index of the most significant bit set in `word`
+///
+#[inline]
+fn fls(word: usize) -> usize {
+    std::mem::size_of::<usize>() * 8 - 1 - word.leading_zeros() as usize
+}
+
+///
+/// Maps a block size to the first- and second-level index of the free list it is stored in
+///
+#[inline]
+fn mapping_insert(size: usize) -> (usize, usize) {
+    if size < SMALL_BLOCK_SIZE {
+        (0, size / (SMALL_BLOCK_SIZE / SL_INDEX_COUNT))
+    }
+    else {
+        let fl = fls(size);
+        let sl = (size >> (fl - SL_INDEX_COUNT_LOG2)) ^ SL_INDEX_COUNT;
+        (fl - (FL_INDEX_SHIFT - 1), sl)
+    }
+}
+
+///
+/// Maps a requested size to the free list whose blocks are all large enough to hold it,
+/// by rounding the size up to the next second-level class before mapping it
+///
+#[inline]
+fn mapping_search(size: usize) -> (usize, usize) {
+    if size >= SMALL_BLOCK_SIZE {
+        let round = (1 << (fls(size) - SL_INDEX_COUNT_LOG2)) - 1;
+        mapping_insert(size + round)
+    }
+    else {
+        mapping_insert(size)
+    }
+}
+
+///
+/// The TlsfAllocatorStorage is a type that is used to
+/// expose a safe API for user allocations where the Allocator
+/// itself is just mutating the Storage backing it
+///
+struct TlsfAllocatorStorage {
+    pub use_internal_mem:   bool,
+    pub region:             VirtualRegion,
+    pub mem_end:            *mut u8,
+    pub fl_bitmap:          u32,
+    pub sl_bitmap:          [u32; FL_INDEX_COUNT],
+    pub free_blocks:        [[*mut BlockHeader; SL_INDEX_COUNT]; FL_INDEX_COUNT],
+    pub stats:              StatsCounters,
+}
+
+impl TlsfAllocatorStorage {
+    ///
+    /// Creates a new TLSF allocator storage and allocates the memory
+    /// block requested by the allocator from the virtual memory API
+    ///
+    fn new(size: usize) -> Result<TlsfAllocatorStorage, AllocError> {
+
+        let mut region = VirtualRegion::reserve(size).ok_or(AllocError::ReserveFailed { size })?;
+        let physical_address_space = region.commit(0, size).ok_or(AllocError::CommitFailed { size })?;
+
+        let mut storage = TlsfAllocatorStorage {
+            use_internal_mem:   true,
+            region,
+            mem_end:            unsafe { physical_address_space.offset(size as isize) },
+            fl_bitmap:          0,
+            sl_bitmap:          [0; FL_INDEX_COUNT],
+            free_blocks:        [[std::ptr::null_mut(); SL_INDEX_COUNT]; FL_INDEX_COUNT],
+            stats:              StatsCounters::default(),
+        };
+
+        storage.reset_blocks();
+        Ok(storage)
+    }
+
+    ///
+    /// Turns the whole memory into one free block followed by a used sentinel block
+    /// of size zero, which keeps the last real block from merging past the end
+    ///
+    fn reset_blocks(&mut self) {
+        self.fl_bitmap = 0;
+        self.sl_bitmap = [0; FL_INDEX_COUNT];
+        self.free_blocks = [[std::ptr::null_mut(); SL_INDEX_COUNT]; FL_INDEX_COUNT];
+
+        let mem_size = self.mem_end as usize - self.region.base() as usize;
+        if mem_size < 2 * BLOCK_OVERHEAD + MIN_BLOCK_SIZE {
+            return;
+        }
+
+        let block_size = math_util::round_to_previous_multiple(mem_size - 2 * BLOCK_OVERHEAD, ALIGN_SIZE);
+
+        unsafe {
+            let block = self.region.base() as *mut BlockHeader;
+            (*block).prev_phys_block = std::ptr::null_mut();
+            (*block).size = if block_size < MAX_BLOCK_SIZE { block_size } else { math_util::round_to_previous_multiple(MAX_BLOCK_SIZE, ALIGN_SIZE) };
+
+            let sentinel = (*block).next_phys_block();
+            (*sentinel).prev_phys_block = block;
+            (*sentinel).size = 0;
+
+            self.insert_free_block(block);
+        }
+    }
+
+    unsafe fn insert_free_block(&mut self, block: *mut BlockHeader) {
+        let (fl, sl) = mapping_insert((*block).size());
+        let current = self.free_blocks[fl][sl];
+
+        (*block).set_free(true);
+        (*block).next_free = current;
+        (*block).prev_free = std::ptr::null_mut();
+
+        if !current.is_null() {
+            (*current).prev_free = block;
+        }
+
+        self.free_blocks[fl][sl] = block;
+        self.fl_bitmap |= 1 << fl;
+        self.sl_bitmap[fl] |= 1 << sl;
+    }
+
+    unsafe fn remove_free_block(&mut self, block: *mut BlockHeader) {
+        let (fl, sl) = mapping_insert((*block).size());
+        let next = (*block).next_free;
+        let prev = (*block).prev_free;
+
+        if !next.is_null() {
+            (*next).prev_free = prev;
+        }
+
+        if !prev.is_null() {
+            (*prev).next_free = next;
+        }
+        else {
+            self.free_blocks[fl][sl] = next;
+
+            if next.is_null() {
+                self.sl_bitmap[fl] &= !(1 << sl);
+
+                if self.sl_bitmap[fl] == 0 {
+                    self.fl_bitmap &= !(1 << fl);
+                }
+            }
+        }
+
+        (*block).set_free(false);
+    }
+
+    ///
+    /// Finds a free block for a request of `size` bytes with two bitmap scans
+    ///
+    fn find_suitable_block(&self, size: usize) -> *mut BlockHeader {
+        let (mut fl, sl) = mapping_search(size);
+        if fl >= FL_INDEX_COUNT {
+            return std::ptr::null_mut();
+        }
+
+        let mut sl_map = self.sl_bitmap[fl] & (!0u32 << sl);
+
+        if sl_map == 0 {
+            // No block in this first-level class is large enough, take the next larger class
+            let fl_map = if fl + 1 < 32 { self.fl_bitmap & (!0u32 << (fl + 1)) } else { 0 };
+            if fl_map == 0 {
+                return std::ptr::null_mut();
+            }
+
+            fl = fl_map.trailing_zeros() as usize;
+            sl_map = self.sl_bitmap[fl];
+        }
+
+        self.free_blocks[fl][sl_map.trailing_zeros() as usize]
+    }
+
+    ///
+    /// Returns the payload size of the largest free block, or 0 if there is none
+    ///
+    fn largest_free_block(&self) -> usize {
+        if self.fl_bitmap == 0 {
+            return 0;
+        }
+
+        // The highest set bits lead to the largest size class, only its list needs a walk
+        let fl = 31 - self.fl_bitmap.leading_zeros() as usize;
+        let sl = 31 - self.sl_bitmap[fl].leading_zeros() as usize;
+
+        let mut largest_size = 0;
+        let mut block = self.free_blocks[fl][sl];
+
+        unsafe {
+            while !block.is_null() {
+                largest_size = std::cmp::max(largest_size, (*block).size());
+                block = (*block).next_free;
+            }
+        }
+
+        largest_size
+    }
+
+    ///
+    /// Shrinks `block` to `size` and returns the remainder as a new block
+    ///
+    unsafe fn split_block(&mut self, block: *mut BlockHeader, size: usize) -> *mut BlockHeader {
+        let remaining_block = (*block).payload().offset(size as isize) as *mut BlockHeader;
+        let remaining_size = (*block).size() - size - BLOCK_OVERHEAD;
+
+        (*remaining_block).size = remaining_size;
+        (*remaining_block).prev_phys_block = block;
+        (*(*remaining_block).next_phys_block()).prev_phys_block = remaining_block;
+
+        (*block).set_size(size);
+
+        remaining_block
+    }
+
+    ///
+    /// Merges `block` with its physical successor, which has to be removed from the free lists already
+    ///
+    unsafe fn absorb_next_block(&mut self, block: *mut BlockHeader) {
+        let next = (*block).next_phys_block();
+        let merged_size = (*block).size() + BLOCK_OVERHEAD + (*next).size();
+
+        (*block).set_size(merged_size);
+        (*(*block).next_phys_block()).prev_phys_block = block;
+    }
+}
+
+///
+/// The TlsfAllocator implements the two-level segregated fit algorithm and offers
+/// allocations and deallocations of arbitrary size and order in bounded time. Free
+/// blocks are kept in segregated lists, a first level of power of two size classes is
+/// split linearly into second-level classes. Two bitmaps allow to find a list holding
+/// a suitable block without any search, freed blocks are merged with their physical
+/// neighbours immediately. This makes the allocator a fit for real-time threads
+/// like audio or physics.
+///
+pub struct TlsfAllocator {
+    storage: RefCell<TlsfAllocatorStorage>,
+}
+
+// The free lists only link blocks inside of the region owned by the allocator,
+// so it can be moved to another thread as a whole
+unsafe impl Send for TlsfAllocator {}
+
+impl BasicAllocator for TlsfAllocator {
+    type AllocatorImplementation = TlsfAllocator;
+
+    fn try_new(size: usize) -> Result<Self::AllocatorImplementation, AllocError> {
+        debug_assert!(size > 0usize, "Size is not allowed to be 0");
+
+        Ok(TlsfAllocator {
+            storage: RefCell::new(TlsfAllocatorStorage::new(size)?),
+        })
+    }
+}
+
+impl Allocator for TlsfAllocator {
+    ///
+    /// The block is searched for the worst case padding `alignment` can require, which
+    /// keeps the search free of any iteration. Unused memory at the end of the block is
+    /// split off and returned to the free lists
+    ///
+    fn alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Option<MemoryBlock> {
+        self.try_alloc_raw(size, alignment, offset).ok()
+    }
+
+    ///
+    /// A failed request reports the payload of the largest free block minus the allocation
+    /// header as available. The good-fit search rounds requests up to the next size class,
+    /// so a request of exactly that size is not guaranteed to be served
+    ///
+    fn try_alloc_raw(&self, size: usize, alignment: usize, offset: usize) -> Result<MemoryBlock, AllocError> {
+        let mut storage = self.storage.borrow_mut();
+
+        if !pointer_util::is_pot(alignment) {
+            storage.stats.on_failed_request();
+            return Err(AllocError::InvalidAlignment { alignment });
+        }
+
+        let search_size = math_util::round_to_next_multiple(size + ALLOCATION_META_SIZE + alignment - 1, ALIGN_SIZE);
+        let block = if search_size > MAX_BLOCK_SIZE { std::ptr::null_mut() } else { storage.find_suitable_block(search_size) };
+
+        if block.is_null() {
+            storage.stats.on_failed_request();
+            return Err(AllocError::OutOfMemory {
+                requested: size,
+                available: storage.largest_free_block().saturating_sub(ALLOCATION_META_SIZE),
+            });
+        }
+
+        unsafe {
+
+            storage.remove_free_block(block);
+
+            let payload = (*block).payload();
+            let aligned_ptr = pointer_util::align_top(payload.offset((offset + ALLOCATION_META_SIZE) as isize), alignment) as *mut u8;
+            let user_ptr = aligned_ptr.offset(-(offset as isize));
+
+            let used_size = math_util::round_to_next_multiple(user_ptr as usize + size - payload as usize, ALIGN_SIZE);
+            let used_size = if used_size < MIN_BLOCK_SIZE { MIN_BLOCK_SIZE } else { used_size };
+
+            let can_split = (*block).size() >= used_size + BLOCK_OVERHEAD + MIN_BLOCK_SIZE;
+            if can_split {
+                // The successor of a free block is always used, so the remainder cannot be merged further
+                let remaining_block = storage.split_block(block, used_size);
+                storage.insert_free_block(remaining_block);
+            }
+
+            // The header sits right in front of the user pointer, an odd offset leaves it unaligned
+            std::ptr::write_unaligned(user_ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *mut AllocationHeader, AllocationHeader {
+                block_offset:       (user_ptr as usize - payload as usize) as u32,
+                allocation_size:    size as u32,
+            });
+            storage.stats.on_alloc(size, (*block).size() + BLOCK_OVERHEAD);
+
+            Ok(MemoryBlock::new(user_ptr))
+        }
+    }
+
+    fn dealloc_raw(&self, memory: MemoryBlock) {
+        let raw_mem = memory.ptr;
+
+        unsafe {
+            let mut storage = self.storage.borrow_mut();
+
+            {
+                let ptr_in_range = raw_mem >= storage.region.base() && raw_mem < storage.mem_end;
+                debug_assert!(ptr_in_range, "AllocatorMem was not allocated by this allocator");
+            }
+
+            let alloc_header = std::ptr::read_unaligned(raw_mem.offset(-(ALLOCATION_META_SIZE as isize)) as *const AllocationHeader);
+            let mut block = raw_mem.offset(-((alloc_header.block_offset as usize + BLOCK_OVERHEAD) as isize)) as *mut BlockHeader;
+
+            debug_assert!(!(*block).is_free(), "MemoryBlock was already freed");
+            storage.stats.on_dealloc(alloc_header.allocation_size as usize, (*block).size() + BLOCK_OVERHEAD);
+
+            let prev = (*block).prev_phys_block;
+            if !prev.is_null() && (*prev).is_free() {
+                storage.remove_free_block(prev);
+                storage.absorb_next_block(prev);
+                block = prev;
+            }
+
+            let next = (*block).next_phys_block();
+            if (*next).is_free() {
+                storage.remove_free_block(next);
+                storage.absorb_next_block(block);
+            }
+
+            storage.insert_free_block(block);
+        }
+    }
+
+    fn reset(&self) {
+        let mut storage = self.storage.borrow_mut();
+        storage.reset_blocks();
+        storage.stats.on_reset();
+    }
+
+    fn get_allocation_size(&self, memory: &MemoryBlock) -> usize {
+        let alloc_header = unsafe { std::ptr::read_unaligned(memory.ptr.offset(-(ALLOCATION_META_SIZE as isize)) as *const AllocationHeader) };
+
+        alloc_header.allocation_size as usize
+    }
+
+    ///
+    /// Every allocation is charged with the header of its block, the alignment
+    /// padding and the rest of the block that was too small to be split off
+    ///
+    fn stats(&self) -> AllocatorStats {
+        let storage = self.storage.borrow();
+        storage.stats.snapshot(storage.mem_end as usize - storage.region.base() as usize, None)
+    }
+}
+
+impl OwningAllocator for TlsfAllocator {
+    fn owns(&self, memory: &MemoryBlock) -> bool {
+        let storage = self.storage.borrow();
+        let ptr = memory.ptr as *const u8;
+        ptr >= storage.region.base() as *const u8 && ptr < storage.mem_end as *const u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KB: usize = 1024;
+    const MB: usize = KB * 1024;
+
+    ///
+    /// Walks all blocks and free lists of the allocator and asserts that they are consistent,
+    /// returns the amount of free blocks
+    ///
+    fn check_heap_invariants(tlsf_alloc: &TlsfAllocator) -> usize {
+        let storage = tlsf_alloc.storage.borrow();
+        let mut free_block_count = 0;
+
+        unsafe {
+            let mut prev: *mut BlockHeader = std::ptr::null_mut();
+            let mut block = storage.region.base() as *mut BlockHeader;
+
+            // The sentinel block is the only one with a size of zero
+            while (*block).size() != 0 {
+                assert_eq!((*block).prev_phys_block, prev, "Block does not point to its physical predecessor");
+                assert_eq!((*block).size() % ALIGN_SIZE, 0, "Block size is not a multiple of the alignment");
+                assert!((*block).size() >= MIN_BLOCK_SIZE, "Block is smaller than the minimal block size");
+
+                if (*block).is_free() {
+                    assert!(prev.is_null() || !(*prev).is_free(), "Two adjacent blocks are free");
+                    free_block_count += 1;
+
+                    let (fl, sl) = mapping_insert((*block).size());
+                    let mut list_block = storage.free_blocks[fl][sl];
+                    while !list_block.is_null() && list_block != block {
+                        list_block = (*list_block).next_free;
+                    }
+
+                    assert_eq!(list_block, block, "Free block is missing in its free list");
+                }
+
+                prev = block;
+                block = (*block).next_phys_block();
+                assert!((block as *mut u8) < storage.mem_end, "Block exceeds the memory of the allocator");
+            }
+
+            assert_eq!((*block).prev_phys_block, prev, "Sentinel does not point to the last block");
+            assert!(!(*block).is_free(), "Sentinel block is free");
+
+            let mut listed_free_blocks = 0;
+            for fl in 0 .. FL_INDEX_COUNT {
+                assert_eq!(storage.fl_bitmap & (1 << fl) != 0, storage.sl_bitmap[fl] != 0, "First-level bitmap does not match the second-level bitmap");
+
+                for sl in 0 .. SL_INDEX_COUNT {
+                    let mut list_block = storage.free_blocks[fl][sl];
+                    assert_eq!(storage.sl_bitmap[fl] & (1 << sl) != 0, !list_block.is_null(), "Second-level bitmap does not match the free list");
+
+                    while !list_block.is_null() {
+                        assert!((*list_block).is_free(), "Used block is part of a free list");
+                        assert_eq!(mapping_insert((*list_block).size()), (fl, sl), "Free block is stored in the wrong free list");
+                        listed_free_blocks += 1;
+                        list_block = (*list_block).next_free;
+                    }
+                }
+            }
+
+            assert_eq!(listed_free_blocks, free_block_count, "Free lists do not hold all free blocks");
+        }
+
+        free_block_count
+    }
+
+    ///
+    /// Simple xorshift generator to drive the stress test reproducibly
+    ///
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn next_in_range(&mut self, min: usize, max: usize) -> usize {
+            min + (self.next() % (max - min) as u64) as usize
+        }
+    }
+
+    #[test]
+    fn mapping_matches_size_classes() {
+        assert_eq!(mapping_insert(0), (0, 0));
+        assert_eq!(mapping_insert(SMALL_BLOCK_SIZE - 1), (0, SL_INDEX_COUNT - 1));
+        assert_eq!(mapping_insert(SMALL_BLOCK_SIZE), (1, 0));
+        assert_eq!(mapping_insert(2 * SMALL_BLOCK_SIZE - 1), (1, SL_INDEX_COUNT - 1));
+        assert_eq!(mapping_insert(2 * SMALL_BLOCK_SIZE), (2, 0));
+
+        // A search always maps to a class whose blocks are all big enough
+        assert_eq!(mapping_search(SMALL_BLOCK_SIZE + 1), (1, 1));
+        assert_eq!(mapping_search(MAX_BLOCK_SIZE - 1).0, FL_INDEX_COUNT - 1);
+    }
+
+    #[test]
+    fn single_allocation() {
+        let tlsf_alloc = TlsfAllocator::new(10 * MB);
+        let mem = tlsf_alloc.alloc_raw(MB, 1, 0);
+        assert!(mem.is_some());
+        check_heap_invariants(&tlsf_alloc);
+    }
+
+    #[test]
+    fn single_allocation_aligned_with_offset() {
+        let tlsf_alloc = TlsfAllocator::new(10 * MB);
+        let mem = tlsf_alloc.alloc_raw(MB + 8, 64, 4).unwrap();
+
+        let offsetted_ptr = unsafe { mem.ptr.offset(4) };
+        assert!(pointer_util::is_aligned_to(offsetted_ptr, 64), "User pointer was not properly aligned");
+    }
+
+    #[test]
+    fn allocation_with_odd_offset() {
+        let tlsf_alloc = TlsfAllocator::new(MB);
+        let mem = tlsf_alloc.alloc_raw(100, 16, 3).unwrap();
+
+        let offsetted_ptr = unsafe { mem.ptr.offset(3) };
+        assert!(pointer_util::is_aligned_to(offsetted_ptr, 16), "User pointer was not properly aligned");
+        assert_eq!(tlsf_alloc.get_allocation_size(&mem), 100);
+
+        tlsf_alloc.dealloc_raw(mem);
+        assert_eq!(check_heap_invariants(&tlsf_alloc), 1);
+    }
+
+    #[test]
+    fn return_right_allocation_size() {
+        let tlsf_alloc = TlsfAllocator::new(10 * MB);
+        let mem_0 = tlsf_alloc.alloc_raw(MB * 2, 1, 0).unwrap();
+        let mem_1 = tlsf_alloc.alloc_raw(333, 16, 4).unwrap();
+
+        assert_eq!(tlsf_alloc.get_allocation_size(&mem_0), MB * 2);
+        assert_eq!(tlsf_alloc.get_allocation_size(&mem_1), 333);
+    }
+
+    #[test]
+    fn returns_none_when_out_of_memory() {
+        let tlsf_alloc = TlsfAllocator::new(64 * KB);
+        assert!(tlsf_alloc.alloc_raw(64 * KB, 1, 0).is_none());
+
+        let mem = tlsf_alloc.alloc_raw(40 * KB, 1, 0).unwrap();
+        assert!(tlsf_alloc.alloc_raw(40 * KB, 1, 0).is_none());
+
+        tlsf_alloc.dealloc_raw(mem);
+        assert!(tlsf_alloc.alloc_raw(40 * KB, 1, 0).is_some(), "Freed memory was not reused");
+    }
+
+    #[test]
+    fn dealloc_merges_neighbours() {
+        let tlsf_alloc = TlsfAllocator::new(MB);
+        let mem_0 = tlsf_alloc.alloc_raw(100, 8, 0).unwrap();
+        let mem_1 = tlsf_alloc.alloc_raw(200, 8, 0).unwrap();
+        let mem_2 = tlsf_alloc.alloc_raw(300, 8, 0).unwrap();
+
+        tlsf_alloc.dealloc_raw(mem_0);
+        tlsf_alloc.dealloc_raw(mem_2);
+        assert_eq!(check_heap_invariants(&tlsf_alloc), 2);
+
+        tlsf_alloc.dealloc_raw(mem_1);
+        assert_eq!(check_heap_invariants(&tlsf_alloc), 1, "Freed blocks were not merged");
+    }
+
+    #[test]
+    fn failed_request_reports_largest_free_block() {
+        let tlsf_alloc = TlsfAllocator::new(MB);
+        let mut allocations = Vec::new();
+        while let Some(mem) = tlsf_alloc.alloc_raw(4 * KB, 8, 0) {
+            allocations.push(mem);
+        }
+
+        // Free two neighbours to create a free block larger than any other one
+        let mem_1 = allocations.remove(2);
+        let mem_0 = allocations.remove(1);
+        tlsf_alloc.dealloc_raw(mem_0);
+        tlsf_alloc.dealloc_raw(mem_1);
+
+        let largest_free_block = unsafe {
+            let storage = tlsf_alloc.storage.borrow();
+            let mut largest_size = 0;
+            let mut block = storage.region.base() as *mut BlockHeader;
+            while (*block).size() != 0 {
+                if (*block).is_free() {
+                    largest_size = std::cmp::max(largest_size, (*block).size());
+                }
+                block = (*block).next_phys_block();
+            }
+            largest_size
+        };
+        assert!(largest_free_block > 8 * KB);
+
+        match tlsf_alloc.try_alloc_raw(64 * KB, 8, 0) {
+            Err(AllocError::OutOfMemory { requested, available }) => {
+                assert_eq!(requested, 64 * KB);
+                assert_eq!(available, largest_free_block - ALLOCATION_META_SIZE);
+            },
+            _ => panic!("Expected OutOfMemory"),
+        }
+
+        assert!(tlsf_alloc.alloc_raw(6 * KB, 8, 0).is_some(), "Reported memory is not available");
+        assert_eq!(tlsf_alloc.try_alloc_raw(8, 3, 0).unwrap_err(), AllocError::InvalidAlignment { alignment: 3 });
+    }
+
+    #[test]
+    fn reset_whole_allocator() {
+        let tlsf_alloc = TlsfAllocator::new(MB);
+        let mem_0 = tlsf_alloc.alloc_raw(KB, 8, 0).unwrap();
+        tlsf_alloc.alloc_raw(KB, 8, 0).unwrap();
+        let mem_0_ptr = mem_0.ptr;
+        tlsf_alloc.reset();
+
+        assert_eq!(check_heap_invariants(&tlsf_alloc), 1);
+        assert_eq!(tlsf_alloc.alloc_raw(KB, 8, 0).unwrap().ptr, mem_0_ptr);
+    }
+
+    #[test]
+    fn random_alloc_free_trace_keeps_heap_consistent() {
+        let tlsf_alloc = TlsfAllocator::new(4 * MB);
+        let mut rng = XorShift(0x2545F4914F6CDD1D);
+        let mut live_allocations: Vec<(MemoryBlock, usize, u8)> = Vec::new();
+
+        for step in 0 .. 20000 {
+            let should_alloc = live_allocations.is_empty() || rng.next() % 3 != 0;
+
+            if should_alloc {
+                let size = rng.next_in_range(1, 4 * KB);
+                let alignment = 1 << rng.next_in_range(0, 8);
+                let offset = rng.next_in_range(0, 8);
+
+                if let Some(mem) = tlsf_alloc.alloc_raw(size, alignment, offset) {
+                    let offsetted_ptr = unsafe { mem.ptr.offset(offset as isize) };
+                    assert!(pointer_util::is_aligned_to(offsetted_ptr, alignment), "User pointer was not properly aligned");
+                    assert_eq!(tlsf_alloc.get_allocation_size(&mem), size);
+
+                    let pattern = step as u8;
+                    unsafe { std::ptr::write_bytes(mem.ptr, pattern, size) };
+                    live_allocations.push((mem, size, pattern));
+                }
+            }
+            else {
+                let idx = rng.next_in_range(0, live_allocations.len());
+                let (mem, size, pattern) = live_allocations.swap_remove(idx);
+
+                let data = unsafe { std::slice::from_raw_parts(mem.ptr, size) };
+                assert!(data.iter().all(|byte| *byte == pattern), "Allocation was overwritten by another one");
+
+                tlsf_alloc.dealloc_raw(mem);
+            }
+
+            if step % 500 == 0 {
+                check_heap_invariants(&tlsf_alloc);
+            }
+        }
+
+        for (mem, _, _) in live_allocations.drain(..) {
+            tlsf_alloc.dealloc_raw(mem);
+        }
+
+        assert_eq!(check_heap_invariants(&tlsf_alloc), 1, "Heap did not merge back into a single block");
+    }
+}
\ No newline at end of file
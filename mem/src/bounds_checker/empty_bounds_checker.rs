@@ -7,6 +7,12 @@ use super::base::{ BoundsChecker };
 ///
 pub struct EmptyBoundsChecker {}
 
+impl Default for EmptyBoundsChecker {
+    fn default() -> EmptyBoundsChecker {
+        EmptyBoundsChecker {}
+    }
+}
+
 impl BoundsChecker for EmptyBoundsChecker {
     unsafe fn write_canary(&self, _memory: *mut u8) {}
     fn validate_front_canary(&self, _memory: *const u8) {}
@@ -1,4 +1,5 @@
 #![feature(ptr_internals, core_intrinsics)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 
 extern crate spark_core;
 
@@ -9,4 +10,6 @@ pub mod virtual_mem;
 // the memory realm
 pub mod allocators;
 pub mod bounds_checker;
+pub mod sync_policy;
+pub mod memory_tracker;
 pub mod memory_realm;
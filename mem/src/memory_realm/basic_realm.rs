@@ -1,32 +1,63 @@
+use std::panic::Location;
+
 use super::allocators::base::{ Allocator, MemoryBlock, BasicAllocator };
 use super::bounds_checker::base::{ BoundsChecker };
+use super::sync_policy::base::{ SyncPolicy, ThreadSafePolicy };
+use super::sync_policy::single_threaded_policy::SingleThreadedPolicy;
+use super::memory_tracker::base::{ MemoryTracker };
+use super::memory_tracker::empty_memory_tracker::EmptyMemoryTracker;
 
 ///
 /// A MemoryRealm is a combination of an allocation strategy and a bounds checking
 /// strategy to combine each possible allocator with different bounds checking variations.
-/// This system of a memory realm could be extended by implementing further memory tracking
-/// and thread synchronisation strategies which would allow for an even broader variation of
-/// memory realms.
+/// The sync policy decides how calls into the allocator are synchronized, realms using a
+/// ThreadSafePolicy are Sync and can be shared across threads. The memory tracker gets
+/// notified about every allocation, which allows to count them or to report leaks with the
+/// source location of the allocation once the realm is dropped.
 ///
-pub struct BasicMemoryRealm<A: Allocator + BasicAllocator, B: BoundsChecker + Default> {
+pub struct BasicMemoryRealm<A, B, S = SingleThreadedPolicy, T = EmptyMemoryTracker>
+    where A: Allocator + BasicAllocator, B: BoundsChecker + Default, S: SyncPolicy + Default, T: MemoryTracker + Default {
     allocator: A,
     bounds_checker: B,
+    sync_policy: S,
+    memory_tracker: T,
 }
 
-impl<A: Allocator, B: BoundsChecker + Default> BasicMemoryRealm<A, B>
-    where A: Allocator + BasicAllocator<AllocatorImplementation = A> {
-    pub fn new(size: usize) -> BasicMemoryRealm<A, B> {
+// The allocator and the tracker are only ever accessed from inside of the synchronized calls of the policy,
+// so the allocator has to be Send to be used by whichever thread currently holds the policy
+// The tracker is also handed out by `memory_tracker`, so sharing the realm requires a Sync tracker
+unsafe impl<A, B, S, T> Send for BasicMemoryRealm<A, B, S, T>
+    where A: Allocator + BasicAllocator + Send, B: BoundsChecker + Default + Send, S: ThreadSafePolicy + Default, T: MemoryTracker + Default + Send {}
+unsafe impl<A, B, S, T> Sync for BasicMemoryRealm<A, B, S, T>
+    where A: Allocator + BasicAllocator + Send, B: BoundsChecker + Default + Sync, S: ThreadSafePolicy + Default, T: MemoryTracker + Default + Sync {}
+
+impl<A, B, S, T> BasicMemoryRealm<A, B, S, T>
+    where A: Allocator + BasicAllocator<AllocatorImplementation = A>, B: BoundsChecker + Default, S: SyncPolicy + Default, T: MemoryTracker + Default {
+    pub fn new(size: usize) -> BasicMemoryRealm<A, B, S, T> {
         BasicMemoryRealm {
             allocator: A::new(size),
             bounds_checker: Default::default(),
+            sync_policy: Default::default(),
+            memory_tracker: Default::default(),
         }
     }
 
+    #[track_caller]
     pub fn alloc(&self, size: usize, alignment: usize) -> Option<MemoryBlock> {
+        let location = Location::caller();
         let canary_size = self.bounds_checker.get_canary_size() as usize;
         let total_allocation_size = size + (canary_size * 2) as usize;
         
-        let block = self.allocator.alloc_raw(total_allocation_size, alignment, canary_size);
+        let block = self.sync_policy.synchronized(|| {
+            let block = self.allocator.alloc_raw(total_allocation_size, alignment, canary_size);
+
+            if let Some(ref block) = block {
+                let user_ptr = unsafe { block.ptr.offset(canary_size as isize) };
+                self.memory_tracker.on_alloc(user_ptr, size, alignment, location);
+            }
+
+            block
+        });
         
         if block.is_none() {
             return None;
@@ -44,20 +75,38 @@ impl<A: Allocator, B: BoundsChecker + Default> BasicMemoryRealm<A, B>
 
     pub fn dealloc(&self, mem_block: MemoryBlock) {
         let canary_size = self.bounds_checker.get_canary_size() as usize;
+        let user_ptr = mem_block.ptr;
 
         unsafe {
-            let allocated_ptr = mem_block.ptr.offset(-(canary_size as isize));
-            let allocation_size = self.allocator.get_allocation_size(&mem_block);
+            let original_mem_block = MemoryBlock { ptr: mem_block.ptr.offset(-(canary_size as isize)), ..mem_block };
+            let allocation_size = self.sync_policy.synchronized(|| self.allocator.get_allocation_size(&original_mem_block));
 
-            self.bounds_checker.validate_front_canary(allocated_ptr);
-            self.bounds_checker.validate_back_canary(allocated_ptr.offset((allocation_size - canary_size) as isize));
+            self.bounds_checker.validate_front_canary(original_mem_block.ptr);
+            self.bounds_checker.validate_back_canary(original_mem_block.ptr.offset((allocation_size - canary_size) as isize));
 
-            self.allocator.dealloc_raw(mem_block);
+            self.sync_policy.synchronized(|| {
+                self.memory_tracker.on_dealloc(user_ptr, allocation_size - (canary_size * 2));
+                self.allocator.dealloc_raw(original_mem_block);
+            });
         }
     }
 
     pub unsafe fn reset(&self) {
-        self.allocator.reset();
+        self.sync_policy.synchronized(|| {
+            self.allocator.reset();
+            self.memory_tracker.on_reset();
+        });
+    }
+
+    pub fn memory_tracker(&self) -> &T {
+        &self.memory_tracker
+    }
+}
+
+impl<A, B, S, T> Drop for BasicMemoryRealm<A, B, S, T>
+    where A: Allocator + BasicAllocator, B: BoundsChecker + Default, S: SyncPolicy + Default, T: MemoryTracker + Default {
+    fn drop(&mut self) {
+        self.memory_tracker.report_leaks();
     }
 }
 
@@ -66,6 +115,38 @@ mod tests {
     use super::*;
     use super::super::allocators;
     use super::super::bounds_checker;
+    use super::super::sync_policy;
+    use super::super::memory_tracker;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn assert_sync<T: Sync + Send>() {}
+
+    fn assert_send<T: Send>() {}
+
+    fn alloc_from_threads<S: ThreadSafePolicy + Default + 'static>() {
+        type SharedRealm<S> = BasicMemoryRealm<allocators::free_list_allocator::FreeListAllocator, bounds_checker::simple_bounds_checker::SimpleBoundsChecker, S>;
+
+        let realm: Arc<SharedRealm<S>> = Arc::new(SharedRealm::new(1024 * 1024));
+
+        let threads: Vec<_> = (0 .. 4).map(|thread_idx| {
+            let realm = realm.clone();
+            thread::spawn(move || {
+                for i in 0 .. 1000usize {
+                    let block = realm.alloc(64, 8).unwrap();
+                    unsafe { *(block.ptr as *mut usize) = thread_idx * i };
+                    assert_eq!(unsafe { *(block.ptr as *const usize) }, thread_idx * i);
+                    realm.dealloc(block);
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(realm.alloc(1000 * 1024, 8).is_some(), "Concurrent frees did not return all memory");
+    }
 
     #[test]
     fn linear_alloc_simple_bounds_checking_realm() {
@@ -81,5 +162,108 @@ mod tests {
         assert_eq!(back_marker, 0xCA);
 
     }
-}
 
+    #[test]
+    fn guard_page_alloc_empty_bounds_checking_realm() {
+        type GuardedRealm = BasicMemoryRealm<allocators::guard_page_allocator::GuardPageAllocator, bounds_checker::empty_bounds_checker::EmptyBoundsChecker>;
+
+        let realm: GuardedRealm = GuardedRealm::new(100);
+
+        let block = realm.alloc(4, 1).unwrap();
+        unsafe { *(block.ptr as *mut u32) = 0xDEADBEEF };
+
+        realm.dealloc(block);
+    }
+
+    #[test]
+    fn free_list_alloc_simple_bounds_checking_realm() {
+        type HeapRealm = BasicMemoryRealm<allocators::free_list_allocator::FreeListAllocator<allocators::free_list_allocator::BestFit>, bounds_checker::simple_bounds_checker::SimpleBoundsChecker>;
+
+        let realm: HeapRealm = HeapRealm::new(1024);
+
+        let block_0 = realm.alloc(16, 8).unwrap();
+        let block_1 = realm.alloc(32, 8).unwrap();
+        unsafe { *(block_1.ptr.offset(28) as *mut u32) = 0xDEADBEEF };
+
+        // Frees in arbitrary order validate the canaries of the original allocation
+        realm.dealloc(block_0);
+        realm.dealloc(block_1);
+
+        assert!(realm.alloc(900, 8).is_some(), "Freed blocks were not returned to the allocator");
+    }
+
+    #[test]
+    #[should_panic(expected = "Back canary was not valid")]
+    fn free_list_alloc_detects_overrun() {
+        type HeapRealm = BasicMemoryRealm<allocators::free_list_allocator::FreeListAllocator, bounds_checker::simple_bounds_checker::SimpleBoundsChecker>;
+
+        let realm: HeapRealm = HeapRealm::new(1024);
+
+        let block = realm.alloc(16, 8).unwrap();
+        unsafe { *(block.ptr.offset(16) as *mut u32) = 0xDEADBEEF };
+
+        realm.dealloc(block);
+    }
+
+    #[test]
+    fn thread_safe_policies_make_realms_sync() {
+        assert_sync::<BasicMemoryRealm<allocators::linear_allocator::LinearAllocator, bounds_checker::empty_bounds_checker::EmptyBoundsChecker, sync_policy::mutex_policy::MutexPolicy>>();
+        assert_sync::<BasicMemoryRealm<allocators::linear_allocator::LinearAllocator, bounds_checker::empty_bounds_checker::EmptyBoundsChecker, sync_policy::spin_lock_policy::SpinLockPolicy>>();
+    }
+
+    #[test]
+    fn full_tracker_realm_can_be_sent() {
+        assert_send::<BasicMemoryRealm<allocators::linear_allocator::LinearAllocator, bounds_checker::empty_bounds_checker::EmptyBoundsChecker, sync_policy::mutex_policy::MutexPolicy, memory_tracker::full_memory_tracker::FullMemoryTracker>>();
+    }
+
+    #[test]
+    fn mutex_policy_realm_shared_across_threads() {
+        alloc_from_threads::<sync_policy::mutex_policy::MutexPolicy>();
+    }
+
+    #[test]
+    fn spin_lock_policy_realm_shared_across_threads() {
+        alloc_from_threads::<sync_policy::spin_lock_policy::SpinLockPolicy>();
+    }
+
+    #[test]
+    fn counting_tracker_realm() {
+        type CountedRealm = BasicMemoryRealm<allocators::free_list_allocator::FreeListAllocator, bounds_checker::simple_bounds_checker::SimpleBoundsChecker, sync_policy::single_threaded_policy::SingleThreadedPolicy, memory_tracker::counting_memory_tracker::CountingMemoryTracker>;
+
+        let realm: CountedRealm = CountedRealm::new(1024);
+
+        let block_0 = realm.alloc(16, 8).unwrap();
+        let block_1 = realm.alloc(100, 8).unwrap();
+        realm.dealloc(block_0);
+
+        assert_eq!(realm.memory_tracker().allocation_count(), 1);
+        assert_eq!(realm.memory_tracker().allocated_bytes(), 100);
+        assert_eq!(realm.memory_tracker().peak_allocated_bytes(), 116);
+
+        realm.dealloc(block_1);
+        assert_eq!(realm.memory_tracker().allocated_bytes(), 0);
+    }
+
+    #[test]
+    fn full_tracker_realm_records_callsites() {
+        type TrackedRealm = BasicMemoryRealm<allocators::linear_allocator::LinearAllocator, bounds_checker::simple_bounds_checker::SimpleBoundsChecker, sync_policy::single_threaded_policy::SingleThreadedPolicy, memory_tracker::full_memory_tracker::FullMemoryTracker>;
+
+        let realm: TrackedRealm = TrackedRealm::new(1024);
+
+        let alloc_line = line!(); let block = realm.alloc(24, 8).unwrap();
+
+        let records = realm.memory_tracker().live_allocations();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].memory, block.ptr as usize);
+        assert_eq!(records[0].size, 24);
+        assert_eq!(records[0].alignment, 8);
+        assert_eq!(records[0].location.file(), file!());
+        assert_eq!(records[0].location.line(), alloc_line, "Tracker did not record the callsite of the allocation");
+
+        let report = realm.memory_tracker().leak_report().unwrap();
+        assert!(report.contains(&format!("{}:{}", file!(), alloc_line)));
+
+        unsafe { realm.reset() };
+        assert!(realm.memory_tracker().leak_report().is_none(), "Reset did not release the live allocations");
+    }
+}
\ No newline at end of file
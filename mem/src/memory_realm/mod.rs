@@ -1,5 +1,7 @@
 use super::allocators;
 use super::bounds_checker;
+use super::sync_policy;
+use super::memory_tracker;
 
 pub mod basic_realm;
 pub mod typed_realm;
\ No newline at end of file
@@ -1,22 +1,40 @@
+use std::panic::Location;
+
 use super::allocators::base::{ Allocator, MemoryBlock, TypedAllocator };
 use super::bounds_checker::base::{ BoundsChecker };
+use super::sync_policy::base::{ SyncPolicy, ThreadSafePolicy };
+use super::sync_policy::single_threaded_policy::SingleThreadedPolicy;
+use super::memory_tracker::base::{ MemoryTracker };
+use super::memory_tracker::empty_memory_tracker::EmptyMemoryTracker;
 
 ///
 /// A TypedMemoryRealm is a combination of an allocation strategy that assumes every allocation
 /// is (at most - it's possible to vary inside of one block) from the same size and a bounds checking
 /// strategy to combine each possible allocator with different bounds checking variations.
-/// This system of a memory realm could be extended by implementing further memory tracking
-/// and thread synchronisation strategies which would allow for an even broader variation of
-/// memory realms.
+/// The sync policy decides how calls into the allocator are synchronized, realms using a
+/// ThreadSafePolicy are Sync and can be shared across threads. The memory tracker gets
+/// notified about every allocation, which allows to count them or to report leaks with the
+/// source location of the allocation once the realm is dropped.
 ///
-pub struct TypedMemoryRealm<A: Allocator + TypedAllocator, B: BoundsChecker + Default> {
+pub struct TypedMemoryRealm<A, B, S = SingleThreadedPolicy, T = EmptyMemoryTracker>
+    where A: Allocator + TypedAllocator, B: BoundsChecker + Default, S: SyncPolicy + Default, T: MemoryTracker + Default {
     allocator: A,
     bounds_checker: B,
+    sync_policy: S,
+    memory_tracker: T,
 }
 
-impl<A: Allocator + TypedAllocator, B: BoundsChecker + Default> TypedMemoryRealm<A, B>
-    where A: Allocator + TypedAllocator<AllocatorImplementation = A> {
-    pub fn new(element_size: usize, element_count: usize, element_alignment: usize) -> TypedMemoryRealm<A, B> {
+// The allocator and the tracker are only ever accessed from inside of the synchronized calls of the policy,
+// so the allocator has to be Send to be used by whichever thread currently holds the policy
+// The tracker is also handed out by `memory_tracker`, so sharing the realm requires a Sync tracker
+unsafe impl<A, B, S, T> Send for TypedMemoryRealm<A, B, S, T>
+    where A: Allocator + TypedAllocator + Send, B: BoundsChecker + Default + Send, S: ThreadSafePolicy + Default, T: MemoryTracker + Default + Send {}
+unsafe impl<A, B, S, T> Sync for TypedMemoryRealm<A, B, S, T>
+    where A: Allocator + TypedAllocator + Send, B: BoundsChecker + Default + Sync, S: ThreadSafePolicy + Default, T: MemoryTracker + Default + Sync {}
+
+impl<A, B, S, T> TypedMemoryRealm<A, B, S, T>
+    where A: Allocator + TypedAllocator<AllocatorImplementation = A>, B: BoundsChecker + Default, S: SyncPolicy + Default, T: MemoryTracker + Default {
+    pub fn new(element_size: usize, element_count: usize, element_alignment: usize) -> TypedMemoryRealm<A, B, S, T> {
         let bounds_checker: B = Default::default();
         
         // Here we alter the element_size by twice the canary size to
@@ -28,14 +46,27 @@ impl<A: Allocator + TypedAllocator, B: BoundsChecker + Default> TypedMemoryRealm
         TypedMemoryRealm {
             allocator: A::new(type_size_with_offset, element_count, element_alignment, canary_size),
             bounds_checker,
+            sync_policy: Default::default(),
+            memory_tracker: Default::default(),
         }
     }
 
+    #[track_caller]
     pub fn alloc(&self, size: usize, alignment: usize) -> Option<MemoryBlock> {
+        let location = Location::caller();
         let canary_size = self.bounds_checker.get_canary_size() as usize;
         let _offset_not_needed = 0;
         
-        let block = self.allocator.alloc_raw(size, alignment, _offset_not_needed);
+        let block = self.sync_policy.synchronized(|| {
+            let block = self.allocator.alloc_raw(size, alignment, _offset_not_needed);
+
+            if let Some(ref block) = block {
+                let user_ptr = unsafe { block.ptr.offset(canary_size as isize) };
+                self.memory_tracker.on_alloc(user_ptr, size, alignment, location);
+            }
+
+            block
+        });
 
         if block.is_none() {
             return None;
@@ -53,20 +84,38 @@ impl<A: Allocator + TypedAllocator, B: BoundsChecker + Default> TypedMemoryRealm
 
     pub fn dealloc(&self, mem_block: MemoryBlock) {
         let canary_size = self.bounds_checker.get_canary_size() as usize;
+        let user_ptr = mem_block.ptr;
 
         unsafe {
             let original_mem_block = MemoryBlock { ptr: mem_block.ptr.offset(-(canary_size as isize)), ..mem_block };
-            let allocation_size = self.allocator.get_allocation_size(&original_mem_block);
+            let allocation_size = self.sync_policy.synchronized(|| self.allocator.get_allocation_size(&original_mem_block));
 
             self.bounds_checker.validate_front_canary(original_mem_block.ptr);
             self.bounds_checker.validate_back_canary(original_mem_block.ptr.offset((allocation_size + canary_size) as isize));
 
-            self.allocator.dealloc_raw(original_mem_block);
+            self.sync_policy.synchronized(|| {
+                self.memory_tracker.on_dealloc(user_ptr, allocation_size);
+                self.allocator.dealloc_raw(original_mem_block);
+            });
         }
     }
 
     pub unsafe fn reset(&self) {
-        self.allocator.reset();
+        self.sync_policy.synchronized(|| {
+            self.allocator.reset();
+            self.memory_tracker.on_reset();
+        });
+    }
+
+    pub fn memory_tracker(&self) -> &T {
+        &self.memory_tracker
+    }
+}
+
+impl<A, B, S, T> Drop for TypedMemoryRealm<A, B, S, T>
+    where A: Allocator + TypedAllocator, B: BoundsChecker + Default, S: SyncPolicy + Default, T: MemoryTracker + Default {
+    fn drop(&mut self) {
+        self.memory_tracker.report_leaks();
     }
 }
 
@@ -78,6 +127,7 @@ mod tests {
     use super::*;
     use super::super::allocators;
     use super::super::bounds_checker;
+    use super::super::sync_policy;
 
     struct Particle {
         pub lifetime: f32,
@@ -122,4 +172,27 @@ mod tests {
             typed_pool.dealloc(particle_mem);
         }
     }
+
+    #[test]
+    fn typed_realm_shared_across_threads() {
+        type SharedPool = TypedMemoryRealm<allocators::pool_allocator::PoolAllocator, bounds_checker::simple_bounds_checker::SimpleBoundsChecker, sync_policy::spin_lock_policy::SpinLockPolicy>;
+
+        let typed_pool = std::sync::Arc::new(SharedPool::new(std::mem::size_of::<Particle>(), 40, 4));
+
+        let threads: Vec<_> = (0 .. 4).map(|_| {
+            let typed_pool = typed_pool.clone();
+            std::thread::spawn(move || {
+                for _ in 0 .. 1000 {
+                    let particles: Vec<_> = (0 .. 10).map(|_| typed_pool.alloc(std::mem::size_of::<Particle>(), 4).unwrap()).collect();
+                    for particle_mem in particles {
+                        typed_pool.dealloc(particle_mem);
+                    }
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
 }
\ No newline at end of file
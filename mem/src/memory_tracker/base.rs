@@ -0,0 +1,20 @@
+use std::panic::Location;
+
+///
+/// A MemoryTracker gets notified about every allocation and deallocation of a memory realm.
+/// Pointers and sizes are the ones the user sees, canaries of the bounds checker excluded
+///
+pub trait MemoryTracker {
+    fn on_alloc(&self, memory: *const u8, size: usize, alignment: usize, location: &'static Location<'static>);
+    fn on_dealloc(&self, memory: *const u8, size: usize);
+
+    ///
+    /// Called when the realm resets its allocator, which releases all live allocations at once
+    ///
+    fn on_reset(&self);
+
+    ///
+    /// Called when the realm is dropped, trackers knowing about live allocations report them as leaks
+    ///
+    fn report_leaks(&self) {}
+}
\ No newline at end of file
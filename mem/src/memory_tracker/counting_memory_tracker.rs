@@ -0,0 +1,92 @@
+use std::cell::Cell;
+use std::panic::Location;
+use super::base::{ MemoryTracker };
+
+///
+/// The CountingMemoryTracker keeps track of the amount of live allocations and
+/// bytes, as well as the peak amount of bytes that were alive at the same time
+///
+pub struct CountingMemoryTracker {
+    allocation_count:       Cell<usize>,
+    allocated_bytes:        Cell<usize>,
+    peak_allocated_bytes:   Cell<usize>,
+    total_allocation_count: Cell<usize>,
+}
+
+impl Default for CountingMemoryTracker {
+    fn default() -> CountingMemoryTracker {
+        CountingMemoryTracker {
+            allocation_count:       Cell::new(0),
+            allocated_bytes:        Cell::new(0),
+            peak_allocated_bytes:   Cell::new(0),
+            total_allocation_count: Cell::new(0),
+        }
+    }
+}
+
+impl CountingMemoryTracker {
+    #[inline]
+    pub fn allocation_count(&self) -> usize { self.allocation_count.get() }
+
+    #[inline]
+    pub fn allocated_bytes(&self) -> usize { self.allocated_bytes.get() }
+
+    #[inline]
+    pub fn peak_allocated_bytes(&self) -> usize { self.peak_allocated_bytes.get() }
+
+    ///
+    /// Returns the amount of allocations done since the tracker was created, freed ones included
+    ///
+    #[inline]
+    pub fn total_allocation_count(&self) -> usize { self.total_allocation_count.get() }
+}
+
+impl MemoryTracker for CountingMemoryTracker {
+    fn on_alloc(&self, _memory: *const u8, size: usize, _alignment: usize, _location: &'static Location<'static>) {
+        let allocated_bytes = self.allocated_bytes.get() + size;
+
+        self.allocation_count.set(self.allocation_count.get() + 1);
+        self.total_allocation_count.set(self.total_allocation_count.get() + 1);
+        self.allocated_bytes.set(allocated_bytes);
+
+        if allocated_bytes > self.peak_allocated_bytes.get() {
+            self.peak_allocated_bytes.set(allocated_bytes);
+        }
+    }
+
+    fn on_dealloc(&self, _memory: *const u8, size: usize) {
+        self.allocation_count.set(self.allocation_count.get() - 1);
+        self.allocated_bytes.set(self.allocated_bytes.get() - size);
+    }
+
+    fn on_reset(&self) {
+        self.allocation_count.set(0);
+        self.allocated_bytes.set(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_live_allocations_and_peak() {
+        let tracker: CountingMemoryTracker = Default::default();
+        let location = Location::caller();
+
+        tracker.on_alloc(0x10 as *const u8, 100, 8, location);
+        tracker.on_alloc(0x80 as *const u8, 50, 8, location);
+        tracker.on_dealloc(0x10 as *const u8, 100);
+        tracker.on_alloc(0x10 as *const u8, 20, 8, location);
+
+        assert_eq!(tracker.allocation_count(), 2);
+        assert_eq!(tracker.allocated_bytes(), 70);
+        assert_eq!(tracker.peak_allocated_bytes(), 150);
+        assert_eq!(tracker.total_allocation_count(), 3);
+
+        tracker.on_reset();
+        assert_eq!(tracker.allocation_count(), 0);
+        assert_eq!(tracker.allocated_bytes(), 0);
+        assert_eq!(tracker.peak_allocated_bytes(), 150);
+    }
+}
\ No newline at end of file
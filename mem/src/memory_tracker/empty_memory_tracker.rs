@@ -0,0 +1,20 @@
+use std::panic::Location;
+use super::base::{ MemoryTracker };
+
+///
+/// The EmptyMemoryTracker ignores all notifications, which disables memory
+/// tracking in release/retail configurations without any overhead
+///
+pub struct EmptyMemoryTracker {}
+
+impl Default for EmptyMemoryTracker {
+    fn default() -> EmptyMemoryTracker {
+        EmptyMemoryTracker {}
+    }
+}
+
+impl MemoryTracker for EmptyMemoryTracker {
+    fn on_alloc(&self, _memory: *const u8, _size: usize, _alignment: usize, _location: &'static Location<'static>) {}
+    fn on_dealloc(&self, _memory: *const u8, _size: usize) {}
+    fn on_reset(&self) {}
+}
\ No newline at end of file
@@ -0,0 +1,142 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::panic::Location;
+use super::base::{ MemoryTracker };
+
+///
+/// Describes a live allocation and the source location it was requested from. The address
+/// is kept as an integer, so the records can be sent to other threads
+///
+#[derive(Debug, Clone, Copy)]
+pub struct AllocationRecord {
+    pub memory:     usize,
+    pub size:       usize,
+    pub alignment:  usize,
+    pub location:   &'static Location<'static>,
+}
+
+///
+/// The FullMemoryTracker records every live allocation together with the source
+/// location that requested it. Allocations that are still alive when the realm is
+/// dropped are reported as leaks
+///
+pub struct FullMemoryTracker {
+    live_allocations: RefCell<HashMap<usize, AllocationRecord>>,
+}
+
+impl Default for FullMemoryTracker {
+    fn default() -> FullMemoryTracker {
+        FullMemoryTracker {
+            live_allocations: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl FullMemoryTracker {
+    ///
+    /// Returns the records of all live allocations, sorted by address
+    ///
+    pub fn live_allocations(&self) -> Vec<AllocationRecord> {
+        let mut records: Vec<AllocationRecord> = self.live_allocations.borrow().values().cloned().collect();
+        records.sort_by_key(|record| record.memory);
+        records
+    }
+
+    ///
+    /// Returns a report listing all live allocations, or None if there are none
+    ///
+    pub fn leak_report(&self) -> Option<String> {
+        let records = self.live_allocations();
+
+        if records.is_empty() {
+            return None;
+        }
+
+        let leaked_bytes: usize = records.iter().map(|record| record.size).sum();
+        let mut report = format!("{} allocations with a total of {} bytes were leaked:\n", records.len(), leaked_bytes);
+        for record in &records {
+            report.push_str(&format!(
+                "    {} bytes (alignment {}) at {:#x}, allocated at {}\n",
+                record.size,
+                record.alignment,
+                record.memory,
+                record.location
+            ));
+        }
+
+        Some(report)
+    }
+}
+
+impl MemoryTracker for FullMemoryTracker {
+    fn on_alloc(&self, memory: *const u8, size: usize, alignment: usize, location: &'static Location<'static>) {
+        let record = AllocationRecord {
+            memory: memory as usize,
+            size,
+            alignment,
+            location,
+        };
+
+        let previous_record = self.live_allocations.borrow_mut().insert(memory as usize, record);
+        debug_assert!(previous_record.is_none(), "Memory was handed out twice without being freed");
+    }
+
+    fn on_dealloc(&self, memory: *const u8, _size: usize) {
+        let record = self.live_allocations.borrow_mut().remove(&(memory as usize));
+        debug_assert!(record.is_some(), "Freed memory that is not a live allocation");
+    }
+
+    fn on_reset(&self) {
+        self.live_allocations.borrow_mut().clear();
+    }
+
+    fn report_leaks(&self) {
+        if let Some(report) = self.leak_report() {
+            eprintln!("{}", report);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_live_allocations() {
+        let tracker: FullMemoryTracker = Default::default();
+        let location = Location::caller();
+
+        tracker.on_alloc(0x80 as *const u8, 50, 16, location);
+        tracker.on_alloc(0x10 as *const u8, 100, 8, location);
+        tracker.on_dealloc(0x80 as *const u8, 50);
+
+        let records = tracker.live_allocations();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].memory, 0x10);
+        assert_eq!(records[0].size, 100);
+        assert_eq!(records[0].alignment, 8);
+        assert_eq!(records[0].location.file(), file!());
+
+        tracker.on_reset();
+        assert!(tracker.leak_report().is_none());
+    }
+
+    #[test]
+    fn leak_report_contains_source_location() {
+        let tracker: FullMemoryTracker = Default::default();
+        let location = Location::caller();
+
+        tracker.on_alloc(0x10 as *const u8, 100, 8, location);
+
+        let report = tracker.leak_report().unwrap();
+        assert!(report.contains("100 bytes"));
+        assert!(report.contains(&format!("{}:{}", file!(), location.line())), "Report did not name the source location");
+    }
+
+    #[test]
+    #[should_panic(expected = "Freed memory that is not a live allocation")]
+    fn detects_unknown_frees() {
+        let tracker: FullMemoryTracker = Default::default();
+        tracker.on_dealloc(0x10 as *const u8, 100);
+    }
+}
\ No newline at end of file
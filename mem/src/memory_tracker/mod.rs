@@ -0,0 +1,4 @@
+pub mod base;
+pub mod empty_memory_tracker;
+pub mod counting_memory_tracker;
+pub mod full_memory_tracker;
\ No newline at end of file
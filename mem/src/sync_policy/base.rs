@@ -0,0 +1,14 @@
+///
+/// A SyncPolicy decides how the accesses of a memory realm to its allocator are synchronized.
+/// Every call into the allocator is wrapped into `synchronized`, which runs it exclusively
+/// if the policy provides mutual exclusion
+///
+pub trait SyncPolicy {
+    fn synchronized<R, F: FnOnce() -> R>(&self, f: F) -> R;
+}
+
+///
+/// Marker for policies that provide mutual exclusion between threads, memory
+/// realms using such a policy can be shared across threads
+///
+pub unsafe trait ThreadSafePolicy: SyncPolicy + Send + Sync {}
\ No newline at end of file
@@ -0,0 +1,4 @@
+pub mod base;
+pub mod single_threaded_policy;
+pub mod mutex_policy;
+pub mod spin_lock_policy;
\ No newline at end of file
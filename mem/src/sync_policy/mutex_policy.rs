@@ -0,0 +1,28 @@
+use std::sync::{ Mutex, PoisonError };
+use super::base::{ SyncPolicy, ThreadSafePolicy };
+
+///
+/// The MutexPolicy serializes all calls with an OS mutex, threads waiting for
+/// the lock are put to sleep which suits realms with long or contended calls
+///
+pub struct MutexPolicy {
+    mutex: Mutex<()>,
+}
+
+impl Default for MutexPolicy {
+    fn default() -> MutexPolicy {
+        MutexPolicy {
+            mutex: Mutex::new(()),
+        }
+    }
+}
+
+impl SyncPolicy for MutexPolicy {
+    fn synchronized<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        // A panicking canary check poisons the mutex, the allocator itself is still consistent
+        let _guard = self.mutex.lock().unwrap_or_else(PoisonError::into_inner);
+        f()
+    }
+}
+
+unsafe impl ThreadSafePolicy for MutexPolicy {}
\ No newline at end of file
@@ -0,0 +1,18 @@
+use super::base::{ SyncPolicy };
+
+///
+/// The SingleThreadedPolicy does not synchronize at all and every call runs directly.
+/// Memory realms using it stay !Sync and can only be used from the thread owning them
+///
+pub struct SingleThreadedPolicy {}
+
+impl Default for SingleThreadedPolicy {
+    fn default() -> SingleThreadedPolicy {
+        SingleThreadedPolicy {}
+    }
+}
+
+impl SyncPolicy for SingleThreadedPolicy {
+    #[inline]
+    fn synchronized<R, F: FnOnce() -> R>(&self, f: F) -> R { f() }
+}
\ No newline at end of file
@@ -0,0 +1,103 @@
+use std::sync::atomic::{ AtomicBool, Ordering };
+use super::base::{ SyncPolicy, ThreadSafePolicy };
+
+///
+/// The SpinLockPolicy serializes all calls with a busy waiting lock, which avoids
+/// putting threads to sleep and suits realms with short and rarely contended calls
+///
+pub struct SpinLockPolicy {
+    locked: AtomicBool,
+}
+
+impl SpinLockPolicy {
+    pub const fn new() -> SpinLockPolicy {
+        SpinLockPolicy {
+            locked: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Default for SpinLockPolicy {
+    fn default() -> SpinLockPolicy {
+        SpinLockPolicy::new()
+    }
+}
+
+///
+/// Releases the lock when dropped, which also happens if the synchronized call panics
+///
+struct SpinLockGuard<'a> {
+    locked: &'a AtomicBool,
+}
+
+impl<'a> Drop for SpinLockGuard<'a> {
+    fn drop(&mut self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+impl SyncPolicy for SpinLockPolicy {
+    fn synchronized<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            // Wait for the lock to look free before trying to take it again
+            while self.locked.load(Ordering::Relaxed) {
+                std::hint::spin_loop();
+            }
+        }
+
+        let _guard = SpinLockGuard { locked: &self.locked };
+        f()
+    }
+}
+
+unsafe impl ThreadSafePolicy for SpinLockPolicy {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::cell::UnsafeCell;
+    use std::thread;
+
+    struct SharedCounter {
+        policy: SpinLockPolicy,
+        count:  UnsafeCell<usize>,
+    }
+
+    unsafe impl Sync for SharedCounter {}
+
+    #[test]
+    fn synchronized_calls_are_exclusive() {
+        let counter = Arc::new(SharedCounter {
+            policy: Default::default(),
+            count:  UnsafeCell::new(0),
+        });
+
+        let threads: Vec<_> = (0 .. 4).map(|_| {
+            let counter = counter.clone();
+            thread::spawn(move || {
+                for _ in 0 .. 10000 {
+                    counter.policy.synchronized(|| unsafe { *counter.count.get() += 1 });
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(unsafe { *counter.count.get() }, 40000, "Concurrent increments were lost");
+    }
+
+    #[test]
+    fn lock_is_released_on_panic() {
+        let policy: SpinLockPolicy = Default::default();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            policy.synchronized(|| panic!("Synchronized call failed"));
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(policy.synchronized(|| 42), 42);
+    }
+}
\ No newline at end of file
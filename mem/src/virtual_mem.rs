@@ -1,12 +1,48 @@
+#[cfg(windows)]
 extern crate winapi;
+#[cfg(unix)]
+extern crate libc;
 
+#[cfg(windows)]
 use std::mem;
 use std::ptr;
-use virtual_mem::winapi::shared::minwindef::{ LPVOID };
+use std::cmp;
+use spark_core::math_util;
+#[cfg(windows)]
+use virtual_mem::winapi::shared::minwindef::{ LPVOID, DWORD };
+#[cfg(windows)]
 use virtual_mem::winapi::um::sysinfoapi;
-use virtual_mem::winapi::um::memoryapi::{ VirtualAlloc, VirtualFree };
-use virtual_mem::winapi::um::winnt::{ MEM_COMMIT, MEM_RESERVE, MEM_DECOMMIT, MEM_RELEASE, PAGE_READWRITE, PAGE_NOACCESS};
+#[cfg(windows)]
+use virtual_mem::winapi::um::memoryapi::{ VirtualAlloc, VirtualFree, VirtualProtect };
+#[cfg(windows)]
+use virtual_mem::winapi::um::winnt::{ MEM_COMMIT, MEM_RESERVE, MEM_DECOMMIT, MEM_RELEASE, PAGE_READWRITE, PAGE_READONLY, PAGE_NOACCESS};
+
+///
+/// Access protection that can be applied to committed pages
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageProtection {
+    NoAccess,
+    ReadOnly,
+    ReadWrite,
+}
 
+///
+/// Size of the pages backing a VirtualRegion. `TransparentHuge` asks the kernel to
+/// back the memory with huge pages whenever it can, `Huge` maps explicit huge pages
+/// taken from the pool configured in /proc/sys/vm/nr_hugepages
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageMode {
+    Default,
+    TransparentHuge,
+    Huge,
+}
+
+///
+/// Returns the size of a default page. Regions backed by huge pages commit with a bigger
+/// granularity, which is reported by `VirtualRegion::page_size`
+///
 #[cfg(windows)]
 pub fn get_page_size() -> usize {
     let mut sys_info: sysinfoapi::SYSTEM_INFO = unsafe { mem::zeroed() };
@@ -64,18 +100,485 @@ pub fn decommit_physical_memory(base_address: *mut u8, mem_size: usize) {
     }
 }
 
+///
+/// Releases the whole address space reserved at `base_address`. VirtualFree
+/// does not need the size of the reservation, it is only required by the
+/// POSIX implementation and ignored here
+///
 #[cfg(windows)]
-pub fn free_address_space(base_address: *mut u8) {
+pub fn free_address_space(base_address: *mut u8, _mem_size: usize) {
     unsafe {
         VirtualFree(base_address as LPVOID, 0usize, MEM_RELEASE);
     }
 }
 
-#[cfg(test)]
+#[cfg(windows)]
+pub fn protect_memory(base_address: *mut u8, mem_size: usize, protection: PageProtection) -> bool {
+    let page_protection = match protection {
+        PageProtection::NoAccess => PAGE_NOACCESS,
+        PageProtection::ReadOnly => PAGE_READONLY,
+        PageProtection::ReadWrite => PAGE_READWRITE,
+    };
+
+    let mut old_page_protection: DWORD = 0;
+
+    unsafe {
+        VirtualProtect(base_address as LPVOID, mem_size, page_protection, &mut old_page_protection) != 0
+    }
+}
+
+///
+/// Returns the size of a default page. Regions backed by huge pages commit with a bigger
+/// granularity, which is reported by `VirtualRegion::page_size`
+///
+#[cfg(unix)]
+pub fn get_page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+///
+/// Reserves address space by mapping it PROT_NONE. MAP_NORESERVE keeps the kernel
+/// from accounting the whole range against the commit limit before it is used
+///
+#[cfg(unix)]
+pub fn reserve_address_space(mem_size: usize) -> Option<*mut u8> {
+    let raw_mem = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            mem_size,
+            libc::PROT_NONE,
+            libc::MAP_PRIVATE | libc::MAP_ANON | libc::MAP_NORESERVE,
+            -1,
+            0
+        )
+    };
+
+    if raw_mem != libc::MAP_FAILED
+    {
+        Some(raw_mem as *mut u8)
+    }
+    else
+    {
+        None
+    }
+}
+
+///
+/// Commits physical memory by making the reserved pages readable and writable,
+/// the kernel backs them with zeroed pages on first touch. In contrast to VirtualAlloc
+/// `base_address` has to be page aligned
+///
+#[cfg(unix)]
+pub fn commit_physical_memory(base_address: *mut u8, mem_size: usize) -> Option<*mut u8> {
+    let result = unsafe { libc::mprotect(base_address as *mut libc::c_void, mem_size, libc::PROT_READ | libc::PROT_WRITE) };
+
+    if result == 0
+    {
+        Some(base_address)
+    }
+    else
+    {
+        None
+    }
+}
+
+///
+/// Hands the physical pages back to the OS and protects the range again so
+/// that it behaves like reserved, but not committed memory
+///
+#[cfg(unix)]
+pub fn decommit_physical_memory(base_address: *mut u8, mem_size: usize) {
+    unsafe {
+        libc::madvise(base_address as *mut libc::c_void, mem_size, libc::MADV_DONTNEED);
+        libc::mprotect(base_address as *mut libc::c_void, mem_size, libc::PROT_NONE);
+    }
+}
+
+///
+/// Releases the whole address space reserved at `base_address`, munmap
+/// needs to know the size of the reservation
+///
+#[cfg(unix)]
+pub fn free_address_space(base_address: *mut u8, mem_size: usize) {
+    unsafe {
+        libc::munmap(base_address as *mut libc::c_void, mem_size);
+    }
+}
+
+#[cfg(unix)]
+pub fn protect_memory(base_address: *mut u8, mem_size: usize, protection: PageProtection) -> bool {
+    let page_protection = match protection {
+        PageProtection::NoAccess => libc::PROT_NONE,
+        PageProtection::ReadOnly => libc::PROT_READ,
+        PageProtection::ReadWrite => libc::PROT_READ | libc::PROT_WRITE,
+    };
+
+    unsafe {
+        libc::mprotect(base_address as *mut libc::c_void, mem_size, page_protection) == 0
+    }
+}
+
+///
+/// Returns the size of a huge page or None if the system does not provide them
+///
+#[cfg(target_os = "linux")]
+pub fn get_huge_page_size() -> Option<usize> {
+    use std::fs::File;
+    use std::io::Read;
+
+    let mut mem_info = String::new();
+    File::open("/proc/meminfo").and_then(|mut file| file.read_to_string(&mut mem_info)).ok()?;
+
+    mem_info.lines()
+        .find(|line| line.starts_with("Hugepagesize:"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|size_in_kb| size_in_kb.parse::<usize>().ok())
+        .map(|size_in_kb| size_in_kb * 1024)
+}
+
+///
+/// Large pages on Windows need the SeLockMemoryPrivilege and have to be committed
+/// together with their reservation, which does not fit the reserve/commit scheme of
+/// this module. Huge pages are hence only supported on Linux
+///
+#[cfg(not(target_os = "linux"))]
+pub fn get_huge_page_size() -> Option<usize> {
+    None
+}
+
+///
+/// Reserves address space backed by explicit huge pages. Without MAP_NORESERVE the kernel
+/// takes the pages from the huge page pool right away, so the reservation fails if the pool
+/// is exhausted instead of faulting on first touch. `mem_size` has to be a multiple of the
+/// huge page size
+///
+#[cfg(target_os = "linux")]
+pub fn reserve_huge_address_space(mem_size: usize) -> Option<*mut u8> {
+    let raw_mem = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            mem_size,
+            libc::PROT_NONE,
+            libc::MAP_PRIVATE | libc::MAP_ANON | libc::MAP_HUGETLB,
+            -1,
+            0
+        )
+    };
+
+    if raw_mem != libc::MAP_FAILED
+    {
+        Some(raw_mem as *mut u8)
+    }
+    else
+    {
+        None
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn reserve_huge_address_space(_mem_size: usize) -> Option<*mut u8> {
+    None
+}
+
+///
+/// Reserves address space aligned to `huge_page_size` and advises the kernel to back it
+/// with transparent huge pages. Returns None if transparent huge pages are disabled.
+/// `mem_size` has to be a multiple of the huge page size
+///
+#[cfg(target_os = "linux")]
+pub fn reserve_transparent_huge_address_space(mem_size: usize, huge_page_size: usize) -> Option<*mut u8> {
+    use std::fs::File;
+    use std::io::Read;
+
+    let mut thp_mode = String::new();
+    File::open("/sys/kernel/mm/transparent_hugepage/enabled").and_then(|mut file| file.read_to_string(&mut thp_mode)).ok()?;
+    if thp_mode.contains("[never]") {
+        return None;
+    }
+
+    // Over-reserve by one huge page and unmap the unaligned head and tail, the
+    // kernel only uses huge pages for huge page aligned parts of a mapping
+    let raw_mem = reserve_address_space(mem_size + huge_page_size)?;
+    let aligned_mem = ::spark_core::pointer_util::align_top(raw_mem, huge_page_size) as *mut u8;
+    let head_size = aligned_mem as usize - raw_mem as usize;
+    let tail_size = huge_page_size - head_size;
+
+    unsafe {
+        if head_size > 0 {
+            libc::munmap(raw_mem as *mut libc::c_void, head_size);
+        }
+
+        if tail_size > 0 {
+            libc::munmap(aligned_mem.offset(mem_size as isize) as *mut libc::c_void, tail_size);
+        }
+
+        if libc::madvise(aligned_mem as *mut libc::c_void, mem_size, libc::MADV_HUGEPAGE) != 0 {
+            libc::munmap(aligned_mem as *mut libc::c_void, mem_size);
+            return None;
+        }
+    }
+
+    Some(aligned_mem)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn reserve_transparent_huge_address_space(_mem_size: usize, _huge_page_size: usize) -> Option<*mut u8> {
+    None
+}
+
+///
+/// A VirtualRegion owns a range of reserved address space and keeps track of
+/// the high-water mark of its committed memory. In contrast to the free functions
+/// above the region remembers its size and hands the address space back to the
+/// OS when it is dropped.
+///
+pub struct VirtualRegion {
+    base:           *mut u8,
+    reserved_size:  usize,
+    committed_size: usize,
+    page_size:      usize,
+    page_mode:      PageMode,
+}
+
+impl VirtualRegion {
+    ///
+    /// Reserves `size` bytes of address space, rounded up to the next page boundary
+    ///
+    pub fn reserve(size: usize) -> Option<VirtualRegion> {
+        let page_size = get_page_size();
+        let reserved_size = math_util::round_to_next_multiple(size, page_size);
+
+        match reserve_address_space(reserved_size) {
+            Some(base) => Some(VirtualRegion {
+                base,
+                reserved_size,
+                committed_size: 0,
+                page_size,
+                page_mode: PageMode::Default,
+            }),
+            None => None,
+        }
+    }
+
+    ///
+    /// Reserves `size` bytes of address space backed by pages of the requested mode. If
+    /// the system cannot provide them the region falls back from explicit to transparent
+    /// huge pages and from there to default pages, `page_mode` reports the mode in use
+    ///
+    pub fn reserve_with_page_mode(size: usize, page_mode: PageMode) -> Option<VirtualRegion> {
+        if page_mode == PageMode::Default {
+            return VirtualRegion::reserve(size);
+        }
+
+        if let Some(huge_page_size) = get_huge_page_size() {
+            let reserved_size = math_util::round_to_next_multiple(size, huge_page_size);
+
+            let huge_mem = if page_mode == PageMode::Huge {
+                reserve_huge_address_space(reserved_size).map(|base| (base, PageMode::Huge))
+            } else {
+                None
+            };
+
+            let huge_mem = huge_mem.or_else(|| {
+                reserve_transparent_huge_address_space(reserved_size, huge_page_size).map(|base| (base, PageMode::TransparentHuge))
+            });
+
+            if let Some((base, effective_page_mode)) = huge_mem {
+                return Some(VirtualRegion {
+                    base,
+                    reserved_size,
+                    committed_size: 0,
+                    page_size: huge_page_size,
+                    page_mode: effective_page_mode,
+                });
+            }
+        }
+
+        VirtualRegion::reserve(size)
+    }
+
+    #[inline]
+    pub fn base(&self) -> *mut u8 { self.base }
+
+    #[inline]
+    pub fn end(&self) -> *mut u8 { unsafe { self.base.offset(self.reserved_size as isize) } }
+
+    #[inline]
+    pub fn reserved_size(&self) -> usize { self.reserved_size }
+
+    ///
+    /// Returns the granularity the region commits memory with, which is the
+    /// huge page size for regions backed by huge pages
+    ///
+    #[inline]
+    pub fn page_size(&self) -> usize { self.page_size }
+
+    #[inline]
+    pub fn page_mode(&self) -> PageMode { self.page_mode }
+
+    ///
+    /// Returns the high-water mark of committed bytes, measured from the base of the region
+    ///
+    #[inline]
+    pub fn committed_size(&self) -> usize { self.committed_size }
+
+    ///
+    /// Commits `size` bytes starting `offset` bytes into the region. The
+    /// offset has to be aligned to `page_size`, the size is rounded up to whole pages
+    ///
+    pub fn commit(&mut self, offset: usize, size: usize) -> Option<*mut u8> {
+        {
+            let commit_in_range = offset + size <= self.reserved_size;
+            debug_assert!(commit_in_range, "Commit exceeds the reserved address space of the region");
+        }
+
+        let address = unsafe { self.base.offset(offset as isize) };
+        let committed_mem = commit_physical_memory(address, math_util::round_to_next_multiple(size, self.page_size));
+
+        if committed_mem.is_some() {
+            let committed_end = math_util::round_to_next_multiple(offset + size, self.page_size);
+            self.committed_size = cmp::max(self.committed_size, committed_end);
+        }
+
+        committed_mem
+    }
+
+    ///
+    /// Decommits `size` bytes starting `offset` bytes into the region. The offset has to be
+    /// aligned to `page_size`, the size is rounded up to whole pages. Decommitting the top
+    /// of the committed memory lowers the high-water mark accordingly
+    ///
+    pub fn decommit(&mut self, offset: usize, size: usize) {
+        {
+            let decommit_in_range = offset + size <= self.reserved_size;
+            debug_assert!(decommit_in_range, "Decommit exceeds the reserved address space of the region");
+        }
+
+        let decommit_size = math_util::round_to_next_multiple(size, self.page_size);
+        decommit_physical_memory(unsafe { self.base.offset(offset as isize) }, decommit_size);
+
+        let decommits_top = offset < self.committed_size && offset + decommit_size >= self.committed_size;
+        if decommits_top {
+            self.committed_size = offset;
+        }
+    }
+
+    ///
+    /// Changes the access protection of `size` committed bytes starting `offset` bytes into the region
+    ///
+    pub fn protect(&mut self, offset: usize, size: usize, protection: PageProtection) -> bool {
+        {
+            let protect_in_committed_range = offset + size <= self.committed_size;
+            debug_assert!(protect_in_committed_range, "Only committed memory can be protected");
+        }
+
+        protect_memory(unsafe { self.base.offset(offset as isize) }, size, protection)
+    }
+}
+
+impl Drop for VirtualRegion {
+    fn drop(&mut self) {
+        free_address_space(self.base, self.reserved_size);
+    }
+}
+
+#[cfg(all(test, any(windows, target_os = "linux")))]
 mod tests {
     use super::*;
-    use virtual_mem::winapi::um::winnt::{ MEMORY_BASIC_INFORMATION, PMEMORY_BASIC_INFORMATION, MEM_FREE };
-    use virtual_mem::winapi::um::memoryapi::{ VirtualQuery };
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum PageState {
+        Reserved,
+        Committed,
+        ReadOnly,
+        Free,
+        Other,
+    }
+
+    ///
+    /// Queries the state that all pages in [address, address + size) share,
+    /// `PageState::Other` is returned if the pages differ in their state
+    ///
+    #[cfg(windows)]
+    fn query_page_state(address: *mut u8, size: usize) -> PageState {
+        use virtual_mem::winapi::um::winnt::{ MEMORY_BASIC_INFORMATION, PMEMORY_BASIC_INFORMATION, MEM_FREE };
+        use virtual_mem::winapi::um::memoryapi::{ VirtualQuery };
+
+        // Invoke VirtualQuery to get information about the region starting at address
+        let mut region_info: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+
+        unsafe {
+            let region_info_ptr = &mut region_info as PMEMORY_BASIC_INFORMATION;
+            let err = VirtualQuery(address as LPVOID, region_info_ptr, mem::size_of::<MEMORY_BASIC_INFORMATION>());
+            assert_ne!(0, err);
+        }
+
+        if region_info.State == MEM_FREE {
+            return PageState::Free;
+        }
+
+        if region_info.RegionSize < size || region_info.AllocationProtect != PAGE_NOACCESS {
+            return PageState::Other;
+        }
+
+        // Committed but inaccessible pages are reported as reserved, as they
+        // are indistinguishable from reserved pages on POSIX systems
+        match region_info.State {
+            MEM_RESERVE => PageState::Reserved,
+            MEM_COMMIT if region_info.Protect == PAGE_NOACCESS => PageState::Reserved,
+            MEM_COMMIT if region_info.Protect == PAGE_READWRITE => PageState::Committed,
+            MEM_COMMIT if region_info.Protect == PAGE_READONLY => PageState::ReadOnly,
+            _ => PageState::Other,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn query_page_state(address: *mut u8, size: usize) -> PageState {
+        use std::fs;
+
+        let maps = fs::read_to_string("/proc/self/maps").expect("Could not read /proc/self/maps");
+        let mappings: Vec<(usize, usize, PageState)> = maps.lines().map(|line| {
+            let mut columns = line.split_whitespace();
+            let mut range = columns.next().unwrap().split('-');
+            let begin = usize::from_str_radix(range.next().unwrap(), 16).unwrap();
+            let end = usize::from_str_radix(range.next().unwrap(), 16).unwrap();
+            let state = match columns.next().unwrap() {
+                "---p" => PageState::Reserved,
+                "rw-p" => PageState::Committed,
+                "r--p" => PageState::ReadOnly,
+                _ => PageState::Other,
+            };
+
+            (begin, end, state)
+        }).collect();
+
+        // Adjacent mappings are not necessarily merged by the kernel, hence we
+        // walk all mappings covering the queried range
+        let range_end = address as usize + size;
+        let mut current = address as usize;
+        let mut range_state = None;
+
+        while current < range_end {
+            let state = match mappings.iter().find(|&&(begin, end, _)| begin <= current && current < end) {
+                Some(&(_, end, state)) => {
+                    current = end;
+                    state
+                },
+                None => {
+                    current += get_page_size();
+                    PageState::Free
+                },
+            };
+
+            match range_state {
+                None => range_state = Some(state),
+                Some(previous_state) if previous_state != state => return PageState::Other,
+                _ => {},
+            }
+        }
+
+        range_state.unwrap_or(PageState::Other)
+    }
 
     #[test]
     fn ensure_proper_page_size() {
@@ -91,51 +594,28 @@ mod tests {
 
         let v_mem_ptr = reserve_address_space(quadruple_page_size).unwrap();
 
-        // Invoke VirtualQuery to get information about the region we just reserved before
-        let mut region_info: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+        // Check whether the whole region is reserved and PAGE_NOACCESS protected or not
+        assert_eq!(PageState::Reserved, query_page_state(v_mem_ptr, quadruple_page_size));
 
-        unsafe {
-            let region_info_ptr = &mut region_info as PMEMORY_BASIC_INFORMATION;
-            let err = VirtualQuery(v_mem_ptr as LPVOID, region_info_ptr, mem::size_of::<MEMORY_BASIC_INFORMATION>());
-            assert_ne!(0, err);
-        }
-
-        // Check whether the whole region is reserved and PAGE_READWRITE protected or not
-        assert_eq!(quadruple_page_size, region_info.RegionSize);
-        assert_eq!(MEM_RESERVE, region_info.State);
-        assert_eq!(PAGE_NOACCESS, region_info.AllocationProtect);
+        free_address_space(v_mem_ptr, quadruple_page_size);
     }
 
     #[test]
     fn commit_physical_address_space() {
-        use virtual_mem::winapi::um::memoryapi::{ VirtualQuery };
-
         let page_size: usize = get_page_size();
         let quadruple_page_size: usize = page_size * 4;
 
         let v_mem_ptr = reserve_address_space(quadruple_page_size).unwrap();
         let p_mem_ptr = commit_physical_memory(v_mem_ptr, quadruple_page_size).unwrap();
 
-        // Invoke VirtualQuery to get information about the region we just reserved before
-        let mut region_info: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
-
-        unsafe {
-            let region_info_ptr = &mut region_info as PMEMORY_BASIC_INFORMATION;
-            let err = VirtualQuery(p_mem_ptr as LPVOID, region_info_ptr, mem::size_of::<MEMORY_BASIC_INFORMATION>());
-            assert_ne!(0, err);
-        }
+        // Check whether the whole region is committed and PAGE_READWRITE protected or not
+        assert_eq!(PageState::Committed, query_page_state(p_mem_ptr, quadruple_page_size));
 
-        // Check whether the whole region is reserved and PAGE_READWRITE protected or not
-        assert_eq!(quadruple_page_size, region_info.RegionSize);
-        assert_eq!(MEM_COMMIT, region_info.State);
-        assert_eq!(PAGE_NOACCESS, region_info.AllocationProtect);
-        assert_eq!(PAGE_READWRITE, region_info.Protect);
+        free_address_space(v_mem_ptr, quadruple_page_size);
     }
 
     #[test]
     fn decommit_physical_address_space() {
-        use virtual_mem::winapi::um::memoryapi::{ VirtualQuery };
-
         let page_size: usize = get_page_size();
         let quadruple_page_size: usize = page_size * 4;
 
@@ -143,49 +623,27 @@ mod tests {
         let p_mem_ptr = commit_physical_memory(v_mem_ptr, quadruple_page_size).unwrap();
         decommit_physical_memory(p_mem_ptr, quadruple_page_size);
 
-        // Invoke VirtualQuery to get information about the region we just reserved before
-        let mut region_info: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+        // Check whether the whole region is reserved again
+        assert_eq!(PageState::Reserved, query_page_state(p_mem_ptr, quadruple_page_size));
 
-        unsafe {
-            let region_info_ptr = &mut region_info as PMEMORY_BASIC_INFORMATION;
-            let err = VirtualQuery(p_mem_ptr as LPVOID, region_info_ptr, mem::size_of::<MEMORY_BASIC_INFORMATION>());
-            assert_ne!(0, err);
-        }
-
-        // Check whether the whole region is reserved and PAGE_READWRITE protected or not
-        assert_eq!(quadruple_page_size, region_info.RegionSize);
-        assert_eq!(MEM_RESERVE, region_info.State);
-        assert_eq!(PAGE_NOACCESS, region_info.AllocationProtect);
+        free_address_space(v_mem_ptr, quadruple_page_size);
     }
 
     #[test]
     fn free_reserved_address_space() {
-        use virtual_mem::winapi::um::memoryapi::{ VirtualQuery };
-
         let page_size: usize = get_page_size();
         let quadruple_page_size: usize = page_size * 4;
 
         let v_mem_ptr = reserve_address_space(quadruple_page_size).unwrap();
         let p_mem_ptr = commit_physical_memory(v_mem_ptr, quadruple_page_size).unwrap();
-        free_address_space(v_mem_ptr);
+        free_address_space(v_mem_ptr, quadruple_page_size);
 
-        // Invoke VirtualQuery to get information about the region we just reserved before
-        let mut region_info: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
-
-        unsafe {
-            let region_info_ptr = &mut region_info as PMEMORY_BASIC_INFORMATION;
-            let err = VirtualQuery(p_mem_ptr as LPVOID, region_info_ptr, mem::size_of::<MEMORY_BASIC_INFORMATION>());
-            assert_ne!(0, err);
-        }
-
-        // Check whether the whole region is reserved and PAGE_READWRITE protected or not
-        assert_eq!(MEM_FREE, region_info.State);
+        // Check whether the whole region was handed back to the OS
+        assert_eq!(PageState::Free, query_page_state(p_mem_ptr, quadruple_page_size));
     }
 
     #[test]
     fn commit_physical_address_space_multiple_times() {
-        use virtual_mem::winapi::um::memoryapi::{ VirtualQuery };
-
         let page_size: usize = get_page_size();
         let quadruple_page_size: usize = page_size * 4;
         let double_page_size: usize = page_size * 2;
@@ -193,31 +651,117 @@ mod tests {
         let v_mem_ptr = reserve_address_space(quadruple_page_size).unwrap();
         let p_mem_ptr_0 = commit_physical_memory(v_mem_ptr, double_page_size).unwrap();
 
-        // Invoke VirtualQuery to get information about the region we just reserved before
-        let mut region_info: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+        // Check whether only the first half of the region is committed
+        assert_eq!(PageState::Committed, query_page_state(p_mem_ptr_0, double_page_size));
+        assert_eq!(PageState::Reserved, query_page_state(unsafe { p_mem_ptr_0.offset(double_page_size as isize) }, double_page_size));
 
-        unsafe {
-            let region_info_ptr = &mut region_info as PMEMORY_BASIC_INFORMATION;
-            let err = VirtualQuery(p_mem_ptr_0 as LPVOID, region_info_ptr, mem::size_of::<MEMORY_BASIC_INFORMATION>());
-            assert_ne!(0, err);
-        }
+        let p_mem_ptr_1 = unsafe { commit_physical_memory(p_mem_ptr_0.offset(double_page_size as isize), double_page_size).unwrap() };
 
-        // Check whether the whole region is reserved and PAGE_READWRITE protected or not
-        assert_eq!(double_page_size, region_info.RegionSize);
-        assert_eq!(MEM_COMMIT, region_info.State);
-        assert_eq!(PAGE_NOACCESS, region_info.AllocationProtect);
-        assert_eq!(PAGE_READWRITE, region_info.Protect);
-            
-        unsafe {
-            let p_mem_ptr_1 = commit_physical_memory(p_mem_ptr_0.offset(double_page_size as isize), double_page_size).unwrap();
-            let region_info_ptr = &mut region_info as PMEMORY_BASIC_INFORMATION;
-            let err = VirtualQuery(p_mem_ptr_1 as LPVOID, region_info_ptr, mem::size_of::<MEMORY_BASIC_INFORMATION>());
-            assert_ne!(0, err);
+        assert_eq!(PageState::Committed, query_page_state(p_mem_ptr_1, double_page_size));
+        assert_eq!(PageState::Committed, query_page_state(v_mem_ptr, quadruple_page_size));
+
+        free_address_space(v_mem_ptr, quadruple_page_size);
+    }
+
+    #[test]
+    fn region_rounds_reservation_to_page_size() {
+        let page_size: usize = get_page_size();
+        let region = VirtualRegion::reserve(page_size + 1).unwrap();
+
+        assert_eq!(region.reserved_size(), page_size * 2);
+        assert_eq!(region.committed_size(), 0);
+        assert_eq!(region.end() as usize - region.base() as usize, page_size * 2);
+        assert_eq!(PageState::Reserved, query_page_state(region.base(), page_size * 2));
+    }
+
+    #[test]
+    fn region_tracks_committed_high_water_mark() {
+        let page_size: usize = get_page_size();
+        let mut region = VirtualRegion::reserve(page_size * 4).unwrap();
+
+        region.commit(0, page_size).unwrap();
+        assert_eq!(region.committed_size(), page_size);
+        region.commit(page_size, page_size + 1).unwrap();
+        assert_eq!(region.committed_size(), page_size * 3);
+        assert_eq!(PageState::Committed, query_page_state(region.base(), page_size * 3));
+
+        region.decommit(page_size * 2, page_size);
+        assert_eq!(region.committed_size(), page_size * 2);
+        assert_eq!(PageState::Reserved, query_page_state(unsafe { region.base().offset(page_size as isize * 2) }, page_size * 2));
+
+        // Decommitting below the high-water mark leaves the mark untouched
+        region.decommit(0, page_size);
+        assert_eq!(region.committed_size(), page_size * 2);
+    }
+
+    #[test]
+    fn region_decommits_whole_pages() {
+        let page_size: usize = get_page_size();
+        let mut region = VirtualRegion::reserve(page_size * 4).unwrap();
+        region.commit(0, page_size * 4).unwrap();
+
+        region.decommit(page_size * 2, 1);
+        assert_eq!(region.committed_size(), page_size * 4);
+        assert_eq!(PageState::Reserved, query_page_state(unsafe { region.base().offset(page_size as isize * 2) }, page_size));
+
+        region.decommit(page_size * 3, page_size - 1);
+        assert_eq!(region.committed_size(), page_size * 3);
+    }
+
+    #[test]
+    fn region_changes_page_protection() {
+        let page_size: usize = get_page_size();
+        let mut region = VirtualRegion::reserve(page_size * 2).unwrap();
+        region.commit(0, page_size * 2).unwrap();
+
+        assert!(region.protect(page_size, page_size, PageProtection::ReadOnly));
+        assert_eq!(PageState::ReadOnly, query_page_state(unsafe { region.base().offset(page_size as isize) }, page_size));
+        assert!(region.protect(page_size, page_size, PageProtection::NoAccess));
+        assert_eq!(PageState::Reserved, query_page_state(unsafe { region.base().offset(page_size as isize) }, page_size));
+        assert!(region.protect(page_size, page_size, PageProtection::ReadWrite));
+        assert_eq!(PageState::Committed, query_page_state(region.base(), page_size * 2));
+    }
+
+    #[test]
+    fn region_frees_address_space_on_drop() {
+        let page_size: usize = get_page_size();
+        let base = {
+            let mut region = VirtualRegion::reserve(page_size * 4).unwrap();
+            region.commit(0, page_size * 4).unwrap();
+            region.base()
+        };
+
+        assert_eq!(PageState::Free, query_page_state(base, page_size * 4));
+    }
+
+    #[test]
+    fn region_falls_back_to_available_page_mode() {
+        let page_size: usize = get_page_size();
+        let mut region = VirtualRegion::reserve_with_page_mode(page_size, PageMode::Huge).unwrap();
+
+        match region.page_mode() {
+            PageMode::Default => assert_eq!(region.page_size(), page_size),
+            _ => {
+                assert_eq!(Some(region.page_size()), get_huge_page_size());
+                assert!(::spark_core::pointer_util::is_aligned_to(region.base(), region.page_size()), "Huge page region was not aligned to the huge page size");
+            },
         }
 
-        assert_eq!(double_page_size, region_info.RegionSize);
-        assert_eq!(MEM_COMMIT, region_info.State);
-        assert_eq!(PAGE_NOACCESS, region_info.AllocationProtect);
-        assert_eq!(PAGE_READWRITE, region_info.Protect);
+        assert_eq!(region.reserved_size(), region.page_size());
+
+        region.commit(0, page_size).unwrap();
+        assert_eq!(region.committed_size(), region.page_size(), "Commit did not use the granularity of the region");
+
+        let data = unsafe { ::std::slice::from_raw_parts_mut(region.base(), region.page_size()) };
+        data[0] = 0xCC;
+        data[region.page_size() - 1] = 0xCC;
+    }
+
+    #[test]
+    fn default_page_mode_uses_default_pages() {
+        let region = VirtualRegion::reserve_with_page_mode(1, PageMode::Default).unwrap();
+
+        assert_eq!(region.page_mode(), PageMode::Default);
+        assert_eq!(region.page_size(), get_page_size());
     }
 }
\ No newline at end of file
@@ -0,0 +1,206 @@
+use std::ptr;
+use std::sync::atomic::{ AtomicU32, AtomicU64, Ordering };
+
+const EMPTY_INDEX: u32 = u32::MAX;
+
+#[inline]
+fn pack_head(index: u32, tag: u32) -> u64 {
+    ((tag as u64) << 32) | index as u64
+}
+
+#[inline]
+fn head_index(head: u64) -> u32 { head as u32 }
+
+#[inline]
+fn head_tag(head: u64) -> u32 { (head >> 32) as u32 }
+
+///
+/// The AtomicFreeList is a lock-free variant of the FreeList that can be shared between
+/// threads. Blocks are linked by their index inside of the memory range instead of their
+/// address, which leaves room to pair the head of the list with a tag in a single atomic.
+/// Every successful update bumps the tag, so a thread whose view of the head is outdated
+/// fails its compare-exchange even if the same block is back on top (ABA problem).
+///
+/// Unlike the FreeList the links are not stored inside of the blocks. A thread popping a
+/// block reads the link of the current head while another thread may already own that
+/// block and write to it, so the links live in a table of their own that is only ever
+/// accessed atomically. The blocks themselves are never touched by the list
+///
+pub struct AtomicFreeList {
+    head:           AtomicU64,
+    links:          Box<[AtomicU32]>,
+    begin:          *mut u8,
+    block_size:     usize,
+}
+
+// The list only hands out blocks of the memory range it was created from, all shared state is atomic
+unsafe impl Send for AtomicFreeList {}
+unsafe impl Sync for AtomicFreeList {}
+
+impl AtomicFreeList {
+    pub fn new_from(begin: *mut u8, end: *mut u8, block_size: usize) -> AtomicFreeList {
+        let mem_range_in_bytes = end as usize - begin as usize;
+        let number_of_blocks = mem_range_in_bytes / block_size;
+        debug_assert!(number_of_blocks < EMPTY_INDEX as usize, "Too many blocks for an AtomicFreeList");
+
+        let mut free_list = AtomicFreeList {
+            head:           AtomicU64::new(pack_head(EMPTY_INDEX, 0)),
+            links:          (0 .. number_of_blocks).map(|_| AtomicU32::new(EMPTY_INDEX)).collect::<Vec<_>>().into_boxed_slice(),
+            begin,
+            block_size,
+        };
+
+        free_list.reset();
+        free_list
+    }
+
+    ///
+    /// Links all blocks of the memory range again, all handed out blocks become free again.
+    /// Rethreading the list cannot be done atomically, so it requires exclusive access
+    ///
+    pub fn reset(&mut self) {
+        let block_count = self.links.len() as u32;
+        for index in 0 .. block_count {
+            let next_index = if index + 1 < block_count { index + 1 } else { EMPTY_INDEX };
+            *self.links[index as usize].get_mut() = next_index;
+        }
+
+        let first_index = if block_count > 0 { 0 } else { EMPTY_INDEX };
+        let head = self.head.get_mut();
+        *head = pack_head(first_index, head_tag(*head).wrapping_add(1));
+    }
+
+    pub fn get_block(&self) -> *mut u8 {
+        let mut head = self.head.load(Ordering::Acquire);
+
+        loop {
+            let index = head_index(head);
+            if index == EMPTY_INDEX {
+                return ptr::null_mut();
+            }
+
+            // The block might be taken by another thread in the meantime, which changes the tag and fails the exchange
+            let next_index = self.links[index as usize].load(Ordering::Relaxed);
+            let new_head = pack_head(next_index, head_tag(head).wrapping_add(1));
+
+            match self.head.compare_exchange_weak(head, new_head, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return self.block_ptr(index),
+                Err(current_head) => head = current_head,
+            }
+        }
+    }
+
+    pub fn return_block(&self, block: *mut u8) {
+        let index = self.block_index(block);
+        let mut head = self.head.load(Ordering::Relaxed);
+
+        loop {
+            self.links[index as usize].store(head_index(head), Ordering::Relaxed);
+            let new_head = pack_head(index, head_tag(head).wrapping_add(1));
+
+            match self.head.compare_exchange_weak(head, new_head, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(current_head) => head = current_head,
+            }
+        }
+    }
+
+    pub fn empty(&self) -> bool { head_index(self.head.load(Ordering::Acquire)) == EMPTY_INDEX }
+
+    #[inline]
+    fn block_ptr(&self, index: u32) -> *mut u8 {
+        unsafe { self.begin.add(index as usize * self.block_size) }
+    }
+
+    #[inline]
+    fn block_index(&self, block: *mut u8) -> u32 {
+        {
+            let block_in_range = block >= self.begin && (block as usize - self.begin as usize) / self.block_size < self.links.len();
+            debug_assert!(block_in_range, "Block was not handed out by this free list");
+            let block_is_at_boundary = (block as usize - self.begin as usize).is_multiple_of(self.block_size);
+            debug_assert!(block_is_at_boundary, "Block does not start at a block boundary");
+        }
+
+        ((block as usize - self.begin as usize) / self.block_size) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    const BLOCK_COUNT: usize = 64;
+
+    #[test]
+    fn hands_out_every_block_once() {
+        let mut memory = [0u64; BLOCK_COUNT];
+        let begin = memory.as_mut_ptr() as *mut u8;
+        let end = unsafe { begin.add(mem::size_of_val(&memory)) };
+        let mut free_list = AtomicFreeList::new_from(begin, end, mem::size_of::<u64>());
+
+        let mut blocks: Vec<*mut u8> = (0 .. BLOCK_COUNT).map(|_| free_list.get_block()).collect();
+        assert!(free_list.empty());
+        assert!(free_list.get_block().is_null());
+
+        blocks.sort();
+        blocks.dedup();
+        assert_eq!(blocks.len(), BLOCK_COUNT, "A block was handed out twice");
+
+        free_list.return_block(blocks[3]);
+        assert_eq!(free_list.get_block(), blocks[3]);
+
+        free_list.reset();
+        assert_eq!(free_list.get_block(), begin);
+    }
+
+    #[test]
+    fn concurrent_get_and_return() {
+        let mut memory = vec![0u64; BLOCK_COUNT];
+        let begin = memory.as_mut_ptr() as *mut u8;
+        let end = unsafe { begin.add(BLOCK_COUNT * mem::size_of::<u64>()) };
+        let free_list = Arc::new(AtomicFreeList::new_from(begin, end, mem::size_of::<u64>()));
+        let in_use: Arc<Vec<AtomicBool>> = Arc::new((0 .. BLOCK_COUNT).map(|_| AtomicBool::new(false)).collect());
+        let begin_address = begin as usize;
+
+        let threads: Vec<_> = (0 .. 8).map(|_| {
+            let free_list = free_list.clone();
+            let in_use = in_use.clone();
+            thread::spawn(move || {
+                let mut blocks = Vec::new();
+                for i in 0 .. 20000 {
+                    let block = free_list.get_block();
+                    if !block.is_null() {
+                        let index = (block as usize - begin_address) / mem::size_of::<u64>();
+                        assert!(!in_use[index].swap(true, Ordering::AcqRel), "Block was handed out twice");
+                        // The list must not rely on the content of handed out blocks
+                        unsafe { *(block as *mut u64) = u64::MAX; }
+                        blocks.push((block, index));
+                    }
+
+                    if i % 3 == 0 || block.is_null() {
+                        while let Some((block, index)) = blocks.pop() {
+                            in_use[index].store(false, Ordering::Release);
+                            free_list.return_block(block);
+                        }
+                    }
+                }
+
+                for (block, index) in blocks {
+                    in_use[index].store(false, Ordering::Release);
+                    free_list.return_block(block);
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let free_block_count = (0 .. BLOCK_COUNT).filter(|_| !free_list.get_block().is_null()).count();
+        assert_eq!(free_block_count, BLOCK_COUNT, "Blocks were lost");
+    }
+}
\ No newline at end of file
@@ -1,32 +1,148 @@
-use std;
+#[cfg(windows)]
 use winapi::um::winnt::LARGE_INTEGER;
+#[cfg(windows)]
 use winapi::um::profileapi::{ QueryPerformanceCounter, QueryPerformanceFrequency };
 
+///
+/// Monotonic clock reporting elapsed time in microseconds. Besides the total
+/// time since `start` the clock can record laps, which allows a single measurement
+/// to report the timings of its phases (e.g. setup, work and teardown)
+///
 pub struct HighPrecisionClock
 {
     pub start: i64,
     pub frequency: f64,
+    last_lap: i64,
+    laps: Vec<(&'static str, f64)>,
+}
+
+impl Default for HighPrecisionClock {
+    fn default() -> Self {
+        HighPrecisionClock::new()
+    }
 }
 
 impl HighPrecisionClock {
-    pub unsafe fn new() -> Self {
-        let mut freq: LARGE_INTEGER = std::mem::uninitialized();
-        QueryPerformanceFrequency(&mut freq);
+    pub fn new() -> Self {
         HighPrecisionClock {
             start: 0,
-            frequency: 1.0 / (*freq.QuadPart() as f64 / 1000000.0),
+            frequency: query_frequency(),
+            last_lap: 0,
+            laps: Vec::new(),
         }
     }
 
-    pub unsafe fn start(&mut self) {
-        let mut cycles: LARGE_INTEGER = std::mem::uninitialized();
+    ///
+    /// (Re-)starts the clock and discards all previously recorded laps
+    ///
+    pub fn start(&mut self) {
+        self.start = query_counter();
+        self.last_lap = self.start;
+        self.laps.clear();
+    }
+
+    ///
+    /// Returns the microseconds elapsed since the clock was started
+    ///
+    pub fn get(&self) -> f64 {
+        (query_counter() as f64 - self.start as f64) * self.frequency
+    }
+
+    ///
+    /// Records a lap labeled `label` and returns the microseconds elapsed
+    /// since the previous lap, or since `start` for the first lap
+    ///
+    pub fn lap(&mut self, label: &'static str) -> f64 {
+        let now = query_counter();
+        let lap_time = (now as f64 - self.last_lap as f64) * self.frequency;
+
+        self.last_lap = now;
+        self.laps.push((label, lap_time));
+
+        lap_time
+    }
+
+    ///
+    /// Returns all laps recorded since the clock was started
+    ///
+    pub fn laps(&self) -> &[(&'static str, f64)] {
+        &self.laps
+    }
+}
+
+///
+/// Returns the microseconds that pass per tick of the counter
+///
+#[cfg(windows)]
+fn query_frequency() -> f64 {
+    unsafe {
+        let mut freq: LARGE_INTEGER = ::std::mem::zeroed();
+        QueryPerformanceFrequency(&mut freq);
+        1.0 / (*freq.QuadPart() as f64 / 1000000.0)
+    }
+}
+
+#[cfg(windows)]
+fn query_counter() -> i64 {
+    unsafe {
+        let mut cycles: LARGE_INTEGER = ::std::mem::zeroed();
         QueryPerformanceCounter(&mut cycles);
-        self.start = *cycles.QuadPart();
+        *cycles.QuadPart()
     }
+}
+
+///
+/// The POSIX counter ticks in nanoseconds
+///
+#[cfg(unix)]
+fn query_frequency() -> f64 {
+    1.0 / 1000.0
+}
+
+#[cfg(unix)]
+fn query_counter() -> i64 {
+    let mut time = ::libc::timespec { tv_sec: 0, tv_nsec: 0 };
+
+    unsafe {
+        ::libc::clock_gettime(::libc::CLOCK_MONOTONIC, &mut time);
+    }
+
+    time.tv_sec * 1000000000 + time.tv_nsec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elapsed_time_is_monotonic() {
+        let mut clock = HighPrecisionClock::new();
+        clock.start();
+
+        let first = clock.get();
+        let second = clock.get();
+
+        assert!(first >= 0.0);
+        assert!(second >= first, "Clock went backwards");
+    }
+
+    #[test]
+    fn laps_add_up_to_total_time() {
+        let mut clock = HighPrecisionClock::new();
+        clock.start();
+
+        let setup = clock.lap("setup");
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let work = clock.lap("work");
+        let total = clock.get();
+
+        assert_eq!(clock.laps().len(), 2);
+        assert_eq!(clock.laps()[0].0, "setup");
+        assert_eq!(clock.laps()[1].0, "work");
+        assert!(work >= 2000.0, "Lap did not report its time in microseconds");
+        assert!(setup + work <= total);
 
-    pub unsafe fn get(&self) -> f64 {
-        let mut curr_cycles: LARGE_INTEGER = std::mem::uninitialized();
-        QueryPerformanceCounter(&mut curr_cycles);
-        (*curr_cycles.QuadPart() as f64 - self.start as f64) * self.frequency
+        clock.start();
+        assert!(clock.laps().is_empty(), "Restarting the clock did not discard the laps");
     }
 }
\ No newline at end of file
@@ -1,11 +1,33 @@
-use std::{ mem, cell::Cell };
+use std::{ mem, ptr, cell::Cell };
 
 pub struct FreeList {
     pub list: Cell<*mut u8>,
 }
 
+impl Default for FreeList {
+    fn default() -> Self {
+        FreeList::new()
+    }
+}
+
 impl FreeList {
+    pub fn new() -> FreeList {
+        FreeList {
+            list: Cell::new(ptr::null_mut()),
+        }
+    }
+
     pub fn new_from(begin: *mut u8, end: *mut u8, block_size: usize) -> FreeList {
+        let free_list = FreeList::new();
+        free_list.add_range(begin, end, block_size);
+        free_list
+    }
+
+    ///
+    /// Threads all blocks of the range [begin, end) into the free list, they
+    /// are handed out before the blocks that were already part of the list
+    ///
+    pub fn add_range(&self, begin: *mut u8, end: *mut u8, block_size: usize) {
         
         {
             let block_greater_or_equal_pointer_size = block_size >= mem::size_of::<*mut u8>();
@@ -15,29 +37,35 @@ impl FreeList {
         let mem_range_in_bytes = end as usize - begin as usize;
         let number_of_blocks = mem_range_in_bytes / block_size;
         let signed_block_size = block_size as isize;
-        let free_list: *mut u8 = begin;
 
-        let mut current: *mut *mut u8 = free_list as *mut *mut u8;
+        if number_of_blocks == 0 {
+            return;
+        }
+
+        let mut current: *mut *mut u8 = begin as *mut *mut u8;
         let mut memory: *mut u8 = begin;
         memory = unsafe { memory.offset(signed_block_size) };
         
+        // Blocks are not necessarily aligned for a pointer, e.g. when a pool puts
+        // a small header in front of its elements, so links are accessed unaligned
         unsafe {
             for _ in 0 .. number_of_blocks - 1 {         
-                *current = memory;
-                current = *current as *mut *mut u8;
+                ptr::write_unaligned(current, memory);
+                current = memory as *mut *mut u8;
                 memory = memory.offset(signed_block_size);
             }
-        }
 
-        FreeList {
-            list: Cell::new(free_list),
+            // The last block links to the previous head, a null terminated list otherwise
+            ptr::write_unaligned(current, self.list.get());
         }
+
+        self.list.set(begin);
     }
 
     pub fn get_block(&self) -> *mut u8 {
         let free_list = self.list.get();
         if !free_list.is_null() {
-            let next_block = unsafe { *(free_list as *mut *mut u8) };
+            let next_block = unsafe { ptr::read_unaligned(free_list as *const *mut u8) };
             self.list.set(next_block);
         }
 
@@ -48,11 +76,63 @@ impl FreeList {
             let free_list = self.list.get();
             let returned_ptr = block;
             unsafe {
-                *(returned_ptr as *mut *mut u8) = free_list;
+                ptr::write_unaligned(returned_ptr as *mut *mut u8, free_list);
             }
             self.list.set(returned_ptr);
 
     }
 
     pub fn empty(&self) -> bool { self.list.get().is_null() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_is_null_terminated() {
+        let mut memory = [0xFFusize; 4];
+        let begin = memory.as_mut_ptr() as *mut u8;
+        let end = unsafe { begin.add(mem::size_of_val(&memory)) };
+        let free_list = FreeList::new_from(begin, end, mem::size_of::<usize>());
+
+        for _ in 0 .. 4 {
+            assert!(!free_list.get_block().is_null());
+        }
+
+        assert!(free_list.empty(), "Last block did not terminate the list");
+    }
+
+    #[test]
+    fn blocks_need_not_be_pointer_aligned() {
+        let mut memory = [0u32; 13];
+        let block_size = 3 * mem::size_of::<u32>();
+        let begin = unsafe { (memory.as_mut_ptr() as *mut u8).add(mem::size_of::<u32>()) };
+        let free_list = unsafe { FreeList::new_from(begin, begin.add(4 * block_size), block_size) };
+
+        let blocks: Vec<*mut u8> = (0 .. 4).map(|_| free_list.get_block()).collect();
+        assert!(free_list.empty());
+        assert_eq!(blocks[3], unsafe { begin.add(3 * block_size) });
+
+        free_list.return_block(blocks[1]);
+        assert_eq!(free_list.get_block(), blocks[1]);
+    }
+
+    #[test]
+    fn add_range_prepends_blocks() {
+        let mut memory_0 = [0usize; 2];
+        let mut memory_1 = [0usize; 2];
+        let block_size = mem::size_of::<usize>();
+        let begin_0 = memory_0.as_mut_ptr() as *mut u8;
+        let begin_1 = memory_1.as_mut_ptr() as *mut u8;
+
+        let free_list = unsafe { FreeList::new_from(begin_0, begin_0.add(2 * block_size), block_size) };
+        unsafe { free_list.add_range(begin_1, begin_1.add(2 * block_size), block_size) };
+
+        assert_eq!(free_list.get_block(), begin_1);
+        assert_eq!(free_list.get_block(), unsafe { begin_1.add(block_size) });
+        assert_eq!(free_list.get_block(), begin_0);
+        assert_eq!(free_list.get_block(), unsafe { begin_0.add(block_size) });
+        assert!(free_list.empty());
+    }
 }
\ No newline at end of file
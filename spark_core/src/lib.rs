@@ -1,7 +1,12 @@
+#[cfg(windows)]
 extern crate winapi;
+#[cfg(windows)]
 extern crate kernel32;
+#[cfg(unix)]
+extern crate libc;
 
 pub mod pointer_util;
 pub mod math_util;
 pub mod freelist;
+pub mod atomic_freelist;
 pub mod clock;
\ No newline at end of file
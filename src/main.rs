@@ -28,11 +28,9 @@ fn main() {
         return;
     }
 
-    unsafe {
-        let mut clock = HighPrecisionClock::new();
+    let mut clock = HighPrecisionClock::new();
 
-        clock.start();
-        scenarios::SCENARIOS[arguments[1].parse::<usize>().expect("Could not parse arg")]();
-        println!("{:.3}", clock.get());
-    }
+    clock.start();
+    scenarios::SCENARIOS[arguments[1].parse::<usize>().expect("Could not parse arg")]();
+    println!("{:.3}", clock.get());
 }